@@ -0,0 +1,6 @@
+-- +goose StatementBegin
+ALTER TABLE assets ADD COLUMN IF NOT EXISTS rank INT;
+
+CREATE INDEX IF NOT EXISTS idx_assets_rank ON assets(rank);
+CREATE INDEX IF NOT EXISTS idx_watchlist_groups_user_created_at ON watchlist_groups(user_id, created_at, id);
+-- +goose StatementEnd
@@ -0,0 +1,10 @@
+-- +goose StatementBegin
+ALTER TABLE watchlist_groups ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP;
+
+-- Purging a group removes its entries together with it
+ALTER TABLE watchlist DROP CONSTRAINT IF EXISTS watchlist_group_id_fkey;
+ALTER TABLE watchlist
+    ADD CONSTRAINT watchlist_group_id_fkey FOREIGN KEY (group_id) REFERENCES watchlist_groups(id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS idx_watchlist_groups_deleted_at ON watchlist_groups(deleted_at) WHERE deleted_at IS NOT NULL;
+-- +goose StatementEnd
@@ -0,0 +1,16 @@
+-- +goose StatementBegin
+CREATE TABLE IF NOT EXISTS asset_candles (
+                               asset_id INT NOT NULL,
+                               timeframe VARCHAR(8) NOT NULL,
+                               open_time TIMESTAMP WITH TIME ZONE NOT NULL,
+                               close_time TIMESTAMP WITH TIME ZONE NOT NULL,
+                               open DOUBLE PRECISION NOT NULL,
+                               high DOUBLE PRECISION NOT NULL,
+                               low DOUBLE PRECISION NOT NULL,
+                               close DOUBLE PRECISION NOT NULL,
+                               volume DOUBLE PRECISION,
+                               market_cap DOUBLE PRECISION,
+                               PRIMARY KEY (asset_id, timeframe, open_time),
+                               FOREIGN KEY (asset_id) REFERENCES assets(id)
+);
+-- +goose StatementEnd
@@ -0,0 +1,17 @@
+-- +goose StatementBegin
+CREATE TABLE IF NOT EXISTS portfolio_transactions (
+                                        id SERIAL PRIMARY KEY,
+                                        group_id INT NOT NULL,
+                                        asset_id INT NOT NULL,
+                                        side VARCHAR(4) NOT NULL CHECK (side IN ('buy', 'sell')),
+                                        quantity DOUBLE PRECISION NOT NULL CHECK (quantity > 0),
+                                        price DOUBLE PRECISION NOT NULL CHECK (price >= 0),
+                                        fee DOUBLE PRECISION NOT NULL DEFAULT 0 CHECK (fee >= 0),
+                                        executed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                                        created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                                        FOREIGN KEY (group_id) REFERENCES watchlist_groups(id) ON DELETE CASCADE,
+                                        FOREIGN KEY (asset_id) REFERENCES assets(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_portfolio_transactions_group_asset ON portfolio_transactions(group_id, asset_id, executed_at);
+-- +goose StatementEnd
@@ -0,0 +1,13 @@
+-- +goose StatementBegin
+CREATE TABLE IF NOT EXISTS user_preferences (
+                                  user_id INT PRIMARY KEY,
+                                  quote_currency VARCHAR(16) NOT NULL DEFAULT 'USD',
+                                  timezone VARCHAR(64) NOT NULL DEFAULT 'UTC',
+                                  default_watchlist_group_id INT,
+                                  locale VARCHAR(16) NOT NULL DEFAULT 'en-US',
+                                  notification_channels TEXT[] NOT NULL DEFAULT '{}',
+                                  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                                  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+                                  FOREIGN KEY (default_watchlist_group_id) REFERENCES watchlist_groups(id) ON DELETE SET NULL
+);
+-- +goose StatementEnd
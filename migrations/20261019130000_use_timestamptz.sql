@@ -0,0 +1,12 @@
+-- +goose StatementBegin
+-- The naive columns were filled by CURRENT_TIMESTAMP on a UTC server, so they are read as UTC
+ALTER TABLE users
+    ALTER COLUMN created_at TYPE TIMESTAMP WITH TIME ZONE USING created_at AT TIME ZONE 'UTC';
+
+ALTER TABLE watchlist_groups
+    ALTER COLUMN created_at TYPE TIMESTAMP WITH TIME ZONE USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN deleted_at TYPE TIMESTAMP WITH TIME ZONE USING deleted_at AT TIME ZONE 'UTC';
+
+ALTER TABLE watchlist
+    ALTER COLUMN added_at TYPE TIMESTAMP WITH TIME ZONE USING added_at AT TIME ZONE 'UTC';
+-- +goose StatementEnd
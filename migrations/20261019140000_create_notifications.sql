@@ -0,0 +1,41 @@
+-- +goose StatementBegin
+CREATE TABLE IF NOT EXISTS notification_channel_settings (
+                                  user_id INT NOT NULL,
+                                  channel VARCHAR(16) NOT NULL,
+                                  target TEXT NOT NULL,
+                                  secret TEXT,
+                                  updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                                  PRIMARY KEY (user_id, channel),
+                                  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS notifications (
+                                  id SERIAL PRIMARY KEY,
+                                  user_id INT NOT NULL,
+                                  kind VARCHAR(64) NOT NULL,
+                                  title TEXT NOT NULL,
+                                  body TEXT NOT NULL,
+                                  payload JSONB NOT NULL DEFAULT '{}',
+                                  read_at TIMESTAMP WITH TIME ZONE,
+                                  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                                  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS notifications_user_id_created_at_idx ON notifications (user_id, created_at DESC, id DESC);
+
+CREATE TABLE IF NOT EXISTS notification_deliveries (
+                                  id SERIAL PRIMARY KEY,
+                                  user_id INT NOT NULL,
+                                  channel VARCHAR(16) NOT NULL,
+                                  kind VARCHAR(64) NOT NULL,
+                                  target TEXT,
+                                  status VARCHAR(16) NOT NULL CHECK (status IN ('delivered', 'failed', 'skipped')),
+                                  attempts INT NOT NULL DEFAULT 0,
+                                  response_status INT,
+                                  error TEXT,
+                                  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                                  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS notification_deliveries_user_id_created_at_idx ON notification_deliveries (user_id, created_at DESC, id DESC);
+-- +goose StatementEnd
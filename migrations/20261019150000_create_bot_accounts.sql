@@ -0,0 +1,21 @@
+-- +goose StatementBegin
+CREATE TABLE IF NOT EXISTS bot_link_codes (
+                                  code VARCHAR(16) PRIMARY KEY,
+                                  user_id INT NOT NULL,
+                                  platform VARCHAR(16) NOT NULL CHECK (platform IN ('telegram', 'discord')),
+                                  expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+                                  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- A chat account is linked to a single user, replies and alerts go to `chat_id`
+CREATE TABLE IF NOT EXISTS bot_accounts (
+                                  platform VARCHAR(16) NOT NULL CHECK (platform IN ('telegram', 'discord')),
+                                  external_user_id VARCHAR(64) NOT NULL,
+                                  user_id INT NOT NULL,
+                                  chat_id VARCHAR(64) NOT NULL,
+                                  linked_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                                  PRIMARY KEY (platform, external_user_id),
+                                  UNIQUE (user_id, platform),
+                                  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+-- +goose StatementEnd
@@ -0,0 +1,24 @@
+-- +goose StatementBegin
+ALTER TABLE assets
+    ADD COLUMN IF NOT EXISTS platform_name VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS platform_slug VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS tags TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX IF NOT EXISTS idx_assets_platform_slug ON assets(platform_slug);
+CREATE INDEX IF NOT EXISTS idx_assets_tags ON assets USING GIN (tags);
+
+-- Latest market data of each asset in USD, replaced on every quotes sync
+CREATE TABLE IF NOT EXISTS asset_quotes (
+                              asset_id INT PRIMARY KEY,
+                              price DOUBLE PRECISION,
+                              market_cap DOUBLE PRECISION,
+                              volume_24h DOUBLE PRECISION,
+                              percent_change_24h DOUBLE PRECISION,
+                              percent_change_7d DOUBLE PRECISION,
+                              updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                              FOREIGN KEY (asset_id) REFERENCES assets(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_asset_quotes_market_cap ON asset_quotes(market_cap);
+CREATE INDEX IF NOT EXISTS idx_asset_quotes_volume_24h ON asset_quotes(volume_24h);
+-- +goose StatementEnd
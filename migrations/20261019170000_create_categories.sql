@@ -0,0 +1,27 @@
+-- +goose StatementBegin
+CREATE TABLE IF NOT EXISTS categories (
+                            id VARCHAR(64) PRIMARY KEY,
+                            name VARCHAR(255) NOT NULL,
+                            title VARCHAR(255),
+                            description TEXT,
+                            num_tokens INT,
+                            updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_categories_name ON categories(LOWER(name));
+
+CREATE TABLE IF NOT EXISTS asset_categories (
+                                  asset_id INT NOT NULL,
+                                  category_id VARCHAR(64) NOT NULL,
+                                  PRIMARY KEY (asset_id, category_id),
+                                  FOREIGN KEY (asset_id) REFERENCES assets(id) ON DELETE CASCADE,
+                                  FOREIGN KEY (category_id) REFERENCES categories(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_asset_categories_category_id ON asset_categories(category_id);
+
+-- Smart groups mirror the top `smart_limit` assets (by rank) of a category, refreshed on every category sync
+ALTER TABLE watchlist_groups
+    ADD COLUMN IF NOT EXISTS smart_category_id VARCHAR(64) REFERENCES categories(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS smart_limit INT;
+-- +goose StatementEnd
@@ -0,0 +1,17 @@
+-- +goose StatementBegin
+-- Smart groups keep their membership rule as JSON and are re-evaluated on a schedule, after syncs and on stale reads
+ALTER TABLE watchlist_groups
+    ADD COLUMN IF NOT EXISTS smart_rule JSONB,
+    ADD COLUMN IF NOT EXISTS smart_refreshed_at TIMESTAMP WITH TIME ZONE;
+
+-- Category smart groups become rules holding the top assets of the category by rank
+UPDATE watchlist_groups
+SET smart_rule = jsonb_build_object('filters', jsonb_build_object('category', smart_category_id),
+                                    'sort', 'rank',
+                                    'limit', COALESCE(smart_limit, 100))
+WHERE smart_category_id IS NOT NULL;
+
+ALTER TABLE watchlist_groups
+    DROP COLUMN IF EXISTS smart_category_id,
+    DROP COLUMN IF EXISTS smart_limit;
+-- +goose StatementEnd
@@ -0,0 +1,12 @@
+-- +goose StatementBegin
+-- Bumped whenever the rendering of a group or of its entries changes, ETags and Last-Modified derive from them
+ALTER TABLE watchlist_groups
+    ADD COLUMN IF NOT EXISTS version INT NOT NULL DEFAULT 1,
+    ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE;
+
+UPDATE watchlist_groups SET updated_at = COALESCE(deleted_at, created_at, NOW()) WHERE updated_at IS NULL;
+
+ALTER TABLE watchlist_groups
+    ALTER COLUMN updated_at SET DEFAULT CURRENT_TIMESTAMP,
+    ALTER COLUMN updated_at SET NOT NULL;
+-- +goose StatementEnd
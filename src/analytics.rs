@@ -0,0 +1,386 @@
+use std::collections::{BTreeMap, HashMap};
+use actix_web::{HttpMessage, HttpRequest};
+use actix_web::web::{Data, Json, Path, Query};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Arguments, Row};
+use sqlx::postgres::PgArguments;
+use tracing::instrument;
+use crate::cache::CacheKey;
+use crate::candles::CandleInterval;
+use crate::config::CONFIG;
+use crate::errors::ApiError;
+use crate::errors::ApiError::BadRequest;
+use crate::helpers::respond_json;
+use crate::middleware_custom::Claims;
+use crate::server::AppState;
+use crate::watchlist::WatchlistResponse;
+use crate::watchlistgroup::find_user_watchlist_group;
+
+const TRADING_DAYS_PER_YEAR: f64 = 365.0;
+
+// Close prices of one asset ordered by close time
+type Series = Vec<(DateTime<Utc>, f64)>;
+
+#[derive(Debug, Clone, Copy)]
+enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    const ALL: [Period; 3] = [Period::Day, Period::Week, Period::Month];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Period::Day => "24h",
+            Period::Week => "7d",
+            Period::Month => "30d",
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        match self {
+            Period::Day => Duration::hours(24),
+            Period::Week => Duration::days(7),
+            Period::Month => Duration::days(30),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AnalyticsQuery {
+    // Comma separated `asset:weight` pairs where asset is an asset id or a symbol, e.g. `BTC:0.6,1027:0.4`
+    weights: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Performer {
+    id: i32,
+    symbol: String,
+    #[serde(rename = "return")]
+    value: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeriodAnalytics {
+    equal_weighted_return: Option<f64>,
+    custom_weighted_return: Option<f64>,
+    best: Option<Performer>,
+    worst: Option<Performer>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssetAnalytics {
+    #[serde(flatten)]
+    asset: WatchlistResponse,
+    weight: Option<f64>,
+    returns: BTreeMap<String, Option<f64>>,
+    volatility_30d: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CorrelationMatrix {
+    asset_ids: Vec<i32>,
+    matrix: Vec<Vec<Option<f64>>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GroupAnalyticsResponse {
+    group_id: i32,
+    as_of: String,
+    periods: BTreeMap<String, PeriodAnalytics>,
+    volatility_30d: Option<f64>,
+    assets: Vec<AssetAnalytics>,
+    correlation: CorrelationMatrix,
+}
+
+fn parse_weights(weights: &str, assets: &[WatchlistResponse]) -> Result<HashMap<i32, f64>, ApiError> {
+    let mut parsed = HashMap::new();
+    for pair in weights.split(',').filter(|pair| !pair.trim().is_empty()) {
+        let (key, weight) = pair.split_once(':')
+            .ok_or(BadRequest(format!("Invalid weight: {}", pair)))?;
+        let weight: f64 = weight.trim().parse()
+            .ok()
+            .filter(|weight: &f64| weight.is_finite() && *weight >= 0.0)
+            .ok_or(BadRequest(format!("Invalid weight: {}", pair)))?;
+        let key = key.trim();
+        let asset = assets.iter()
+            .find(|asset| asset.id.to_string() == key || asset.symbol.eq_ignore_ascii_case(key))
+            .ok_or(BadRequest(format!("{} is not in this watchlist group", key)))?;
+        parsed.insert(asset.id, weight);
+    }
+
+    if !parsed.is_empty() && parsed.values().sum::<f64>() <= 0.0 {
+        return Err(BadRequest("Weights must add up to more than zero".into()));
+    }
+    Ok(parsed)
+}
+
+// Simple return between the latest close and the last close at least `period` older. The base close
+// must fall within `tolerance` of the target so gaps in the history do not stretch the period.
+fn period_return(series: &[(DateTime<Utc>, f64)], period: Duration, tolerance: Duration) -> Option<f64> {
+    let (last_time, last_close) = series.last()?;
+    let target = *last_time - period;
+    let (base_time, base_close) = series.iter().rev().find(|(time, _)| *time <= target)?;
+
+    if target - *base_time > tolerance || *base_close == 0.0 {
+        return None;
+    }
+    Some(last_close / base_close - 1.0)
+}
+
+// Daily log returns keyed by the close date
+fn log_returns(series: &[(DateTime<Utc>, f64)]) -> BTreeMap<DateTime<Utc>, f64> {
+    series
+        .windows(2)
+        .filter(|window| window[0].1 > 0.0 && window[1].1 > 0.0)
+        .map(|window| (window[1].0, (window[1].1 / window[0].1).ln()))
+        .collect()
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+// Annualised standard deviation of daily log returns
+fn volatility(returns: &[f64]) -> Option<f64> {
+    if returns.len() < 2 {
+        return None;
+    }
+    let average = mean(returns)?;
+    let variance = returns.iter().map(|value| (value - average).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+    Some((variance * TRADING_DAYS_PER_YEAR).sqrt())
+}
+
+// Pearson correlation over the dates both series have a return for
+fn correlation(a: &BTreeMap<DateTime<Utc>, f64>, b: &BTreeMap<DateTime<Utc>, f64>) -> Option<f64> {
+    let pairs: Vec<(f64, f64)> = a.iter()
+        .filter_map(|(date, value)| b.get(date).map(|other| (*value, *other)))
+        .collect();
+    if pairs.len() < 3 {
+        return None;
+    }
+
+    let mean_a = pairs.iter().map(|(value, _)| value).sum::<f64>() / pairs.len() as f64;
+    let mean_b = pairs.iter().map(|(_, value)| value).sum::<f64>() / pairs.len() as f64;
+    let covariance: f64 = pairs.iter().map(|(x, y)| (x - mean_a) * (y - mean_b)).sum();
+    let variance_a: f64 = pairs.iter().map(|(x, _)| (x - mean_a).powi(2)).sum();
+    let variance_b: f64 = pairs.iter().map(|(_, y)| (y - mean_b).powi(2)).sum();
+
+    if variance_a == 0.0 || variance_b == 0.0 {
+        return None;
+    }
+    Some(covariance / (variance_a * variance_b).sqrt())
+}
+
+fn weighted_return(returns: &[(i32, f64)], weights: &HashMap<i32, f64>) -> Option<f64> {
+    let total: f64 = returns.iter().filter_map(|(id, _)| weights.get(id)).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    Some(returns.iter()
+        .filter_map(|(id, value)| weights.get(id).map(|weight| value * weight / total))
+        .sum())
+}
+
+fn compute_analytics(
+    group_id: i32,
+    assets: Vec<WatchlistResponse>,
+    weights: &HashMap<i32, f64>,
+    daily: &HashMap<i32, Series>,
+    hourly: &HashMap<i32, Series>,
+) -> GroupAnalyticsResponse {
+    let empty: Series = vec![];
+    let daily_returns: HashMap<i32, BTreeMap<DateTime<Utc>, f64>> = assets.iter()
+        .map(|asset| (asset.id, log_returns(daily.get(&asset.id).unwrap_or(&empty))))
+        .collect();
+
+    let asset_returns = |asset_id: i32, period: Period| -> Option<f64> {
+        let daily = daily.get(&asset_id).unwrap_or(&empty);
+        match period {
+            Period::Day => period_return(hourly.get(&asset_id).unwrap_or(&empty), period.duration(), Duration::hours(2))
+                .or_else(|| period_return(daily, period.duration(), Duration::hours(12))),
+            _ => period_return(daily, period.duration(), Duration::days(1)),
+        }
+    };
+
+    let mut periods = BTreeMap::new();
+    for period in Period::ALL {
+        let returns: Vec<(i32, f64)> = assets.iter()
+            .filter_map(|asset| asset_returns(asset.id, period).map(|value| (asset.id, value)))
+            .collect();
+        let performer = |(id, value): &(i32, f64)| Performer {
+            id: *id,
+            symbol: assets.iter().find(|asset| asset.id == *id).map(|asset| asset.symbol.clone()).unwrap_or_default(),
+            value: *value,
+        };
+
+        periods.insert(period.label().to_string(), PeriodAnalytics {
+            equal_weighted_return: mean(&returns.iter().map(|(_, value)| *value).collect::<Vec<f64>>()),
+            custom_weighted_return: if weights.is_empty() { None } else { weighted_return(&returns, weights) },
+            best: returns.iter().max_by(|a, b| a.1.total_cmp(&b.1)).map(performer),
+            worst: returns.iter().min_by(|a, b| a.1.total_cmp(&b.1)).map(performer),
+        });
+    }
+
+    // Equal weighted basket rebalanced daily, over the days every member has a return for
+    let mut basket: BTreeMap<DateTime<Utc>, Vec<f64>> = BTreeMap::new();
+    for returns in daily_returns.values() {
+        for (date, value) in returns {
+            basket.entry(*date).or_default().push(*value);
+        }
+    }
+    let basket_returns: Vec<f64> = basket.values()
+        .filter(|values| values.len() == assets.len())
+        .filter_map(|values| mean(values))
+        .collect();
+
+    let ids: Vec<i32> = assets.iter().map(|asset| asset.id).collect();
+    let matrix = ids.iter()
+        .map(|a| ids.iter()
+            .map(|b| if a == b { Some(1.0) } else { correlation(&daily_returns[a], &daily_returns[b]) })
+            .collect())
+        .collect();
+
+    let assets = assets.into_iter()
+        .map(|asset| AssetAnalytics {
+            weight: weights.get(&asset.id).copied(),
+            returns: Period::ALL.iter()
+                .map(|period| (period.label().to_string(), asset_returns(asset.id, *period)))
+                .collect(),
+            volatility_30d: volatility(&daily_returns[&asset.id].values().copied().collect::<Vec<f64>>()),
+            asset,
+        })
+        .collect();
+
+    GroupAnalyticsResponse {
+        group_id,
+        as_of: Utc::now().to_rfc3339(),
+        periods,
+        volatility_30d: volatility(&basket_returns),
+        assets,
+        correlation: CorrelationMatrix { asset_ids: ids, matrix },
+    }
+}
+
+#[instrument]
+pub async fn retrieve_group_analytics(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<i32>,
+    query: Query<AnalyticsQuery>,
+) -> Result<Json<GroupAnalyticsResponse>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let group_id = path.into_inner();
+    find_user_watchlist_group(&state.db, user_id, group_id).await?;
+
+    let cache_key = format!("group_analytics::{}", group_id);
+    let cache_field = serde_json::to_string(&query.0)?;
+    let analytics = state.redis_client.read_through(CacheKey::new(cache_key, cache_field).ttl(CONFIG.analytics_cache_ttl_secs), async {
+        let mut args = PgArguments::default();
+        args.add(group_id);
+        let assets: Vec<WatchlistResponse> = state.db
+            .fetch_all("SELECT a.id, a.name, a.symbol, a.rank FROM watchlist w JOIN assets a ON w.asset_id = a.id WHERE w.group_id = $1 ORDER BY a.id", args)
+            .await?
+            .iter()
+            .map(WatchlistResponse::from_row)
+            .collect();
+        let weights = parse_weights(query.weights.as_deref().unwrap_or_default(), &assets)?;
+
+        // 31 days of daily closes give 30 daily returns, 25 hours of hourly closes cover the 24h change
+        let mut args = PgArguments::default();
+        args.add(assets.iter().map(|asset| asset.id).collect::<Vec<i32>>());
+        args.add(CandleInterval::OneDay.code());
+        args.add(CandleInterval::OneHour.code());
+        let records = state.db
+            .fetch_all(r#"SELECT asset_id, timeframe, close_time, close FROM asset_candles
+                          WHERE asset_id = ANY($1)
+                            AND ((timeframe = $2 AND close_time >= NOW() - INTERVAL '32 days')
+                              OR (timeframe = $3 AND close_time >= NOW() - INTERVAL '26 hours'))
+                          ORDER BY asset_id, timeframe, close_time"#, args)
+            .await?;
+
+        let mut daily: HashMap<i32, Series> = HashMap::new();
+        let mut hourly: HashMap<i32, Series> = HashMap::new();
+        for record in &records {
+            let series = if record.get::<String, _>("timeframe") == CandleInterval::OneDay.code() { &mut daily } else { &mut hourly };
+            series.entry(record.get("asset_id")).or_default().push((record.get("close_time"), record.get("close")));
+        }
+
+        let analytics = compute_analytics(group_id, assets, &weights, &daily, &hourly);
+
+        Ok(analytics)
+    })
+    .await?;
+
+    respond_json(analytics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn daily_series(closes: &[f64]) -> Series {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        closes.iter().enumerate()
+            .map(|(day, close)| (start + Duration::days(day as i64), *close))
+            .collect()
+    }
+
+    fn asset(id: i32, symbol: &str) -> WatchlistResponse {
+        WatchlistResponse { id, name: symbol.into(), symbol: symbol.into(), rank: None, added_at: None, categories: None }
+    }
+
+    #[test]
+    fn test_unit_period_return() {
+        let series = daily_series(&[100.0, 110.0, 120.0, 90.0, 99.0, 100.0, 105.0, 120.0]);
+
+        assert!((period_return(&series, Duration::days(7), Duration::days(1)).unwrap() - 0.2).abs() < 1e-9);
+        assert_eq!(period_return(&series, Duration::days(30), Duration::days(1)), None);
+        assert_eq!(period_return(&[], Duration::days(1), Duration::days(1)), None);
+    }
+
+    #[test]
+    fn test_unit_volatility_and_correlation() {
+        let a = log_returns(&daily_series(&[100.0, 101.0, 99.0, 102.0, 100.0]));
+        let b = log_returns(&daily_series(&[50.0, 50.5, 49.5, 51.0, 50.0]));
+        let flat = log_returns(&daily_series(&[10.0, 10.0, 10.0, 10.0]));
+
+        assert!(volatility(&a.values().copied().collect::<Vec<f64>>()).unwrap() > 0.0);
+        assert!((correlation(&a, &b).unwrap() - 1.0).abs() < 1e-9);
+        assert_eq!(correlation(&a, &flat), None);
+    }
+
+    #[test]
+    fn test_unit_weights() {
+        let assets = vec![asset(1, "BTC"), asset(1027, "ETH")];
+        let weights = parse_weights("btc:3, 1027:1", &assets).unwrap();
+
+        assert!((weighted_return(&[(1, 0.1), (1027, -0.1)], &weights).unwrap() - 0.05).abs() < 1e-9);
+        assert!(parse_weights("SOL:1", &assets).is_err());
+        assert!(parse_weights("BTC:-1", &assets).is_err());
+        assert!(parse_weights("BTC:0", &assets).is_err());
+    }
+
+    #[test]
+    fn test_unit_compute_analytics() {
+        let assets = vec![asset(1, "BTC"), asset(1027, "ETH")];
+        let daily = HashMap::from([
+            (1, daily_series(&[100.0, 100.0, 100.0, 100.0, 100.0, 100.0, 100.0, 110.0])),
+            (1027, daily_series(&[100.0, 100.0, 100.0, 100.0, 100.0, 100.0, 100.0, 90.0])),
+        ]);
+        let analytics = compute_analytics(7, assets, &HashMap::new(), &daily, &HashMap::new());
+        let week = &analytics.periods["7d"];
+
+        assert!(week.equal_weighted_return.unwrap().abs() < 1e-9);
+        assert_eq!(week.best.as_ref().unwrap().symbol, "BTC");
+        assert_eq!(week.worst.as_ref().unwrap().symbol, "ETH");
+        assert_eq!(week.custom_weighted_return, None);
+        assert_eq!(analytics.correlation.matrix[0][0], Some(1.0));
+    }
+}
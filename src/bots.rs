@@ -0,0 +1,702 @@
+use std::sync::Arc;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use actix_web::web::{Bytes, Data, Json, Path, Query};
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use log::{error, info};
+use rand::Rng;
+use reqwest::Client;
+use sqlx::{Arguments, Row};
+use sqlx::postgres::PgArguments;
+use tracing::instrument;
+use crate::config::CONFIG;
+use crate::currency::{conversion_rate, BASE_CURRENCY};
+use crate::database::Database;
+use crate::errors::ApiError;
+use crate::errors::ApiError::{BadRequest, InvalidToken};
+use crate::helpers::{format_datetime, respond_json, respond_ok, Timestamp, TimestampQuery};
+use crate::middleware_custom::Claims;
+use crate::notifications::{http_client, send_request, ChannelTarget, Notification, NotificationSender, SendError};
+use crate::portfolio::latest_prices;
+use crate::preferences::{load_user_preferences, NotificationChannel};
+use crate::server::AppState;
+use crate::smart_groups::ensure_manual_group;
+use crate::telemetry::with_trace_context;
+use crate::watchlistgroup::{find_user_watchlist_group, touch_watchlist_group};
+
+// Called by Telegram and Discord themselves, they authenticate with their own secrets instead of a JWT
+pub const BOT_HOOKS_PATH: &str = "/api/v1/bots/hooks/";
+
+const TELEGRAM_SECRET_HEADER: &str = "X-Telegram-Bot-Api-Secret-Token";
+const DISCORD_SIGNATURE_HEADER: &str = "X-Signature-Ed25519";
+const DISCORD_TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
+const DISCORD_COMMAND: &str = "watchlist";
+// Only the user who ran the command sees the reply
+const DISCORD_EPHEMERAL_FLAG: u64 = 64;
+
+// Without 0/O and 1/I so codes survive being read out loud
+const LINK_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const LINK_CODE_LENGTH: usize = 8;
+
+const HELP: &str = "Commands:
+/groups - list your watchlist groups
+/group <group_id> - show the assets of a group
+/add <group_id> <symbol> - add an asset to a group
+/remove <group_id> <symbol> - remove an asset from a group
+/link <code> - link this chat with your account
+/unlink - stop sending alerts to this chat";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BotPlatform {
+    Telegram,
+    Discord,
+}
+
+impl BotPlatform {
+    // Value stored in `bot_accounts.platform`, the same as the notification channel code
+    fn code(&self) -> &'static str {
+        self.channel().code()
+    }
+
+    fn from_code(code: &str) -> Self {
+        match code {
+            "discord" => BotPlatform::Discord,
+            _ => BotPlatform::Telegram,
+        }
+    }
+
+    fn channel(&self) -> NotificationChannel {
+        match self {
+            BotPlatform::Telegram => NotificationChannel::Telegram,
+            BotPlatform::Discord => NotificationChannel::Discord,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            BotPlatform::Telegram => !CONFIG.telegram_bot_token.is_empty(),
+            BotPlatform::Discord => !CONFIG.discord_bot_token.is_empty(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum BotCommand {
+    Help,
+    Link(String),
+    Unlink,
+    Groups,
+    Group(i32),
+    Add(i32, String),
+    Remove(i32, String),
+    // Carries the usage of a command that was called with the wrong arguments
+    Usage(&'static str),
+}
+
+// Accepts `/add 3 btc`, `add 3 btc` and Telegram's `/add@SomeBot 3 btc`
+fn parse_command(text: &str) -> BotCommand {
+    let mut words = text.split_whitespace();
+    let Some(name) = words.next() else {
+        return BotCommand::Help;
+    };
+    let name = name.trim_start_matches('/').split('@').next().unwrap_or_default().to_lowercase();
+    let args: Vec<&str> = words.collect();
+    let group_id = args.first().and_then(|group_id| group_id.trim_start_matches('#').parse::<i32>().ok());
+
+    match (name.as_str(), args.len()) {
+        // Telegram deep links arrive as `/start <code>`
+        ("start" | "link", 1) => BotCommand::Link(args[0].to_uppercase()),
+        ("link", _) => BotCommand::Usage("/link <code>"),
+        ("unlink", _) => BotCommand::Unlink,
+        ("groups", _) => BotCommand::Groups,
+        ("group", 1) => group_id.map_or(BotCommand::Usage("/group <group_id>"), BotCommand::Group),
+        ("group", _) => BotCommand::Usage("/group <group_id>"),
+        ("add", 2) => group_id.map_or(BotCommand::Usage("/add <group_id> <symbol>"), |group_id| BotCommand::Add(group_id, args[1].to_uppercase())),
+        ("add", _) => BotCommand::Usage("/add <group_id> <symbol>"),
+        ("remove", 2) => group_id.map_or(BotCommand::Usage("/remove <group_id> <symbol>"), |group_id| BotCommand::Remove(group_id, args[1].to_uppercase())),
+        ("remove", _) => BotCommand::Usage("/remove <group_id> <symbol>"),
+        _ => BotCommand::Help,
+    }
+}
+
+fn generate_link_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..LINK_CODE_LENGTH)
+        .map(|_| LINK_CODE_ALPHABET[rng.gen_range(0..LINK_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+fn format_price(price: f64) -> String {
+    if price >= 1.0 {
+        format!("{:.2}", price)
+    } else {
+        format!("{:.6}", price)
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+// Discord signs `{timestamp}{body}` with the application's Ed25519 key
+fn verify_discord_signature(public_key: &str, signature: &str, timestamp: &str, body: &[u8]) -> bool {
+    let key = decode_hex(public_key)
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .and_then(|key| VerifyingKey::from_bytes(&key).ok());
+    let signature = decode_hex(signature)
+        .and_then(|signature| <[u8; 64]>::try_from(signature).ok())
+        .map(|signature| Signature::from_bytes(&signature));
+
+    let (Some(key), Some(signature)) = (key, signature) else {
+        return false;
+    };
+    let mut message = timestamp.as_bytes().to_vec();
+    message.extend_from_slice(body);
+    key.verify(&message, &signature).is_ok()
+}
+
+async fn linked_user(db: &Arc<dyn Database>, platform: BotPlatform, external_user_id: &str) -> Result<Option<i32>, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(platform.code());
+    args.add(external_user_id);
+    let record = db
+        .fetch_optional("SELECT user_id FROM bot_accounts WHERE platform = $1 AND external_user_id = $2", args)
+        .await?;
+    Ok(record.map(|record| record.get("user_id")))
+}
+
+async fn link_account(state: &AppState, platform: BotPlatform, external_user_id: &str, chat_id: &str, code: &str) -> Result<String, ApiError> {
+    // Codes are single use, so they are deleted whether or not they are still valid
+    let mut args = PgArguments::default();
+    args.add(code);
+    args.add(platform.code());
+    let record = state.db
+        .fetch_optional("DELETE FROM bot_link_codes WHERE code = $1 AND platform = $2 RETURNING user_id, expires_at", args)
+        .await?;
+    let Some(record) = record.filter(|record| record.get::<DateTime<Utc>, _>("expires_at") > Utc::now()) else {
+        return Ok("This link code is invalid or has expired, create a new one in the app.".into());
+    };
+    let user_id: i32 = record.get("user_id");
+
+    // A user links one chat account per platform
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    args.add(platform.code());
+    state.db.execute("DELETE FROM bot_accounts WHERE user_id = $1 AND platform = $2", args).await?;
+
+    let mut args = PgArguments::default();
+    args.add(platform.code());
+    args.add(external_user_id);
+    args.add(user_id);
+    args.add(chat_id);
+    state.db
+        .execute(r#"INSERT INTO bot_accounts (platform, external_user_id, user_id, chat_id)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT (platform, external_user_id) DO UPDATE
+                    SET user_id = EXCLUDED.user_id, chat_id = EXCLUDED.chat_id, linked_at = NOW()"#, args)
+        .await?;
+
+    // Alerts for the channel go to the linked chat
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    args.add(platform.code());
+    args.add(chat_id);
+    state.db
+        .execute(r#"INSERT INTO notification_channel_settings (user_id, channel, target)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (user_id, channel) DO UPDATE SET target = EXCLUDED.target, secret = NULL, updated_at = NOW()"#, args)
+        .await?;
+
+    Ok(format!("Linked! Enable the {} notification channel in your preferences to receive alerts here.", platform.code()))
+}
+
+async fn unlink_account(db: &Arc<dyn Database>, platform: BotPlatform, user_id: i32) -> Result<bool, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    args.add(platform.code());
+    let result = db.execute("DELETE FROM bot_accounts WHERE user_id = $1 AND platform = $2", args).await?;
+
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    args.add(platform.code());
+    db.execute("DELETE FROM notification_channel_settings WHERE user_id = $1 AND channel = $2", args).await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn list_groups(db: &Arc<dyn Database>, user_id: i32) -> Result<String, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    let records = db
+        .fetch_all(r#"SELECT wg.id, wg.name, COUNT(w.asset_id) AS assets
+                      FROM watchlist_groups wg LEFT JOIN watchlist w ON w.group_id = wg.id
+                      WHERE wg.user_id = $1 AND wg.deleted_at IS NULL
+                      GROUP BY wg.id, wg.name ORDER BY wg.id"#, args)
+        .await?;
+
+    if records.is_empty() {
+        return Ok("You have no watchlist groups yet.".into());
+    }
+    let lines: Vec<String> = records.iter()
+        .map(|record| format!("#{} {} ({} assets)", record.get::<i32, _>("id"), record.get::<String, _>("name"), record.get::<i64, _>("assets")))
+        .collect();
+    Ok(format!("Your watchlist groups:\n{}", lines.join("\n")))
+}
+
+async fn show_group(state: &AppState, user_id: i32, group_id: i32) -> Result<String, ApiError> {
+    let group = find_user_watchlist_group(&state.db, user_id, group_id).await?;
+
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    let records = state.db
+        .fetch_all(r#"SELECT a.id, a.symbol, a.name FROM watchlist w JOIN assets a ON a.id = w.asset_id
+                      WHERE w.group_id = $1 ORDER BY a.rank NULLS LAST, a.symbol"#, args)
+        .await?;
+    if records.is_empty() {
+        return Ok(format!("#{} {} has no assets yet.", group_id, group.name));
+    }
+
+    let prices = latest_prices(&state.db, records.iter().map(|record| record.get("id")).collect()).await?;
+    // Prices are shown in the user's currency, falling back to USD when the rate is unavailable
+    let currency = load_user_preferences(&state.db, &state.redis_client, user_id).await?.quote_currency;
+    let (currency, rate) = match conversion_rate(&state.redis_client, &currency).await {
+        Ok(rate) => (currency, rate),
+        Err(_) => (BASE_CURRENCY.to_string(), 1.0),
+    };
+
+    let lines: Vec<String> = records.iter()
+        .map(|record| {
+            let price = prices.get(&record.get::<i32, _>("id"))
+                .map_or("-".to_string(), |price| format!("{} {}", format_price(price * rate), currency));
+            format!("{} {}: {}", record.get::<String, _>("symbol"), record.get::<Option<String>, _>("name").unwrap_or_default(), price)
+        })
+        .collect();
+    Ok(format!("#{} {}\n{}", group_id, group.name, lines.join("\n")))
+}
+
+async fn find_asset_id(db: &Arc<dyn Database>, symbol: &str) -> Result<Option<i32>, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(symbol);
+    // Symbols are not unique, the best ranked asset wins
+    let record = db
+        .fetch_optional("SELECT id FROM assets WHERE UPPER(symbol) = $1 ORDER BY rank NULLS LAST, id LIMIT 1", args)
+        .await?;
+    Ok(record.map(|record| record.get("id")))
+}
+
+async fn change_group(state: &AppState, user_id: i32, group_id: i32, symbol: &str, add: bool) -> Result<String, ApiError> {
+    let group = find_user_watchlist_group(&state.db, user_id, group_id).await?;
+    ensure_manual_group(&state.db, group_id).await?;
+    let Some(asset_id) = find_asset_id(&state.db, symbol).await? else {
+        return Ok(format!("Unknown symbol {}.", symbol));
+    };
+
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    args.add(asset_id);
+    let sql = if add {
+        "INSERT INTO watchlist (group_id, asset_id) VALUES ($1, $2) ON CONFLICT DO NOTHING"
+    } else {
+        "DELETE FROM watchlist WHERE group_id = $1 AND asset_id = $2"
+    };
+    let result = state.db.execute(sql, args).await?;
+    if result.rows_affected() == 0 {
+        let reason = if add { "is already in" } else { "is not in" };
+        return Ok(format!("{} {} #{} {}.", symbol, reason, group_id, group.name));
+    }
+    touch_watchlist_group(&state.db, group_id).await?;
+
+    state.redis_client.del(format!("all_watchlist::{}", group_id)).await;
+    state.redis_client.del(format!("group_analytics::{}", group_id)).await;
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
+
+    let action = if add { "Added" } else { "Removed" };
+    let preposition = if add { "to" } else { "from" };
+    Ok(format!("{} {} {} #{} {}.", action, symbol, preposition, group_id, group.name))
+}
+
+async fn run_command(state: &AppState, platform: BotPlatform, external_user_id: &str, chat_id: &str, command: BotCommand) -> Result<String, ApiError> {
+    match command {
+        BotCommand::Help => return Ok(HELP.into()),
+        BotCommand::Usage(usage) => return Ok(format!("Usage: {}", usage)),
+        BotCommand::Link(code) => return link_account(state, platform, external_user_id, chat_id, &code).await,
+        _ => {}
+    }
+
+    let Some(user_id) = linked_user(&state.db, platform, external_user_id).await? else {
+        return Ok("This chat is not linked yet. Create a link code in the app and send /link <code>.".into());
+    };
+
+    let reply = match command {
+        BotCommand::Unlink => {
+            unlink_account(&state.db, platform, user_id).await?;
+            Ok("Unlinked, this chat will no longer receive alerts.".into())
+        }
+        BotCommand::Groups => list_groups(&state.db, user_id).await,
+        BotCommand::Group(group_id) => show_group(state, user_id, group_id).await,
+        BotCommand::Add(group_id, symbol) => change_group(state, user_id, group_id, &symbol, true).await,
+        BotCommand::Remove(group_id, symbol) => change_group(state, user_id, group_id, &symbol, false).await,
+        BotCommand::Help | BotCommand::Usage(_) | BotCommand::Link(_) => unreachable!(),
+    };
+
+    match reply {
+        Err(ApiError::NotFound) => Ok("Watchlist group not found.".into()),
+        Err(BadRequest(message)) => Ok(format!("{}.", message)),
+        reply => reply,
+    }
+}
+
+// Replies never fail the webhook, the platform would only retry the same command
+async fn reply_to_command(state: &AppState, platform: BotPlatform, external_user_id: &str, chat_id: &str, text: &str) -> String {
+    run_command(state, platform, external_user_id, chat_id, parse_command(text)).await
+        .unwrap_or_else(|err| {
+            error!("Failed to run the {} bot command {:?}: {}", platform.code(), text, err);
+            "Something went wrong, please try again later.".into()
+        })
+}
+
+fn alert_text(notification: &Notification) -> String {
+    format!("{}\n\n{}", notification.title, notification.body)
+}
+
+async fn send_telegram_message(client: &Client, chat_id: &str, text: &str) -> Result<Option<u16>, SendError> {
+    let request = client
+        .post(format!("{}/bot{}/sendMessage", CONFIG.telegram_api_base_url, CONFIG.telegram_bot_token))
+        .json(&serde_json::json!({ "chat_id": chat_id, "text": text }));
+    send_request(request, "Telegram").await
+}
+
+async fn send_discord_message(client: &Client, channel_id: &str, text: &str) -> Result<Option<u16>, SendError> {
+    let request = client
+        .post(format!("{}/channels/{}/messages", CONFIG.discord_api_base_url, channel_id))
+        .header(reqwest::header::AUTHORIZATION, format!("Bot {}", CONFIG.discord_bot_token))
+        .json(&serde_json::json!({ "content": text }));
+    send_request(request, "Discord").await
+}
+
+pub struct TelegramSender {
+    pub client: Client,
+}
+
+#[async_trait]
+impl NotificationSender for TelegramSender {
+    async fn send(&self, _user_id: i32, target: Option<&ChannelTarget>, notification: &Notification) -> Result<Option<u16>, SendError> {
+        let target = target.ok_or_else(|| SendError::new("No Telegram chat linked", false))?;
+        send_telegram_message(&self.client, &target.address, &alert_text(notification)).await
+    }
+}
+
+pub struct DiscordSender {
+    pub client: Client,
+}
+
+#[async_trait]
+impl NotificationSender for DiscordSender {
+    async fn send(&self, _user_id: i32, target: Option<&ChannelTarget>, notification: &Notification) -> Result<Option<u16>, SendError> {
+        let target = target.ok_or_else(|| SendError::new("No Discord channel linked", false))?;
+        send_discord_message(&self.client, &target.address, &alert_text(notification)).await
+    }
+}
+
+// Register the `/watchlist <command>` slash command, commands are typed as text so both bots share one parser
+pub async fn register_discord_commands() -> Result<(), Box<dyn std::error::Error>> {
+    let command = serde_json::json!([{
+        "name": DISCORD_COMMAND,
+        "description": "Manage your crypto watchlist",
+        "options": [{
+            "type": 3,
+            "name": "command",
+            "description": "For example: groups, group 3, add 3 BTC, link ABCD2345",
+            "required": false,
+        }],
+    }]);
+
+    let request = http_client()?
+        .put(format!("{}/applications/{}/commands", CONFIG.discord_api_base_url, CONFIG.discord_application_id))
+        .header(reqwest::header::AUTHORIZATION, format!("Bot {}", CONFIG.discord_bot_token))
+        .json(&command);
+    with_trace_context(request)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    info!("Registered the Discord /{} command", DISCORD_COMMAND);
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LinkCodeRequest {
+    platform: BotPlatform,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LinkCodeResponse {
+    platform: BotPlatform,
+    code: String,
+    // What to send the bot to finish linking
+    command: String,
+    expires_at: Option<Timestamp>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BotAccountResponse {
+    platform: BotPlatform,
+    external_user_id: String,
+    chat_id: String,
+    linked_at: Option<Timestamp>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TelegramUpdate {
+    message: Option<TelegramMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TelegramMessage {
+    chat: TelegramChat,
+    from: Option<TelegramUser>,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TelegramChat {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TelegramUser {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscordInteraction {
+    #[serde(rename = "type")]
+    kind: u8,
+    data: Option<DiscordCommandData>,
+    // Set for commands run in a server, `user` is set for direct messages
+    member: Option<DiscordMember>,
+    user: Option<DiscordUser>,
+    channel_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscordCommandData {
+    #[serde(default)]
+    options: Vec<DiscordCommandOption>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscordCommandOption {
+    name: String,
+    value: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscordMember {
+    user: DiscordUser,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscordUser {
+    id: String,
+}
+
+const DISCORD_PING: u8 = 1;
+const DISCORD_APPLICATION_COMMAND: u8 = 2;
+const DISCORD_PONG: u8 = 1;
+const DISCORD_CHANNEL_MESSAGE: u8 = 4;
+
+#[instrument]
+pub async fn create_link_code(
+    state: Data<AppState>,
+    request: HttpRequest,
+    query: Query<TimestampQuery>,
+    body: Json<LinkCodeRequest>,
+) -> Result<Json<LinkCodeResponse>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let platform = body.platform;
+    if !platform.is_enabled() {
+        return Err(BadRequest(format!("The {} bot is not configured", platform.code())));
+    }
+
+    let code = generate_link_code();
+    let expires_at = Utc::now() + Duration::seconds(CONFIG.bot_link_code_ttl_secs);
+
+    // Only the latest code of a user is valid
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    args.add(platform.code());
+    state.db.execute("DELETE FROM bot_link_codes WHERE user_id = $1 AND platform = $2", args).await?;
+
+    let mut args = PgArguments::default();
+    args.add(&code);
+    args.add(user_id);
+    args.add(platform.code());
+    args.add(expires_at);
+    state.db
+        .execute("INSERT INTO bot_link_codes (code, user_id, platform, expires_at) VALUES ($1, $2, $3, $4)", args)
+        .await?;
+
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let command = match platform {
+        BotPlatform::Telegram => format!("/link {}", code),
+        BotPlatform::Discord => format!("/{} command:link {}", DISCORD_COMMAND, code),
+    };
+    respond_json(LinkCodeResponse {
+        platform,
+        code,
+        command,
+        expires_at: format_datetime(Some(expires_at), timezone, query.time_format.unwrap_or_default()),
+    })
+}
+
+#[instrument]
+pub async fn retrieve_bot_accounts(
+    state: Data<AppState>,
+    request: HttpRequest,
+    query: Query<TimestampQuery>,
+) -> Result<Json<Vec<BotAccountResponse>>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    let records = state.db
+        .fetch_all("SELECT platform, external_user_id, chat_id, linked_at FROM bot_accounts WHERE user_id = $1 ORDER BY platform", args)
+        .await?;
+
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let time_format = query.time_format.unwrap_or_default();
+    respond_json(records.iter()
+        .map(|record| BotAccountResponse {
+            platform: BotPlatform::from_code(record.get("platform")),
+            external_user_id: record.get("external_user_id"),
+            chat_id: record.get("chat_id"),
+            linked_at: format_datetime(record.get::<Option<DateTime<Utc>>, _>("linked_at"), timezone, time_format),
+        })
+        .collect())
+}
+
+#[instrument]
+pub async fn delete_bot_account(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<BotPlatform>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    if !unlink_account(&state.db, path.into_inner(), user_id).await? {
+        return Err(ApiError::NotFound);
+    }
+    respond_ok()
+}
+
+#[instrument(skip(body))]
+pub async fn telegram_webhook(
+    state: Data<AppState>,
+    request: HttpRequest,
+    body: Json<TelegramUpdate>,
+) -> Result<HttpResponse, ApiError> {
+    if !BotPlatform::Telegram.is_enabled() {
+        return Err(ApiError::NotFound);
+    }
+    let secret = request.headers().get(TELEGRAM_SECRET_HEADER).and_then(|secret| secret.to_str().ok());
+    if CONFIG.telegram_webhook_secret.is_empty() || secret != Some(CONFIG.telegram_webhook_secret.as_str()) {
+        return Err(InvalidToken);
+    }
+
+    // Other update types (edits, joins, ...) are acknowledged and ignored
+    let Some(message) = body.into_inner().message else {
+        return respond_ok();
+    };
+    let (Some(from), Some(text)) = (message.from, message.text) else {
+        return respond_ok();
+    };
+
+    let chat_id = message.chat.id.to_string();
+    let reply = reply_to_command(&state, BotPlatform::Telegram, &from.id.to_string(), &chat_id, &text).await;
+    match http_client() {
+        Ok(client) => if let Err(err) = send_telegram_message(&client, &chat_id, &reply).await {
+            error!("Failed to reply on Telegram: {:?}", err);
+        },
+        Err(err) => error!("Failed to build the Telegram client: {}", err),
+    }
+
+    respond_ok()
+}
+
+#[instrument(skip(body))]
+pub async fn discord_interactions(
+    state: Data<AppState>,
+    request: HttpRequest,
+    body: Bytes,
+) -> Result<HttpResponse, ApiError> {
+    if !BotPlatform::Discord.is_enabled() {
+        return Err(ApiError::NotFound);
+    }
+    let header = |name| request.headers().get(name).and_then(|value| value.to_str().ok()).unwrap_or_default();
+    if !verify_discord_signature(&CONFIG.discord_public_key, header(DISCORD_SIGNATURE_HEADER), header(DISCORD_TIMESTAMP_HEADER), &body) {
+        return Err(InvalidToken);
+    }
+
+    let interaction: DiscordInteraction = serde_json::from_slice(&body)
+        .map_err(|err| BadRequest(format!("Invalid interaction: {}", err)))?;
+    if interaction.kind == DISCORD_PING {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "type": DISCORD_PONG })));
+    }
+    if interaction.kind != DISCORD_APPLICATION_COMMAND {
+        return Err(BadRequest("Unsupported interaction type".into()));
+    }
+
+    let user = interaction.member.map(|member| member.user).or(interaction.user)
+        .ok_or(BadRequest("Interaction without a user".into()))?;
+    let chat_id = interaction.channel_id.ok_or(BadRequest("Interaction without a channel".into()))?;
+    let text = interaction.data
+        .and_then(|data| data.options.into_iter().find(|option| option.name == "command"))
+        .and_then(|option| option.value)
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default();
+
+    let reply = reply_to_command(&state, BotPlatform::Discord, &user.id, &chat_id, &text).await;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "type": DISCORD_CHANNEL_MESSAGE,
+        "data": { "content": reply, "flags": DISCORD_EPHEMERAL_FLAG },
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_unit_parse_command() {
+        assert_eq!(parse_command("/groups"), BotCommand::Groups);
+        assert_eq!(parse_command("/add@WatchlistBot 3 btc"), BotCommand::Add(3, "BTC".into()));
+        assert_eq!(parse_command("remove #3 eth"), BotCommand::Remove(3, "ETH".into()));
+        assert_eq!(parse_command("/start abcd2345"), BotCommand::Link("ABCD2345".into()));
+        assert_eq!(parse_command("/group three"), BotCommand::Usage("/group <group_id>"));
+        assert_eq!(parse_command("/add 3"), BotCommand::Usage("/add <group_id> <symbol>"));
+        assert_eq!(parse_command(""), BotCommand::Help);
+        assert_eq!(parse_command("hello"), BotCommand::Help);
+    }
+
+    #[test]
+    fn test_unit_verify_discord_signature() {
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let public_key: String = signing_key.verifying_key().to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+        let body = br#"{"type":1}"#;
+        let signature: String = signing_key.sign(b"1700000000{\"type\":1}").to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+
+        assert!(verify_discord_signature(&public_key, &signature, "1700000000", body));
+        assert!(!verify_discord_signature(&public_key, &signature, "1700000001", body));
+        assert!(!verify_discord_signature(&public_key, "zz", "1700000000", body));
+        assert!(!verify_discord_signature("", &signature, "1700000000", body));
+    }
+}
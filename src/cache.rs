@@ -1,88 +1,690 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
-use std::sync::Arc;
-use redis_async::client::PairedConnection;
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use redis_async::client::pubsub::PubsubStream;
+use redis_async::error::Error as RedisAsyncError;
+use futures_util::StreamExt;
 use crate::config::CONFIG;
-use futures_util::TryStreamExt;
-use kafka::producer::AsBytes;
-use redis_async::resp::{FromResp, RespValue};
+use redis_async::resp::RespValue;
 use serde::{Deserialize, Serialize};
-use tracing::instrument;
+use tracing::field::Empty;
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument, Span};
 use crate::errors::ApiError;
+use crate::metrics::{record_cache_lookup, record_redis_error};
+use crate::telemetry::record_outcome;
+use crate::redis_pool::{is_topology_change, node_connection_builder, RedisMode, RedisNodeHealth, RedisPool};
+
+// Part of every key namespace. Bump it whenever the shape of a cached payload changes so instances
+// running different releases during a rollout never read each other's entries.
+const PAYLOAD_VERSION: &str = "v3";
+// How often a request waiting on another one's cache fill checks for the value
+const LOCK_POLL_INTERVAL_MS: u64 = 25;
+
+lazy_static! {
+    static ref FAMILY_TTLS: HashMap<String, u64> = parse_family_ttls(&CONFIG.cache_family_ttls);
+}
+
+// `family=secs` pairs, e.g. `all_watchlist=600,candles=86400`
+fn parse_family_ttls(ttls: &str) -> HashMap<String, u64> {
+    ttls.split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .filter_map(|(family, secs)| Some((family.trim().to_string(), secs.trim().parse().ok()?)))
+        .collect()
+}
+
+// Keys are named `<family>::<id>`, e.g. `all_watchlist::42`
+fn key_family(key: &str) -> &str {
+    key.split("::").next().unwrap_or(key)
+}
+
+fn family_ttl(family: &str) -> u64 {
+    if let Some(ttl) = FAMILY_TTLS.get(family) {
+        return *ttl;
+    }
+    match family {
+        "conversion_rates" => CONFIG.conversion_rates_ttl_secs,
+        _ => CONFIG.cache_ttl_secs,
+    }
+}
+
+// `<prefix>:<payload version>[.<deploy version>]:<key>`
+fn namespaced_key(key: &str) -> String {
+    let version = match CONFIG.cache_key_version.as_str() {
+        "" => PAYLOAD_VERSION.to_string(),
+        deploy_version => format!("{}.{}", PAYLOAD_VERSION, deploy_version),
+    };
+    format!("{}:{}:{}", CONFIG.redis_key_prefix, version, key)
+}
+
+// Shared by every payload and deploy version, an instance running another release must drop its copies too
+fn invalidation_channel() -> String {
+    format!("{}:invalidations", CONFIG.redis_key_prefix)
+}
+
+// Bounded in-process tier in front of Redis. Entries live for `local_cache_ttl_secs` at most and the least
+// recently used ones are evicted first once `capacity` fields are stored.
+#[derive(Debug, Default)]
+struct LocalCache {
+    capacity: usize,
+    // key -> field -> entry, so that invalidating a key drops every variant of it
+    entries: HashMap<String, HashMap<String, LocalEntry>>,
+    // Last use -> (key, field), oldest first
+    recency: BTreeMap<u64, (String, String)>,
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct LocalEntry {
+    value: String,
+    expires_at: Instant,
+    used: u64,
+}
+
+impl LocalCache {
+    fn new(capacity: usize) -> Self {
+        LocalCache { capacity, ..Default::default() }
+    }
+
+    fn len(&self) -> usize {
+        self.recency.len()
+    }
+
+    fn get(&mut self, key: &str, field: &str, now: Instant) -> Option<String> {
+        let entry = self.entries.get_mut(key)?.get_mut(field)?;
+        if entry.expires_at <= now {
+            self.remove(key, field);
+            return None;
+        }
+        self.tick += 1;
+        let (key, field) = self.recency.remove(&entry.used)?;
+        entry.used = self.tick;
+        let value = entry.value.clone();
+        self.recency.insert(self.tick, (key, field));
+        Some(value)
+    }
+
+    fn insert(&mut self, key: &str, field: &str, value: String, expires_at: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.remove(key, field);
+        while self.len() >= self.capacity {
+            let Some((_, (key, field))) = self.recency.pop_first() else { break };
+            self.remove(&key, &field);
+        }
+        self.tick += 1;
+        self.entries.entry(key.to_string()).or_default()
+            .insert(field.to_string(), LocalEntry { value, expires_at, used: self.tick });
+        self.recency.insert(self.tick, (key.to_string(), field.to_string()));
+    }
+
+    fn remove(&mut self, key: &str, field: &str) {
+        let Some(fields) = self.entries.get_mut(key) else { return };
+        if let Some(entry) = fields.remove(field) {
+            self.recency.remove(&entry.used);
+        }
+        if fields.is_empty() {
+            self.entries.remove(key);
+        }
+    }
+
+    fn invalidate(&mut self, key: &str) {
+        for entry in self.entries.remove(key).into_iter().flat_map(|fields| fields.into_values()) {
+            self.recency.remove(&entry.used);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+// Hash fields expire on their own, so every value is stored with its expiry and the time it took to compute
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry<T> {
+    value: T,
+    expires_at: i64,
+    compute_ms: u64,
+}
+
+// Probabilistic early expiration (XFetch): the closer the expiry and the slower the value is to compute,
+// the likelier a read is to refresh it ahead of time. `random` is uniform in (0, 1].
+fn should_refresh_early(now_ms: i64, expires_at: i64, compute_ms: u64, beta: f64, random: f64) -> bool {
+    let head_start = -(compute_ms as f64) * beta * random.ln();
+    now_ms as f64 + head_start >= expires_at as f64
+}
+
+// Anything that can be cached as JSON
+pub trait Cacheable: Serialize + for<'de> Deserialize<'de> + Debug + Unpin {}
+
+impl<T> Cacheable for T where T: Serialize + for<'de> Deserialize<'de> + Debug + Unpin {}
+
+// Where a cached value lives: the hash `key`, the `field` of this variant and, unless set, the TTL of the key's family
+#[derive(Debug)]
+pub struct CacheKey {
+    key: String,
+    field: String,
+    ttl_secs: Option<u64>,
+}
+
+impl CacheKey {
+    pub fn new(key: String, field: String) -> Self {
+        CacheKey { key, field, ttl_secs: None }
+    }
+
+    pub fn ttl(self, secs: u64) -> Self {
+        CacheKey { ttl_secs: Some(secs), ..self }
+    }
+}
 
 #[derive(Debug)]
 pub struct Redis {
-    redis_client: PairedConnection,
+    // `None` until the first successful connection
+    pool: RwLock<Option<Arc<RedisPool>>>,
+    breaker: Mutex<Breaker>,
+    // Keys whose `del` could not be sent while Redis was down, replayed once it is back
+    pending_dels: Mutex<HashSet<String>>,
+    local: Mutex<LocalCache>,
+    // When each cache fill this instance is responsible for started, to record how long it took
+    fills: Mutex<HashMap<String, Instant>>,
+}
+
+// Circuit breaker around the connection: after `redis_breaker_failure_threshold` consecutive failures every
+// command is skipped, so requests are served from Postgres, until a background reconnect succeeds
+#[derive(Debug, Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    down_since: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CacheHealth {
+    pub status: String,
+    pub consecutive_failures: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub down_since: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    // Fields held by the in-process tier of this instance
+    pub local_entries: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<RedisMode>,
+    // Empty while the breaker is open
+    pub nodes: Vec<RedisNodeHealth>,
 }
 
+// Starts in the down state when Redis cannot be reached, the reconnect task brings it up later
 #[instrument]
 pub async fn create_redis_client() -> Result<Arc<Redis>, ApiError> {
-    // Init connection
-    let paired_conn = redis_async::client::paired_connect(&CONFIG.redis_host, CONFIG.redis_port).await.unwrap();
-
-    // Authenticate with the Redis server
-    let auth_command = vec![
-        RespValue::BulkString(b"AUTH".to_vec()),
-        RespValue::BulkString(CONFIG.redis_password.as_bytes().to_vec()),
-    ];
+    let redis = Redis {
+        pool: RwLock::new(None),
+        breaker: Mutex::new(Breaker::default()),
+        pending_dels: Mutex::new(HashSet::new()),
+        local: Mutex::new(LocalCache::new(CONFIG.local_cache_capacity)),
+        fills: Mutex::new(HashMap::new()),
+    };
 
-    let result = paired_conn.send::<RespValue>(RespValue::Array(auth_command)).await.map_err(ApiError::from);
+    match RedisPool::connect().await {
+        Ok(pool) => *redis.pool.write().unwrap() = Some(Arc::new(pool)),
+        Err(err) => {
+            error!("Failed to connect to Redis, serving from the database until it is reachable: {}", err);
+            redis.breaker.lock().unwrap().trip(err.to_string());
+        }
+    }
+    Ok(Arc::new(redis))
+}
 
-    match result {
-        Ok(RespValue::SimpleString(ref s)) if s == "OK" => {
+impl Breaker {
+    fn is_open(&self) -> bool {
+        self.down_since.is_some()
+    }
 
-            let redis_client_arc = Arc::new(Redis{ redis_client: paired_conn });
-            Ok(redis_client_arc)
-        },
-        _ => Err(ApiError::RedisError("Authentication failed".into())),
+    fn trip(&mut self, error: String) {
+        self.down_since.get_or_insert_with(Utc::now);
+        self.last_error = Some(error);
     }
 }
 
 impl Redis {
 
+    // The breaker state and a PING of every pooled connection
+    pub async fn health(&self) -> CacheHealth {
+        let pool = self.available_pool();
+        let nodes = match &pool {
+            Some(pool) => pool.health().await,
+            None => Vec::new(),
+        };
+        let breaker = self.breaker.lock().unwrap();
+        CacheHealth {
+            status: if breaker.is_open() { "down" } else { "up" }.to_string(),
+            consecutive_failures: breaker.consecutive_failures,
+            down_since: breaker.down_since.map(|since| since.to_rfc3339()),
+            last_error: breaker.last_error.clone(),
+            local_entries: self.local.lock().unwrap().len(),
+            mode: pool.map(|pool| pool.mode()),
+            nodes,
+        }
+    }
+
+    // Called periodically, reconnects while the breaker is open and replays the deletes missed meanwhile.
+    // Connecting again also rediscovers the sentinel master or the cluster slots.
+    pub async fn reconnect_if_down(&self) {
+        if !self.breaker.lock().unwrap().is_open() {
+            return;
+        }
+
+        match RedisPool::connect().await {
+            Ok(pool) => {
+                let pending: Vec<String> = self.pending_dels.lock().unwrap().drain().collect();
+                for key in &pending {
+                    send_traced_and_forget(&pool, del_command(key));
+                    send_traced_and_forget(&pool, publish_command(key));
+                }
+                // Invalidations published by the other instances meanwhile were missed
+                self.local.lock().unwrap().clear();
+                *self.pool.write().unwrap() = Some(Arc::new(pool));
+                *self.breaker.lock().unwrap() = Breaker::default();
+                info!("Reconnected to Redis, invalidated {} keys changed while it was down", pending.len());
+            }
+            Err(err) => {
+                debug!("Redis is still unreachable: {}", err);
+                self.breaker.lock().unwrap().last_error = Some(err.to_string());
+            }
+        }
+    }
+
+    // A round trip through the breaker, for readiness checks
+    pub async fn ping(&self) -> Result<(), ApiError> {
+        match self.send(vec![RespValue::BulkString(b"PING".to_vec())]).await? {
+            RespValue::SimpleString(ref pong) if pong == "PONG" => Ok(()),
+            reply => Err(ApiError::RedisError(format!("Unexpected reply to PING: {:?}", reply))),
+        }
+    }
+
+    // Runs for the lifetime of the server, dropping the local copies of the keys other instances invalidate
+    pub async fn listen_for_invalidations(&self) {
+        let channel = invalidation_channel();
+        loop {
+            match self.subscribe(&channel).await {
+                Ok(mut messages) => {
+                    info!("Listening for cache invalidations on {}", channel);
+                    while let Some(message) = messages.next().await {
+                        match message {
+                            Ok(RespValue::BulkString(key)) => self.local.lock().unwrap().invalidate(&String::from_utf8_lossy(&key)),
+                            Ok(message) => debug!("Ignoring unexpected invalidation message: {:?}", message),
+                            Err(err) => {
+                                warn!("Lost the cache invalidation subscription: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(err) => debug!("Failed to subscribe to cache invalidations: {}", err),
+            }
+            // Whatever was published while unsubscribed is lost
+            self.local.lock().unwrap().clear();
+            actix_rt::time::sleep(Duration::from_secs(CONFIG.redis_reconnect_interval_secs)).await;
+        }
+    }
+
+    async fn subscribe(&self, channel: &str) -> Result<PubsubStream, ApiError> {
+        let Some(pool) = self.available_pool() else {
+            return Err(ApiError::RedisError("Redis is unavailable".into()));
+        };
+        let (host, port) = pool.primary_address();
+        let builder = node_connection_builder(host, port)?;
+        let timeout = Duration::from_millis(CONFIG.redis_command_timeout_ms) * 4;
+        let connection = actix_rt::time::timeout(timeout, builder.pubsub_connect()).await
+            .map_err(|_| ApiError::RedisError("Timed out connecting to Redis".into()))??;
+        Ok(connection.subscribe(channel).await?)
+    }
+
+    // The connections to use, unless the breaker is open
+    fn available_pool(&self) -> Option<Arc<RedisPool>> {
+        if self.breaker.lock().unwrap().is_open() {
+            return None;
+        }
+        self.pool.read().unwrap().clone()
+    }
+
+    async fn send(&self, command: Vec<RespValue>) -> Result<RespValue, ApiError> {
+        let span = command_span(&command);
+        let result = self.send_through_breaker(command).instrument(span.clone()).await;
+        record_outcome(&span, &result);
+        result
+    }
+
+    async fn send_through_breaker(&self, command: Vec<RespValue>) -> Result<RespValue, ApiError> {
+        let Some(pool) = self.available_pool() else {
+            return Err(ApiError::RedisError("Redis is unavailable".into()));
+        };
+
+        let timeout = Duration::from_millis(CONFIG.redis_command_timeout_ms);
+        let result = match actix_rt::time::timeout(timeout, pool.send(command)).await {
+            Ok(result) => result,
+            Err(_) => Err(RedisAsyncError::Internal("Redis command timed out".into())),
+        };
+
+        let mut breaker = self.breaker.lock().unwrap();
+        match result {
+            Ok(value) => {
+                breaker.consecutive_failures = 0;
+                Ok(value)
+            }
+            // Connecting again picks up the new master or slot owners
+            Err(RedisAsyncError::Remote(message)) if is_topology_change(&message) => {
+                record_redis_error("topology_change");
+                error!("Redis topology changed, serving from the database until reconnected: {}", message);
+                breaker.trip(message.clone());
+                Err(ApiError::RedisError(message))
+            }
+            // Other errors returned by Redis itself say nothing about its health
+            Err(RedisAsyncError::Remote(message)) => {
+                record_redis_error("remote");
+                Err(ApiError::RedisError(message))
+            }
+            Err(err) => {
+                record_redis_error("connection");
+                breaker.consecutive_failures += 1;
+                if breaker.consecutive_failures >= CONFIG.redis_breaker_failure_threshold && !breaker.is_open() {
+                    error!("Redis failed {} commands in a row, serving from the database: {}", breaker.consecutive_failures, err);
+                    breaker.trip(err.to_string());
+                }
+                Err(err.into())
+            }
+        }
+    }
+
+    fn send_and_forget(&self, command: Vec<RespValue>) {
+        if let Some(pool) = self.available_pool() {
+            send_traced_and_forget(&pool, command);
+        }
+    }
+
+    // Drops the key here, in Redis and in the local tier of every other instance
     #[instrument]
-    pub async fn del(&self, key: String) -> Result<(), ApiError> {
-        let command = vec![
-            RespValue::BulkString(b"DEL".to_vec()),
-            RespValue::BulkString(key.as_bytes().to_vec()),
-        ];
+    pub async fn del(&self, key: String) {
+        self.local.lock().unwrap().invalidate(&key);
+        let Some(pool) = self.available_pool() else {
+            self.pending_dels.lock().unwrap().insert(key);
+            return;
+        };
 
-        self.redis_client.send_and_forget(RespValue::Array(command));
-        Ok(())
+        send_traced_and_forget(&pool, del_command(&key));
+        send_traced_and_forget(&pool, publish_command(&key));
     }
 
+    // Cached values live in hashes so a single `del` drops every variant (page, sort, filter) of a key.
+    // The value expires after the TTL of the key's family, and storing it releases the fill lock.
     #[instrument]
-    pub async fn set<T: Serialize + Debug>(&self, key: String, value: T) -> Result<(), ApiError> {
-        let serialized_value = serde_json::to_string(&value)?;
+    pub async fn hset<T: Serialize + Debug>(&self, key: String, field: String, value: T) -> Result<(), ApiError> {
+        let ttl = family_ttl(key_family(&key));
+        self.hset_with_ttl(key, field, value, ttl).await
+    }
 
-        let command = vec![
-            RespValue::BulkString(b"SET".to_vec()),
+    async fn hset_with_ttl<T: Serialize + Debug>(&self, key: String, field: String, value: T, ttl: u64) -> Result<(), ApiError> {
+        let compute_ms = self.fills.lock().unwrap()
+            .remove(&fill_id(&key, &field))
+            .map_or(0, |started| started.elapsed().as_millis() as u64);
+        let entry = CacheEntry {
+            value,
+            expires_at: Utc::now().timestamp_millis() + (ttl * 1000) as i64,
+            compute_ms,
+        };
+        let serialized_value = serde_json::to_string(&entry)?;
+        let key = namespaced_key(&key);
+
+        self.send_and_forget(vec![
+            RespValue::BulkString(b"HSET".to_vec()),
+            RespValue::BulkString(key.as_bytes().to_vec()),
+            RespValue::BulkString(field.as_bytes().to_vec()),
+            RespValue::BulkString(serialized_value.into_bytes()),
+        ]);
+
+        // Expired fields are overwritten on the next fill, the key itself goes once nothing refills it
+        self.send_and_forget(vec![
+            RespValue::BulkString(b"EXPIRE".to_vec()),
             RespValue::BulkString(key.as_bytes().to_vec()),
-            RespValue::BulkString(serialized_value.as_bytes().to_vec()),
-        ];
+            RespValue::BulkString(ttl.to_string().into_bytes()),
+        ]);
 
-        self.redis_client.send_and_forget(RespValue::Array(command));
+        self.send_and_forget(vec![
+            RespValue::BulkString(b"DEL".to_vec()),
+            RespValue::BulkString(lock_key(&key, &field).into_bytes()),
+        ]);
         Ok(())
     }
 
+    // A miss (`RedisNil`) makes the caller responsible for computing and `hset`-ing the value. Only one
+    // caller across instances gets a miss at a time: the others wait up to `cache_lock_timeout_ms` for
+    // the value, then compute it themselves. Fresh values may also be reported as a miss to one caller
+    // ahead of their expiry, so they are refreshed before everyone misses at once.
     #[instrument]
-    pub async fn get<T>(&self, key: String) -> Result<T, ApiError>
-    where T: for<'de> Deserialize<'de> + FromResp + Unpin + Debug {
+    pub async fn hget<T>(&self, key: String, field: String) -> Result<T, ApiError>
+    where T: for<'de> Deserialize<'de> + Unpin + Debug {
+        let result = self.lookup(&key, &field).await;
+        let outcome = match &result {
+            Ok(_) => "hit",
+            Err(ApiError::RedisNil) => "miss",
+            Err(_) => "error",
+        };
+        record_cache_lookup(key_family(&key), outcome);
+        result
+    }
 
-        let command = vec![
-            RespValue::BulkString(b"GET".to_vec()),
-            RespValue::BulkString(key.as_bytes().to_vec()),
-        ];
+    async fn lookup<T>(&self, key: &str, field: &str) -> Result<T, ApiError>
+    where T: for<'de> Deserialize<'de> + Unpin + Debug {
+        if let Some(entry) = self.read_entry::<T>(key, field).await? {
+            let random = 1.0 - rand::random::<f64>();
+            let now = Utc::now().timestamp_millis();
+            if !should_refresh_early(now, entry.expires_at, entry.compute_ms, CONFIG.cache_early_refresh_beta, random)
+                || !self.try_lock_fill(key, field).await {
+                return Ok(entry.value);
+            }
+            return Err(ApiError::RedisNil);
+        }
+
+        if self.try_lock_fill(key, field).await {
+            return Err(ApiError::RedisNil);
+        }
+
+        let deadline = Instant::now() + Duration::from_millis(CONFIG.cache_lock_timeout_ms);
+        while Instant::now() < deadline {
+            actix_rt::time::sleep(Duration::from_millis(LOCK_POLL_INTERVAL_MS)).await;
+            if let Some(entry) = self.read_entry::<T>(key, field).await? {
+                return Ok(entry.value);
+            }
+        }
+        self.start_fill(key, field);
+        Err(ApiError::RedisNil)
+    }
+
+    // Serve the cached value or run `loader` and cache its result. Redis failures are logged and the
+    // value is loaded from the database instead, so an unavailable cache only makes requests slower.
+    #[instrument(skip(loader))]
+    pub async fn read_through<T: Cacheable>(&self, cache_key: CacheKey, loader: impl Future<Output = Result<T, ApiError>>) -> Result<T, ApiError> {
+        // Invalidations only reach this instance through Redis, so the local tier is bypassed while it is down
+        let family = key_family(&cache_key.key);
+        if self.available_pool().is_none() {
+            record_cache_lookup(family, "bypassed");
+            return loader.await;
+        }
+        let ttl = cache_key.ttl_secs.unwrap_or_else(|| family_ttl(family));
+        if let Some(value) = self.local_get(&cache_key) {
+            record_cache_lookup(family, "local_hit");
+            return Ok(value);
+        }
 
-        let result = self.redis_client.send::<RespValue>(RespValue::Array(command)).await.map_err(ApiError::from);
+        match self.hget::<T>(cache_key.key.clone(), cache_key.field.clone()).await {
+            Ok(value) => {
+                self.local_insert(&cache_key, &value, ttl);
+                return Ok(value);
+            }
+            Err(ApiError::RedisNil) => {}
+            Err(err) => warn!("Failed to read {} from the cache, loading it instead: {}", cache_key.key, err),
+        }
+
+        let value = loader.await?;
+        self.local_insert(&cache_key, &value, ttl);
+        if let Err(err) = self.hset_with_ttl(cache_key.key.clone(), cache_key.field, &value, ttl).await {
+            warn!("Failed to write {} to the cache: {}", cache_key.key, err);
+        }
+        Ok(value)
+    }
+
+    fn local_get<T: Cacheable>(&self, cache_key: &CacheKey) -> Option<T> {
+        let value = self.local.lock().unwrap().get(&cache_key.key, &cache_key.field, Instant::now())?;
+        serde_json::from_str(&value).ok()
+    }
+
+    fn local_insert<T: Cacheable>(&self, cache_key: &CacheKey, value: &T, ttl: u64) {
+        let Ok(serialized_value) = serde_json::to_string(value) else { return };
+        let expires_at = Instant::now() + Duration::from_secs(ttl.min(CONFIG.local_cache_ttl_secs));
+        self.local.lock().unwrap().insert(&cache_key.key, &cache_key.field, serialized_value, expires_at);
+    }
+
+    // The entry of a field, or `None` when it is missing or expired
+    async fn read_entry<T>(&self, key: &str, field: &str) -> Result<Option<CacheEntry<T>>, ApiError>
+    where T: for<'de> Deserialize<'de> + Unpin + Debug {
+        let result = self.send(vec![
+            RespValue::BulkString(b"HGET".to_vec()),
+            RespValue::BulkString(namespaced_key(key).into_bytes()),
+            RespValue::BulkString(field.as_bytes().to_vec()),
+        ]).await;
 
         match result {
             Ok(RespValue::BulkString(raw_data)) => {
-                let value: T = serde_json::from_slice(&raw_data)?;
-                Ok(value)
+                let entry: CacheEntry<T> = serde_json::from_slice(&raw_data)?;
+                Ok(Some(entry).filter(|entry| entry.expires_at > Utc::now().timestamp_millis()))
             }
-            Ok(RespValue::Nil) => Err(ApiError::RedisNil),
+            Ok(RespValue::Nil) => Ok(None),
             _ => Err(ApiError::RedisError("Failed to get value".into())),
         }
+    }
+
+    // Take the fill lock of a field. A failure to reach Redis counts as taking it, so the caller computes the value.
+    async fn try_lock_fill(&self, key: &str, field: &str) -> bool {
+        let result = self.send(vec![
+            RespValue::BulkString(b"SET".to_vec()),
+            RespValue::BulkString(lock_key(&namespaced_key(key), field).into_bytes()),
+            RespValue::BulkString(b"1".to_vec()),
+            RespValue::BulkString(b"NX".to_vec()),
+            RespValue::BulkString(b"PX".to_vec()),
+            RespValue::BulkString(CONFIG.cache_lock_timeout_ms.to_string().into_bytes()),
+        ]).await;
+
+        let locked = !matches!(result, Ok(RespValue::Nil));
+        if locked {
+            self.start_fill(key, field);
+        }
+        locked
+    }
 
+    fn start_fill(&self, key: &str, field: &str) {
+        let mut fills = self.fills.lock().unwrap();
+        // Fills whose caller never stored a value are dropped once their lock is long gone
+        let lock_timeout = Duration::from_millis(CONFIG.cache_lock_timeout_ms);
+        fills.retain(|_, started| started.elapsed() < lock_timeout * 10);
+        fills.insert(fill_id(key, field), Instant::now());
     }
-}
\ No newline at end of file
+}
+
+// Client span of a command, named after it but without its arguments since keys carry user ids
+fn command_span(command: &[RespValue]) -> Span {
+    let name = match command.first() {
+        Some(RespValue::BulkString(name)) => String::from_utf8_lossy(name).into_owned(),
+        _ => "UNKNOWN".to_string(),
+    };
+    info_span!(
+        "redis.command",
+        otel.name = %format_args!("redis {}", name),
+        otel.kind = "client",
+        db.system = "redis",
+        db.operation = %name,
+        otel.status_code = Empty,
+        error.message = Empty,
+    )
+}
+
+fn send_traced_and_forget(pool: &RedisPool, command: Vec<RespValue>) {
+    let _span = command_span(&command).entered();
+    pool.send_and_forget(command);
+}
+
+fn del_command(key: &str) -> Vec<RespValue> {
+    vec![
+        RespValue::BulkString(b"DEL".to_vec()),
+        RespValue::BulkString(namespaced_key(key).into_bytes()),
+    ]
+}
+
+fn publish_command(key: &str) -> Vec<RespValue> {
+    vec![
+        RespValue::BulkString(b"PUBLISH".to_vec()),
+        RespValue::BulkString(invalidation_channel().into_bytes()),
+        RespValue::BulkString(key.as_bytes().to_vec()),
+    ]
+}
+
+fn fill_id(key: &str, field: &str) -> String {
+    format!("{}::{}", key, field)
+}
+
+fn lock_key(namespaced_key: &str, field: &str) -> String {
+    format!("{}::lock::{}", namespaced_key, field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_cache_ttls_and_early_refresh() {
+        let ttls = parse_family_ttls("all_watchlist=600, candles = 86400,broken,screener=soon");
+        assert_eq!(ttls.len(), 2);
+        assert_eq!(ttls["candles"], 86400);
+        assert_eq!(key_family("all_watchlist::42"), "all_watchlist");
+        assert_eq!(key_family("screener"), "screener");
+
+        // Far from the expiry only a very unlucky draw refreshes, at the expiry every draw does
+        assert!(!should_refresh_early(0, 60_000, 100, 1.0, 0.5));
+        assert!(should_refresh_early(0, 60_000, 100, 1.0, 1e-300));
+        assert!(should_refresh_early(60_000, 60_000, 100, 1.0, 1.0));
+        // A beta of zero turns it off
+        assert!(!should_refresh_early(59_999, 60_000, 100, 0.0, 1e-300));
+    }
+
+    #[test]
+    fn test_unit_local_cache_eviction_and_invalidation() {
+        let now = Instant::now();
+        let later = now + Duration::from_secs(30);
+        let mut cache = LocalCache::new(3);
+        cache.insert("all_watchlist::1", "a", "1a".into(), later);
+        cache.insert("all_watchlist::1", "b", "1b".into(), later);
+        cache.insert("all_watchlist::2", "a", "2a".into(), later);
+
+        // Reading `1a` makes `1b` the least recently used, so it goes first
+        assert_eq!(cache.get("all_watchlist::1", "a", now).as_deref(), Some("1a"));
+        cache.insert("candles::3", "a", "3a".into(), later);
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get("all_watchlist::1", "b", now), None);
+
+        // Every field of a key is dropped at once
+        cache.insert("all_watchlist::1", "b", "1b".into(), later);
+        cache.invalidate("all_watchlist::1");
+        assert_eq!(cache.get("all_watchlist::1", "a", now), None);
+        assert_eq!(cache.len(), 1);
+
+        assert_eq!(cache.get("candles::3", "a", later), None);
+        assert_eq!(cache.len(), 0);
+
+        let mut disabled = LocalCache::new(0);
+        disabled.insert("all_watchlist::1", "a", "1a".into(), later);
+        assert_eq!(disabled.get("all_watchlist::1", "a", now), None);
+    }
+}
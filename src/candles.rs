@@ -0,0 +1,210 @@
+use actix_web::{HttpMessage, HttpRequest};
+use actix_web::web::{Data, Json, Path, Query};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use chrono_tz::Tz;
+use sqlx::{Arguments, Row};
+use sqlx::postgres::PgArguments;
+use tracing::instrument;
+use crate::cache::CacheKey;
+use crate::currency::{conversion_rate, convert_price, parse_currency, BASE_CURRENCY};
+use crate::errors::ApiError;
+use crate::errors::ApiError::BadRequest;
+use crate::helpers::{format_datetime, respond_json, Timestamp, TimestampFormat};
+use crate::middleware_custom::Claims;
+use crate::preferences::load_user_preferences;
+use crate::server::AppState;
+
+const DEFAULT_CANDLES_LIMIT: i64 = 500;
+const MAX_CANDLES_LIMIT: i64 = 2000;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandleInterval {
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl CandleInterval {
+    // Value stored in `asset_candles.timeframe`
+    pub fn code(&self) -> &'static str {
+        match self {
+            CandleInterval::OneHour => "1h",
+            CandleInterval::OneDay => "1d",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code.trim() {
+            "1h" => Some(CandleInterval::OneHour),
+            "1d" => Some(CandleInterval::OneDay),
+            _ => None,
+        }
+    }
+
+    // Period name used by the CoinMarketCap OHLCV API
+    pub fn cmc_period(&self) -> &'static str {
+        match self {
+            CandleInterval::OneHour => "hourly",
+            CandleInterval::OneDay => "daily",
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        match self {
+            CandleInterval::OneHour => Duration::hours(1),
+            CandleInterval::OneDay => Duration::days(1),
+        }
+    }
+
+    // End of the candle `now` falls in, so requests without an `end` share one cache field per candle
+    fn period_end(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now.duration_trunc(self.duration()).unwrap_or(now) + self.duration()
+    }
+
+    fn default_range(&self) -> Duration {
+        match self {
+            CandleInterval::OneHour => Duration::days(7),
+            CandleInterval::OneDay => Duration::days(90),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Candle {
+    pub open_time: Option<Timestamp>,
+    pub close_time: Option<Timestamp>,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: Option<f64>,
+    pub market_cap: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CandlesResponse {
+    asset_id: i32,
+    interval: CandleInterval,
+    #[serde(default)]
+    currency: String,
+    candles: Vec<Candle>,
+}
+
+impl CandlesResponse {
+    // Candles are stored and cached in USD and converted on the way out
+    fn convert(mut self, currency: String, rate: f64) -> Self {
+        for candle in self.candles.iter_mut() {
+            candle.open *= rate;
+            candle.high *= rate;
+            candle.low *= rate;
+            candle.close *= rate;
+            candle.volume = convert_price(candle.volume, rate);
+            candle.market_cap = convert_price(candle.market_cap, rate);
+        }
+        self.currency = currency;
+        self
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CandlesQuery {
+    interval: Option<CandleInterval>,
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+    convert: Option<String>,
+    time_format: Option<TimestampFormat>,
+}
+
+// Candles of an asset between `start` (inclusive) and `end` (exclusive), oldest first. When more
+// than `limit` candles fall in the range the most recent ones are returned.
+#[instrument]
+pub async fn retrieve_candles(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<i32>,
+    query: Query<CandlesQuery>,
+) -> Result<Json<CandlesResponse>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let asset_id = path.into_inner();
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    let currency = parse_currency(query.convert.as_deref(), &preferences.quote_currency)?;
+    let mut query = query.into_inner();
+    query.convert = None;
+    let interval = query.interval.unwrap_or(CandleInterval::OneDay);
+    query.interval = Some(interval);
+    query.end = Some(query.end.unwrap_or_else(|| interval.period_end(Utc::now())));
+
+    let cache_key = format!("candles::{}", asset_id);
+    let cache_field = serde_json::to_string(&query)?;
+
+    let response = state.redis_client.read_through(CacheKey::new(cache_key, cache_field), async {
+        let end = query.end.unwrap_or_else(Utc::now);
+        let start = query.start.unwrap_or(end - interval.default_range());
+        let limit = query.limit.unwrap_or(DEFAULT_CANDLES_LIMIT);
+        if start >= end {
+            return Err(BadRequest("start must be before end".into()));
+        }
+        if !(1..=MAX_CANDLES_LIMIT).contains(&limit) {
+            return Err(BadRequest(format!("limit must be between 1 and {}", MAX_CANDLES_LIMIT)));
+        }
+
+        let mut args = PgArguments::default();
+        args.add(asset_id);
+        state.db
+            .fetch_optional("SELECT id FROM assets WHERE id = $1", args)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+
+        let mut args = PgArguments::default();
+        args.add(asset_id);
+        args.add(interval.code());
+        args.add(start);
+        args.add(end);
+        args.add(limit);
+        let records = state.db
+            .fetch_all(r#"SELECT open_time, close_time, open, high, low, close, volume, market_cap
+                          FROM asset_candles
+                          WHERE asset_id = $1 AND timeframe = $2 AND open_time >= $3 AND open_time < $4
+                          ORDER BY open_time DESC
+                          LIMIT $5"#, args)
+            .await?;
+
+        // Candles are shared between users, so their times stay in UTC
+        let time_format = query.time_format.unwrap_or_default();
+        let candles = records
+            .iter()
+            .rev()
+            .map(|record| Candle {
+                open_time: format_datetime(record.get("open_time"), Tz::UTC, time_format),
+                close_time: format_datetime(record.get("close_time"), Tz::UTC, time_format),
+                open: record.get("open"),
+                high: record.get("high"),
+                low: record.get("low"),
+                close: record.get("close"),
+                volume: record.get("volume"),
+                market_cap: record.get("market_cap"),
+            })
+            .collect();
+        let response = CandlesResponse { asset_id, interval, currency: BASE_CURRENCY.to_string(), candles };
+
+        Ok(response)
+    })
+    .await?;
+
+    let rate = conversion_rate(&state.redis_client, &currency).await?;
+    respond_json(response.convert(currency, rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_candle_period_end() {
+        let now = DateTime::parse_from_rfc3339("2026-10-19T13:45:12Z").unwrap().with_timezone(&Utc);
+        assert_eq!(CandleInterval::OneHour.period_end(now).to_rfc3339(), "2026-10-19T14:00:00+00:00");
+        assert_eq!(CandleInterval::OneDay.period_end(now).to_rfc3339(), "2026-10-20T00:00:00+00:00");
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use actix_web::web::{Data, Json};
+use sqlx::{Arguments, Row};
+use sqlx::postgres::PgArguments;
+use tracing::instrument;
+use crate::cache::CacheKey;
+use crate::database::Database;
+use crate::errors::ApiError;
+use crate::errors::ApiError::BadRequest;
+use crate::helpers::respond_json;
+use crate::server::AppState;
+
+const CACHE_KEY: &str = "categories";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AssetCategory {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CategoryResponse {
+    id: String,
+    name: String,
+    title: Option<String>,
+    description: Option<String>,
+    num_tokens: Option<i32>,
+    // Assets of the category that are in the catalog
+    asset_count: i64,
+}
+
+// JSON array of `{id, name}` for the asset in `asset_column`, selected as `categories`
+pub fn categories_json(asset_column: &str) -> String {
+    format!(
+        "COALESCE((SELECT json_agg(json_build_object('id', c.id, 'name', c.name) ORDER BY c.name) \
+         FROM asset_categories ac JOIN categories c ON c.id = ac.category_id WHERE ac.asset_id = {}), '[]'::json)",
+        asset_column
+    )
+}
+
+// Clause for `SqlConditions::push`, matching a category by id or case-insensitive name
+pub fn category_filter(asset_column: &str) -> String {
+    format!(
+        "EXISTS (SELECT 1 FROM asset_categories ac JOIN categories c ON c.id = ac.category_id \
+         WHERE ac.asset_id = {} AND (c.id = $? OR LOWER(c.name) = LOWER($?)))",
+        asset_column
+    )
+}
+
+// Resolve a category by id or name, preferring an exact id match
+pub async fn find_category(db: &Arc<dyn Database>, category: &str) -> Result<AssetCategory, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(category.trim());
+    let record = db
+        .fetch_optional("SELECT id, name FROM categories WHERE id = $1 OR LOWER(name) = LOWER($1) ORDER BY id = $1 DESC LIMIT 1", args)
+        .await?
+        .ok_or_else(|| BadRequest(format!("Category not found: {}", category)))?;
+
+    Ok(AssetCategory { id: record.get("id"), name: record.get("name") })
+}
+
+#[instrument]
+pub async fn retrieve_categories(state: Data<AppState>) -> Result<Json<Vec<CategoryResponse>>, ApiError> {
+    let cache_field = "all".to_string();
+    let categories = state.redis_client.read_through(CacheKey::new(CACHE_KEY.to_string(), cache_field), async {
+        let records = state.db
+            .fetch_all(r#"SELECT c.id, c.name, c.title, c.description, c.num_tokens, COUNT(ac.asset_id) AS asset_count
+                          FROM categories c LEFT JOIN asset_categories ac ON ac.category_id = c.id
+                          GROUP BY c.id ORDER BY LOWER(c.name), c.id"#, PgArguments::default())
+            .await?;
+
+        let categories: Vec<CategoryResponse> = records.iter()
+            .map(|record| CategoryResponse {
+                id: record.get("id"),
+                name: record.get("name"),
+                title: record.get("title"),
+                description: record.get("description"),
+                num_tokens: record.get("num_tokens"),
+                asset_count: record.get("asset_count"),
+            })
+            .collect();
+
+        Ok(categories)
+    })
+    .await?;
+
+    respond_json(categories)
+}
@@ -1,19 +1,359 @@
 use dotenv::dotenv;
+use crate::redis_pool::RedisMode;
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Config {
     pub database_url: String,
     pub server: String,
-    pub app_version: String,
+    // Read straight from the environment by the tracing `EnvFilter`, kept here so a missing value fails at startup
+    #[allow(dead_code)]
     pub log_level: String,
     pub redis_host: String,
     pub redis_port: u16,
     pub redis_password: String,
+    // `host:port` list of the event broker, checked by readiness when set
+    #[serde(default)]
+    pub kafka_brokers: String,
+    // How long each readiness check may take before its dependency counts as down
+    #[serde(default = "default_health_check_timeout_ms")]
+    pub health_check_timeout_ms: u64,
+    // OTLP/gRPC collector spans are exported to, e.g. `http://localhost:4317`, tracing is off when empty
+    #[serde(default)]
+    pub otel_exporter_otlp_endpoint: String,
+    #[serde(default = "default_otel_exporter_otlp_timeout_ms")]
+    pub otel_exporter_otlp_timeout_ms: u64,
+    #[serde(default = "default_otel_service_name")]
+    pub otel_service_name: String,
+    // Share of the traces started here that are exported, requests with a sampled parent always are
+    #[serde(default = "default_otel_traces_sampler_ratio")]
+    pub otel_traces_sampler_ratio: f64,
+    // Bearer token Prometheus must send to scrape `/metrics`, open when empty
+    #[serde(default)]
+    pub metrics_token: String,
+    // ACL user, the `default` user when empty
+    #[serde(default)]
+    pub redis_username: String,
+    #[serde(default)]
+    pub redis_tls: bool,
+    #[serde(default)]
+    pub redis_mode: RedisMode,
+    // `host:port` list of the sentinels, and of the cluster nodes to discover the cluster from
+    #[serde(default)]
+    pub redis_sentinels: String,
+    #[serde(default = "default_redis_sentinel_master")]
+    pub redis_sentinel_master: String,
+    #[serde(default)]
+    pub redis_sentinel_password: String,
+    #[serde(default)]
+    pub redis_cluster_nodes: String,
+    // Connections per node
+    #[serde(default = "default_redis_pool_size")]
+    pub redis_pool_size: usize,
+    // Namespace of every key, `cache_key_version` can be bumped on deploy to start from an empty cache
+    #[serde(default = "default_redis_key_prefix")]
+    pub redis_key_prefix: String,
+    #[serde(default)]
+    pub cache_key_version: String,
+    // TTL of the cache families without a dedicated setting, `cache_family_ttls` overrides any family
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    #[serde(default)]
+    pub cache_family_ttls: String,
+    // How long a cache miss is left to one request before the others waiting on it compute the value too
+    #[serde(default = "default_cache_lock_timeout_ms")]
+    pub cache_lock_timeout_ms: u64,
+    // Eagerness of the probabilistic early refresh, 0 disables it
+    #[serde(default = "default_cache_early_refresh_beta")]
+    pub cache_early_refresh_beta: f64,
+    // Consecutive failed commands after which Redis is skipped until a reconnect succeeds
+    #[serde(default = "default_redis_breaker_failure_threshold")]
+    pub redis_breaker_failure_threshold: u32,
+    #[serde(default = "default_redis_reconnect_interval_secs")]
+    pub redis_reconnect_interval_secs: u64,
+    #[serde(default = "default_redis_command_timeout_ms")]
+    pub redis_command_timeout_ms: u64,
+    // In-process tier in front of Redis, a capacity of 0 turns it off
+    #[serde(default = "default_local_cache_capacity")]
+    pub local_cache_capacity: usize,
+    #[serde(default = "default_local_cache_ttl_secs")]
+    pub local_cache_ttl_secs: u64,
     pub cmc_api_key: String,
     pub cmc_token_id_endpoint: String,
     pub is_feed_assets_data_enabled: bool,
     pub jwt_secret: String,
     pub log_file_location: String,
+    #[serde(default = "default_cmc_ohlcv_historical_endpoint")]
+    pub cmc_ohlcv_historical_endpoint: String,
+    #[serde(default)]
+    pub is_candles_backfill_enabled: bool,
+    // Comma separated candle intervals to backfill, e.g. `1d,1h`
+    #[serde(default = "default_candles_backfill_intervals")]
+    pub candles_backfill_intervals: String,
+    #[serde(default = "default_candles_backfill_days")]
+    pub candles_backfill_days: i64,
+    #[serde(default = "default_candles_backfill_interval_secs")]
+    pub candles_backfill_interval_secs: u64,
+    #[serde(default = "default_watchlist_group_retention_days")]
+    pub watchlist_group_retention_days: i32,
+    #[serde(default = "default_watchlist_group_purge_interval_secs")]
+    pub watchlist_group_purge_interval_secs: u64,
+    // Group analytics are also refreshed by new candles, so they expire instead of waiting for a mutation
+    #[serde(default = "default_analytics_cache_ttl_secs")]
+    pub analytics_cache_ttl_secs: u64,
+    #[serde(default = "default_portfolio_cache_ttl_secs")]
+    pub portfolio_cache_ttl_secs: u64,
+    #[serde(default = "default_cmc_price_conversion_endpoint")]
+    pub cmc_price_conversion_endpoint: String,
+    #[serde(default = "default_cmc_listings_latest_endpoint")]
+    pub cmc_listings_latest_endpoint: String,
+    #[serde(default = "default_cmc_categories_endpoint")]
+    pub cmc_categories_endpoint: String,
+    #[serde(default = "default_cmc_category_endpoint")]
+    pub cmc_category_endpoint: String,
+    // Assets requested per category, the most CoinMarketCap returns in one page is 1000
+    #[serde(default = "default_category_assets_limit")]
+    pub category_assets_limit: u32,
+    #[serde(default)]
+    pub is_asset_quotes_sync_enabled: bool,
+    // Readiness reports the instance degraded once the last quotes sync is older, 0 only reports the age
+    #[serde(default = "default_health_max_asset_sync_age_secs")]
+    pub health_max_asset_sync_age_secs: i64,
+    #[serde(default = "default_asset_quotes_sync_interval_secs")]
+    pub asset_quotes_sync_interval_secs: u64,
+    // Number of assets requested from the listings endpoint, by market cap
+    #[serde(default = "default_asset_quotes_sync_limit")]
+    pub asset_quotes_sync_limit: u32,
+    #[serde(default = "default_screener_cache_ttl_secs")]
+    pub screener_cache_ttl_secs: u64,
+    #[serde(default)]
+    pub is_smart_groups_refresh_enabled: bool,
+    // Period of the scheduled refresh, and the age after which a smart group is re-evaluated on read
+    #[serde(default = "default_smart_groups_refresh_interval_secs")]
+    pub smart_groups_refresh_interval_secs: u64,
+    // Comma separated currencies prices can be converted to, USD is always available
+    #[serde(default = "default_quote_currencies")]
+    pub quote_currencies: String,
+    #[serde(default = "default_conversion_rates_ttl_secs")]
+    pub conversion_rates_ttl_secs: u64,
+    // Plain http:// webhook URLs and hosts on loopback or private addresses are refused unless this is set,
+    // e.g. to point them at a local stand-in
+    #[serde(default)]
+    pub webhook_allow_insecure_targets: bool,
+    #[serde(default = "default_webhook_timeout_secs")]
+    pub webhook_timeout_secs: u64,
+    // Attempts per delivery, retried with exponential backoff from `notification_retry_base_delay_ms`
+    #[serde(default = "default_notification_max_attempts")]
+    pub notification_max_attempts: u32,
+    #[serde(default = "default_notification_retry_base_delay_ms")]
+    pub notification_retry_base_delay_ms: u64,
+    #[serde(default = "default_smtp_host")]
+    pub smtp_host: String,
+    #[serde(default = "default_smtp_port")]
+    pub smtp_port: u16,
+    #[serde(default)]
+    pub smtp_username: Option<String>,
+    #[serde(default)]
+    pub smtp_password: Option<String>,
+    #[serde(default)]
+    pub smtp_starttls: bool,
+    #[serde(default = "default_smtp_from")]
+    pub smtp_from: String,
+    #[serde(default = "default_telegram_api_base_url")]
+    pub telegram_api_base_url: String,
+    // The Telegram bot is disabled while the token is empty
+    #[serde(default)]
+    pub telegram_bot_token: String,
+    // Sent back by Telegram in `X-Telegram-Bot-Api-Secret-Token`, set it when registering the webhook
+    #[serde(default)]
+    pub telegram_webhook_secret: String,
+    #[serde(default = "default_discord_api_base_url")]
+    pub discord_api_base_url: String,
+    // The Discord bot is disabled while the token is empty
+    #[serde(default)]
+    pub discord_bot_token: String,
+    #[serde(default)]
+    pub discord_application_id: String,
+    // Hex encoded Ed25519 key used to verify interaction requests
+    #[serde(default)]
+    pub discord_public_key: String,
+    #[serde(default = "default_bot_link_code_ttl_secs")]
+    pub bot_link_code_ttl_secs: i64,
+}
+
+fn default_cmc_ohlcv_historical_endpoint() -> String {
+    "https://pro-api.coinmarketcap.com/v2/cryptocurrency/ohlcv/historical".into()
+}
+
+fn default_candles_backfill_intervals() -> String {
+    "1d".into()
+}
+
+fn default_candles_backfill_days() -> i64 {
+    365
+}
+
+fn default_candles_backfill_interval_secs() -> u64 {
+    3600
+}
+
+fn default_watchlist_group_retention_days() -> i32 {
+    30
+}
+
+fn default_watchlist_group_purge_interval_secs() -> u64 {
+    3600
+}
+
+fn default_otel_exporter_otlp_timeout_ms() -> u64 {
+    10000
+}
+
+fn default_otel_service_name() -> String {
+    env!("CARGO_PKG_NAME").into()
+}
+
+fn default_otel_traces_sampler_ratio() -> f64 {
+    1.0
+}
+
+fn default_redis_sentinel_master() -> String {
+    "mymaster".to_string()
+}
+
+fn default_redis_pool_size() -> usize {
+    4
+}
+
+fn default_redis_key_prefix() -> String {
+    "watchlist".into()
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_cache_lock_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_cache_early_refresh_beta() -> f64 {
+    1.0
+}
+
+fn default_redis_breaker_failure_threshold() -> u32 {
+    3
+}
+
+fn default_redis_reconnect_interval_secs() -> u64 {
+    5
+}
+
+fn default_redis_command_timeout_ms() -> u64 {
+    500
+}
+
+fn default_local_cache_capacity() -> usize {
+    10000
+}
+
+fn default_local_cache_ttl_secs() -> u64 {
+    30
+}
+
+fn default_analytics_cache_ttl_secs() -> u64 {
+    300
+}
+
+fn default_portfolio_cache_ttl_secs() -> u64 {
+    60
+}
+
+fn default_cmc_price_conversion_endpoint() -> String {
+    "https://pro-api.coinmarketcap.com/v2/tools/price-conversion".into()
+}
+
+fn default_cmc_listings_latest_endpoint() -> String {
+    "https://pro-api.coinmarketcap.com/v1/cryptocurrency/listings/latest".into()
+}
+
+fn default_cmc_categories_endpoint() -> String {
+    "https://pro-api.coinmarketcap.com/v1/cryptocurrency/categories".into()
+}
+
+fn default_cmc_category_endpoint() -> String {
+    "https://pro-api.coinmarketcap.com/v1/cryptocurrency/category".into()
+}
+
+fn default_category_assets_limit() -> u32 {
+    1000
+}
+
+fn default_health_max_asset_sync_age_secs() -> i64 {
+    1800
+}
+
+fn default_health_check_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_asset_quotes_sync_interval_secs() -> u64 {
+    300
+}
+
+fn default_asset_quotes_sync_limit() -> u32 {
+    5000
+}
+
+fn default_screener_cache_ttl_secs() -> u64 {
+    60
+}
+
+fn default_smart_groups_refresh_interval_secs() -> u64 {
+    300
+}
+
+fn default_quote_currencies() -> String {
+    "USD,EUR,IDR,BTC,ETH".into()
+}
+
+fn default_conversion_rates_ttl_secs() -> u64 {
+    300
+}
+
+fn default_webhook_timeout_secs() -> u64 {
+    10
+}
+
+fn default_notification_max_attempts() -> u32 {
+    4
+}
+
+fn default_notification_retry_base_delay_ms() -> u64 {
+    500
+}
+
+fn default_smtp_host() -> String {
+    "localhost".into()
+}
+
+fn default_smtp_port() -> u16 {
+    25
+}
+
+fn default_smtp_from() -> String {
+    "Crypto Watchlist <notifications@localhost>".into()
+}
+
+fn default_telegram_api_base_url() -> String {
+    "https://api.telegram.org".into()
+}
+
+fn default_discord_api_base_url() -> String {
+    "https://discord.com/api/v10".into()
+}
+
+fn default_bot_link_code_ttl_secs() -> i64 {
+    600
 }
 
 lazy_static! {
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use actix_web::web::{Data, Json};
+use log::{error, warn};
+use tracing::instrument;
+use crate::cache::Redis;
+use crate::config::CONFIG;
+use crate::data_provider::fetch_conversion_rates;
+use crate::errors::ApiError;
+use crate::errors::ApiError::{BadRequest, InternalServerError};
+use crate::helpers::respond_json;
+use crate::server::AppState;
+
+// Currency every stored price is in
+pub const BASE_CURRENCY: &str = "USD";
+
+const RATES_CACHE_KEY: &str = "conversion_rates";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CurrencyResponse {
+    code: String,
+    // Amount of the currency one US dollar buys
+    rate: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CurrenciesResponse {
+    base: String,
+    currencies: Vec<CurrencyResponse>,
+}
+
+pub fn supported_currencies() -> Vec<String> {
+    let mut currencies = vec![BASE_CURRENCY.to_string()];
+    for currency in CONFIG.quote_currencies.split(',').map(|currency| currency.trim().to_uppercase()) {
+        if !currency.is_empty() && !currencies.contains(&currency) {
+            currencies.push(currency);
+        }
+    }
+    currencies
+}
+
+pub fn normalize_currency(currency: &str, supported: &[String]) -> Result<String, ApiError> {
+    let currency = currency.trim().to_uppercase();
+    if !supported.contains(&currency) {
+        return Err(BadRequest(format!("Unsupported currency: {}, expected one of {}", currency, supported.join(", "))));
+    }
+    Ok(currency)
+}
+
+// Validate the `convert` query parameter, falling back to `default` (usually the user's preferred currency)
+pub fn parse_currency(convert: Option<&str>, default: &str) -> Result<String, ApiError> {
+    normalize_currency(convert.unwrap_or(default), &supported_currencies())
+}
+
+// Rates are cached together in one hash, each expiring after `conversion_rates_ttl_secs`
+async fn refresh_conversion_rates(redis: &Arc<Redis>) -> Result<HashMap<String, f64>, ApiError> {
+    let currencies: Vec<String> = supported_currencies()
+        .into_iter()
+        .filter(|currency| currency != BASE_CURRENCY)
+        .collect();
+
+    let rates = fetch_conversion_rates(&currencies).await.map_err(|err| {
+        error!("Failed to fetch conversion rates: {}", err);
+        InternalServerError
+    })?;
+
+    // Prices can still be converted with the fetched rates when they cannot be cached
+    for (currency, rate) in &rates {
+        if let Err(err) = redis.hset(RATES_CACHE_KEY.to_string(), currency.clone(), rate).await {
+            warn!("Failed to cache the {} conversion rate: {}", currency, err);
+        }
+    }
+    Ok(rates)
+}
+
+pub async fn conversion_rate(redis: &Arc<Redis>, currency: &str) -> Result<f64, ApiError> {
+    if currency == BASE_CURRENCY {
+        return Ok(1.0);
+    }
+
+    match redis.hget::<f64>(RATES_CACHE_KEY.to_string(), currency.to_string()).await {
+        Ok(rate) => return Ok(rate),
+        Err(ApiError::RedisNil) => {}
+        Err(err) => warn!("Failed to read the {} conversion rate from the cache, fetching it instead: {}", currency, err),
+    }
+    refresh_conversion_rates(redis).await?
+        .get(currency)
+        .copied()
+        .ok_or(InternalServerError)
+}
+
+pub fn convert_price(price: Option<f64>, rate: f64) -> Option<f64> {
+    price.map(|price| price * rate)
+}
+
+#[instrument]
+pub async fn retrieve_currencies(state: Data<AppState>) -> Result<Json<CurrenciesResponse>, ApiError> {
+    let mut currencies = vec![];
+    for code in supported_currencies() {
+        let rate = conversion_rate(&state.redis_client, &code).await?;
+        currencies.push(CurrencyResponse { code, rate });
+    }
+
+    respond_json(CurrenciesResponse { base: BASE_CURRENCY.to_string(), currencies })
+}
@@ -1,30 +1,28 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::sync::Arc;
 use std::time::Duration;
 use chrono::{DateTime, Utc};
-use log::{debug, error};
-use reqwest::ClientBuilder;
+use log::{debug, error, info, warn};
+use tracing::field::Empty;
+use tracing::{instrument, Span};
+use reqwest::{Client, ClientBuilder, RequestBuilder, Response, StatusCode};
 use crate::config::CONFIG;
-use sqlx::{Arguments, Pool, Postgres};
+use sqlx::{Arguments, Row};
 use sqlx::postgres::PgArguments;
+use crate::cache::Redis;
+use crate::candles::CandleInterval;
 use crate::database::{Database, PostgresDB};
+use crate::metrics::record_data_provider_request;
+use crate::smart_groups::refresh_smart_groups;
+use crate::telemetry::{record_outcome, with_trace_context};
+use crate::watchlistgroup::touch_watchlist_groups_of_assets;
 
 #[derive(Debug, Deserialize)]
 pub struct CMCAPIResponse {
-    status: Status,
     data: Vec<TokenInfo>
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Status {
-    timestamp: String,
-    error_code: u32,
-    error_message: Option<String>,
-    elapsed: u32,
-    credit_count: u8,
-    notice: Option<String>,
-}
-
 #[derive(Debug, Deserialize)]
 pub struct TokenInfo {
     id: i32,
@@ -39,21 +37,150 @@ pub struct TokenInfo {
 
 #[derive(Debug, Deserialize)]
 pub struct Platform {
-    id: u32,
     name: String,
-    symbol: String,
     slug: String,
-    tokenAddress: String,
 }
 
-pub async fn feed_assets_data(db_conn: Arc<PostgresDB>) -> Result<(), Box<dyn Error>> {
-    let client = ClientBuilder::new()
+#[derive(Debug, Deserialize)]
+pub struct CMCOHLCVResponse {
+    data: HashMap<String, OHLCVAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OHLCVAsset {
+    quotes: Vec<OHLCVQuote>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OHLCVQuote {
+    time_open: DateTime<Utc>,
+    time_close: DateTime<Utc>,
+    quote: HashMap<String, OHLCVPrice>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OHLCVPrice {
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: Option<f64>,
+    market_cap: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CMCPriceConversionResponse {
+    data: PriceConversion,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PriceConversion {
+    quote: HashMap<String, ConversionQuote>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConversionQuote {
+    price: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CMCListingsResponse {
+    data: Vec<Listing>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Listing {
+    id: i32,
+    cmc_rank: Option<i32>,
+    #[serde(default)]
+    tags: Vec<String>,
+    platform: Option<ListingPlatform>,
+    quote: HashMap<String, ListingQuote>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListingPlatform {
+    name: String,
+    slug: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListingQuote {
+    price: Option<f64>,
+    market_cap: Option<f64>,
+    volume_24h: Option<f64>,
+    percent_change_24h: Option<f64>,
+    percent_change_7d: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CMCCategoriesResponse {
+    data: Vec<Category>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Category {
+    id: String,
+    name: String,
+    title: Option<String>,
+    description: Option<String>,
+    num_tokens: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CMCCategoryResponse {
+    data: CategoryAssets,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CategoryAssets {
+    #[serde(default)]
+    coins: Vec<CategoryAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CategoryAsset {
+    id: i32,
+}
+
+// CoinMarketCap id of the US dollar, the currency every stored price is in
+const CMC_USD_ID: &str = "2781";
+
+// Share of the stored categories a single sync may delete, a larger prune is taken for a truncated response
+const MAX_CATEGORY_PRUNE_RATIO: f64 = 0.5;
+
+fn cmc_client() -> Client {
+    ClientBuilder::new()
         .timeout(Duration::from_secs(20))
-        .build().unwrap();
+        .build().unwrap()
+}
 
-    let response = match client.get(&CONFIG.cmc_token_id_endpoint)
-        .header("X-CMC_PRO_API_KEY", &CONFIG.cmc_api_key as &str)
-        .send().await {
+// Send a CoinMarketCap request in a client span carrying the trace context, counting its outcome per endpoint
+#[instrument(skip(request), fields(otel.kind = "client", http.status_code = Empty, otel.status_code = Empty, error.message = Empty))]
+async fn send_cmc_request(endpoint: &str, request: RequestBuilder) -> reqwest::Result<Response> {
+    let span = Span::current();
+    let result = with_trace_context(request).send().await;
+    if let Ok(response) = &result {
+        span.record("http.status_code", response.status().as_u16());
+    }
+    record_outcome(&span, &result);
+    let outcome = match &result {
+        Ok(response) if response.status().is_success() => "success",
+        Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        Ok(_) => "http_error",
+        Err(err) if err.is_timeout() => "timeout",
+        Err(_) => "transport_error",
+    };
+    record_data_provider_request(endpoint, outcome);
+    result
+}
+
+pub async fn feed_assets_data(db_conn: Arc<PostgresDB>) -> Result<(), Box<dyn Error>> {
+    let client = cmc_client();
+
+    let request = client.get(&CONFIG.cmc_token_id_endpoint)
+        .header("X-CMC_PRO_API_KEY", &CONFIG.cmc_api_key as &str);
+    let response = match send_cmc_request("token_map", request).await {
         Ok(resp) => resp,
         Err(err) => {
             error!("Error making request: {}", err);
@@ -78,6 +205,7 @@ pub async fn feed_assets_data(db_conn: Arc<PostgresDB>) -> Result<(), Box<dyn Er
     let api_response: CMCAPIResponse = serde_json::from_slice(&bytes)?;
 
     // Insert the TokenInfo data into PostgreSQL
+    let mut changed_assets = Vec::new();
     for asset in api_response.data {
         debug!("The symbol: {}", &asset.symbol);
         let first_historical_data = &asset.first_historical_data.parse::<DateTime<Utc>>()?;
@@ -89,13 +217,375 @@ pub async fn feed_assets_data(db_conn: Arc<PostgresDB>) -> Result<(), Box<dyn Er
         args.add(&asset.slug);
         args.add(first_historical_data);
         args.add(last_historical_data);
+        args.add(&asset.rank);
+        args.add(asset.platform.as_ref().map(|platform| &platform.name));
+        args.add(asset.platform.as_ref().map(|platform| &platform.slug));
 
-        db_conn
-            .execute(r#"INSERT INTO assets (id, name, symbol, slug, first_historical_data, last_historical_data)
-                        VALUES ($1, $2, $3, $4, $5, $6)
-                        ON CONFLICT (id) DO NOTHING"#, args)
+        let record = db_conn
+            .execute(r#"INSERT INTO assets (id, name, symbol, slug, first_historical_data, last_historical_data, rank, platform_name, platform_slug)
+                        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                        ON CONFLICT (id) DO UPDATE
+                        SET rank = EXCLUDED.rank, platform_name = EXCLUDED.platform_name, platform_slug = EXCLUDED.platform_slug
+                        WHERE (assets.rank, assets.platform_name, assets.platform_slug)
+                              IS DISTINCT FROM (EXCLUDED.rank, EXCLUDED.platform_name, EXCLUDED.platform_slug)"#, args)
             .await?;
+        if record.rows_affected() > 0 {
+            changed_assets.push(asset.id);
+        }
     }
 
+    // Ranks are part of the entries of the groups holding the assets
+    let db: Arc<dyn Database> = db_conn;
+    touch_watchlist_groups_of_assets(&db, &changed_assets).await?;
+
     Ok(())
-}
\ No newline at end of file
+}
+
+// Fetch missing OHLCV candles for every asset that is on at least one watchlist, resuming from the
+// latest stored candle or going back `candles_backfill_days` for assets without history
+pub async fn backfill_candles(db_conn: Arc<dyn Database>, redis_client: Arc<Redis>) -> Result<(), Box<dyn Error>> {
+    let client = cmc_client();
+    let intervals: Vec<CandleInterval> = CONFIG.candles_backfill_intervals
+        .split(',')
+        .filter_map(CandleInterval::from_code)
+        .collect();
+
+    for interval in intervals {
+        let mut args = PgArguments::default();
+        args.add(interval.code());
+        let assets = db_conn
+            .fetch_all(r#"SELECT a.id, a.first_historical_data,
+                                 (SELECT MAX(c.open_time) FROM asset_candles c WHERE c.asset_id = a.id AND c.timeframe = $1) AS last_open_time
+                          FROM assets a
+                          WHERE a.id IN (SELECT w.asset_id FROM watchlist w
+                                         JOIN watchlist_groups wg ON wg.id = w.group_id AND wg.deleted_at IS NULL)"#, args)
+            .await?;
+
+        let now = Utc::now();
+        for asset in assets {
+            let asset_id: i32 = asset.get("id");
+            let first_historical_data: Option<DateTime<Utc>> = asset.get("first_historical_data");
+            let last_open_time: Option<DateTime<Utc>> = asset.get("last_open_time");
+
+            // The latest stored candle may have been incomplete, so it is fetched again
+            let lookback = now - chrono::Duration::days(CONFIG.candles_backfill_days);
+            let start = last_open_time
+                .unwrap_or_else(|| first_historical_data.map_or(lookback, |first| first.max(lookback)));
+            if start + interval.duration() > now && last_open_time.is_some() {
+                continue;
+            }
+
+            match fetch_candles(&client, asset_id, interval, start, now).await {
+                Ok(quotes) => {
+                    let inserted = store_candles(&db_conn, asset_id, interval, &quotes).await?;
+                    debug!("Stored {} {} candles for asset {}", inserted, interval.code(), asset_id);
+                    redis_client.del(format!("candles::{}", asset_id)).await;
+                }
+                Err(err) => error!("Failed to fetch {} candles for asset {}: {}", interval.code(), asset_id, err),
+            }
+        }
+    }
+
+    info!("Candles backfill finished");
+    Ok(())
+}
+
+async fn fetch_candles(
+    client: &Client,
+    asset_id: i32,
+    interval: CandleInterval,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<OHLCVQuote>, Box<dyn Error>> {
+    let request = client.get(&CONFIG.cmc_ohlcv_historical_endpoint)
+        .header("X-CMC_PRO_API_KEY", &CONFIG.cmc_api_key as &str)
+        .query(&[
+            ("id", asset_id.to_string()),
+            ("time_period", interval.cmc_period().to_string()),
+            ("interval", interval.cmc_period().to_string()),
+            ("time_start", start.to_rfc3339()),
+            ("time_end", end.to_rfc3339()),
+            ("convert", "USD".to_string()),
+        ]);
+    let response = send_cmc_request("ohlcv_historical", request).await?;
+
+    if !response.status().is_success() {
+        return Err(format!("Request failed with status code: {}", response.status()).into());
+    }
+
+    let api_response: CMCOHLCVResponse = response.json().await?;
+    Ok(api_response.data
+        .into_values()
+        .flat_map(|asset| asset.quotes)
+        .collect())
+}
+
+async fn store_candles(
+    db_conn: &Arc<dyn Database>,
+    asset_id: i32,
+    interval: CandleInterval,
+    quotes: &[OHLCVQuote],
+) -> Result<u64, Box<dyn Error>> {
+    let quotes: Vec<(&OHLCVQuote, &OHLCVPrice)> = quotes
+        .iter()
+        .filter_map(|quote| quote.quote.get("USD").map(|usd| (quote, usd)))
+        .collect();
+    if quotes.is_empty() {
+        return Ok(0);
+    }
+
+    let mut args = PgArguments::default();
+    args.add(asset_id);
+    args.add(interval.code());
+    args.add(quotes.iter().map(|(quote, _)| quote.time_open).collect::<Vec<_>>());
+    args.add(quotes.iter().map(|(quote, _)| quote.time_close).collect::<Vec<_>>());
+    args.add(quotes.iter().map(|(_, usd)| usd.open).collect::<Vec<_>>());
+    args.add(quotes.iter().map(|(_, usd)| usd.high).collect::<Vec<_>>());
+    args.add(quotes.iter().map(|(_, usd)| usd.low).collect::<Vec<_>>());
+    args.add(quotes.iter().map(|(_, usd)| usd.close).collect::<Vec<_>>());
+    args.add(quotes.iter().map(|(_, usd)| usd.volume).collect::<Vec<_>>());
+    args.add(quotes.iter().map(|(_, usd)| usd.market_cap).collect::<Vec<_>>());
+
+    let record = db_conn
+        .execute(r#"INSERT INTO asset_candles (asset_id, timeframe, open_time, close_time, open, high, low, close, volume, market_cap)
+                    SELECT $1, $2, * FROM UNNEST($3::timestamptz[], $4::timestamptz[], $5::float8[], $6::float8[],
+                                                 $7::float8[], $8::float8[], $9::float8[], $10::float8[])
+                    ON CONFLICT (asset_id, timeframe, open_time) DO UPDATE
+                    SET close_time = EXCLUDED.close_time, open = EXCLUDED.open, high = EXCLUDED.high, low = EXCLUDED.low,
+                        close = EXCLUDED.close, volume = EXCLUDED.volume, market_cap = EXCLUDED.market_cap"#, args)
+        .await?;
+
+    Ok(record.rows_affected())
+}
+
+// Amount of each currency one US dollar buys. Currencies are requested one at a time since lower
+// CoinMarketCap plans accept a single `convert` value per call.
+pub async fn fetch_conversion_rates(currencies: &[String]) -> Result<HashMap<String, f64>, Box<dyn Error>> {
+    let client = cmc_client();
+    let mut rates = HashMap::new();
+
+    for currency in currencies {
+        let request = client.get(&CONFIG.cmc_price_conversion_endpoint)
+            .header("X-CMC_PRO_API_KEY", &CONFIG.cmc_api_key as &str)
+            .query(&[("id", CMC_USD_ID), ("amount", "1"), ("convert", currency)]);
+        let response = send_cmc_request("price_conversion", request).await?;
+
+        if !response.status().is_success() {
+            return Err(format!("Request failed with status code: {}", response.status()).into());
+        }
+
+        let api_response: CMCPriceConversionResponse = response.json().await?;
+        let rate = api_response.data.quote
+            .get(currency)
+            .and_then(|quote| quote.price)
+            .ok_or(format!("No conversion rate returned for {}", currency))?;
+        rates.insert(currency.clone(), rate);
+    }
+
+    Ok(rates)
+}
+
+// Refresh the latest quotes, rank, platform and tags of the top `asset_quotes_sync_limit` assets.
+// Listings of assets missing from the catalog are skipped until the next assets feed.
+pub async fn sync_asset_quotes(db_conn: Arc<dyn Database>, redis_client: Arc<Redis>) -> Result<(), Box<dyn Error>> {
+    let client = cmc_client();
+    let request = client.get(&CONFIG.cmc_listings_latest_endpoint)
+        .header("X-CMC_PRO_API_KEY", &CONFIG.cmc_api_key as &str)
+        .query(&[("limit", CONFIG.asset_quotes_sync_limit.to_string()), ("convert", "USD".to_string())]);
+    let response = send_cmc_request("listings_latest", request).await?;
+
+    if !response.status().is_success() {
+        return Err(format!("Request failed with status code: {}", response.status()).into());
+    }
+
+    let api_response: CMCListingsResponse = response.json().await?;
+    let listings: Vec<(&Listing, &ListingQuote)> = api_response.data
+        .iter()
+        .filter_map(|listing| listing.quote.get("USD").map(|usd| (listing, usd)))
+        .collect();
+    if listings.is_empty() {
+        return Ok(());
+    }
+
+    let mut args = PgArguments::default();
+    args.add(listings.iter().map(|(listing, _)| listing.id).collect::<Vec<_>>());
+    args.add(listings.iter().map(|(listing, _)| listing.cmc_rank).collect::<Vec<_>>());
+    // Tag slugs never contain commas, so each list travels as one string
+    args.add(listings.iter().map(|(listing, _)| listing.tags.join(",")).collect::<Vec<_>>());
+    args.add(listings.iter().map(|(listing, _)| listing.platform.as_ref().map(|platform| platform.name.clone())).collect::<Vec<_>>());
+    args.add(listings.iter().map(|(listing, _)| listing.platform.as_ref().map(|platform| platform.slug.clone())).collect::<Vec<_>>());
+    // `previous` still reads the rows as they were before the update
+    let ranked = db_conn
+        .fetch_all(r#"UPDATE assets a
+                      SET rank = COALESCE(l.rank, a.rank), tags = string_to_array(l.tags, ','),
+                          platform_name = l.platform_name, platform_slug = l.platform_slug
+                      FROM UNNEST($1::int[], $2::int[], $3::text[], $4::text[], $5::text[]) AS l(id, rank, tags, platform_name, platform_slug),
+                           assets previous
+                      WHERE a.id = l.id AND previous.id = a.id
+                      RETURNING a.id, a.rank IS DISTINCT FROM previous.rank AS rank_changed"#, args)
+        .await?;
+    let reranked: Vec<i32> = ranked.iter()
+        .filter(|record| record.get::<bool, _>("rank_changed"))
+        .map(|record| record.get("id"))
+        .collect();
+    touch_watchlist_groups_of_assets(&db_conn, &reranked).await?;
+
+    let mut args = PgArguments::default();
+    args.add(listings.iter().map(|(listing, _)| listing.id).collect::<Vec<_>>());
+    args.add(listings.iter().map(|(_, usd)| usd.price).collect::<Vec<_>>());
+    args.add(listings.iter().map(|(_, usd)| usd.market_cap).collect::<Vec<_>>());
+    args.add(listings.iter().map(|(_, usd)| usd.volume_24h).collect::<Vec<_>>());
+    args.add(listings.iter().map(|(_, usd)| usd.percent_change_24h).collect::<Vec<_>>());
+    args.add(listings.iter().map(|(_, usd)| usd.percent_change_7d).collect::<Vec<_>>());
+    let record = db_conn
+        .execute(r#"INSERT INTO asset_quotes (asset_id, price, market_cap, volume_24h, percent_change_24h, percent_change_7d)
+                    SELECT q.* FROM UNNEST($1::int[], $2::float8[], $3::float8[], $4::float8[], $5::float8[], $6::float8[])
+                        AS q(asset_id, price, market_cap, volume_24h, percent_change_24h, percent_change_7d)
+                    WHERE EXISTS (SELECT 1 FROM assets a WHERE a.id = q.asset_id)
+                    ON CONFLICT (asset_id) DO UPDATE
+                    SET price = EXCLUDED.price, market_cap = EXCLUDED.market_cap, volume_24h = EXCLUDED.volume_24h,
+                        percent_change_24h = EXCLUDED.percent_change_24h, percent_change_7d = EXCLUDED.percent_change_7d,
+                        updated_at = NOW()"#, args)
+        .await?;
+
+    redis_client.del("screener".to_string()).await;
+    info!("Synced quotes of {} assets", record.rows_affected());
+
+    let refreshed = refresh_smart_groups(&db_conn, &redis_client).await?;
+    debug!("Refreshed {} smart watchlist groups", refreshed);
+    Ok(())
+}
+
+// Whether deleting the `stale` categories missing from a sync still looks like CMC retiring a few of them
+fn is_safe_category_prune(stored: i64, stale: i64) -> bool {
+    stale as f64 <= stored as f64 * MAX_CATEGORY_PRUNE_RATIO
+}
+
+// Replace the categories and their member assets, then bring smart groups in line with them.
+// A category whose assets fail to load keeps its previous members, and categories missing from the response
+// are only deleted when they are a small share of the stored ones. An empty response fails the sync.
+pub async fn sync_asset_categories(db_conn: Arc<dyn Database>, redis_client: Arc<Redis>) -> Result<(), Box<dyn Error>> {
+    let client = cmc_client();
+    let request = client.get(&CONFIG.cmc_categories_endpoint)
+        .header("X-CMC_PRO_API_KEY", &CONFIG.cmc_api_key as &str);
+    let response = send_cmc_request("categories", request).await?;
+
+    if !response.status().is_success() {
+        return Err(format!("Request failed with status code: {}", response.status()).into());
+    }
+
+    let api_response: CMCCategoriesResponse = response.json().await?;
+    let categories = api_response.data;
+    if categories.is_empty() {
+        return Err("CoinMarketCap returned no categories, keeping the stored ones".into());
+    }
+
+    let mut args = PgArguments::default();
+    args.add(categories.iter().map(|category| category.id.clone()).collect::<Vec<_>>());
+    let record = db_conn
+        .fetch_one("SELECT COUNT(*) AS stored, COUNT(*) FILTER (WHERE id <> ALL($1)) AS stale FROM categories", args)
+        .await?;
+    let (stored, stale): (i64, i64) = (record.get("stored"), record.get("stale"));
+    let prune = is_safe_category_prune(stored, stale);
+    if !prune {
+        warn!("Keeping {} of {} categories missing from the CoinMarketCap response", stale, stored);
+    }
+
+    // Assets whose categories render differently after the sync, starting with the members of renamed or removed categories
+    let mut args = PgArguments::default();
+    args.add(categories.iter().map(|category| category.id.clone()).collect::<Vec<_>>());
+    args.add(categories.iter().map(|category| category.name.clone()).collect::<Vec<_>>());
+    args.add(prune);
+    let mut changed_assets: HashSet<i32> = db_conn
+        .fetch_all(r#"SELECT ac.asset_id FROM asset_categories ac JOIN categories c ON c.id = ac.category_id
+                      LEFT JOIN UNNEST($1::text[], $2::text[]) AS n(id, name) ON n.id = c.id
+                      WHERE (n.id IS NULL AND $3) OR n.name <> c.name"#, args)
+        .await?
+        .iter()
+        .map(|record| record.get("asset_id"))
+        .collect();
+
+    let mut args = PgArguments::default();
+    args.add(categories.iter().map(|category| category.id.clone()).collect::<Vec<_>>());
+    args.add(categories.iter().map(|category| category.name.clone()).collect::<Vec<_>>());
+    args.add(categories.iter().map(|category| category.title.clone()).collect::<Vec<_>>());
+    args.add(categories.iter().map(|category| category.description.clone()).collect::<Vec<_>>());
+    args.add(categories.iter().map(|category| category.num_tokens).collect::<Vec<_>>());
+    db_conn
+        .execute(r#"INSERT INTO categories (id, name, title, description, num_tokens)
+                    SELECT * FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[], $5::int[])
+                    ON CONFLICT (id) DO UPDATE
+                    SET name = EXCLUDED.name, title = EXCLUDED.title, description = EXCLUDED.description,
+                        num_tokens = EXCLUDED.num_tokens, updated_at = NOW()"#, args)
+        .await?;
+
+    if prune {
+        let mut args = PgArguments::default();
+        args.add(categories.iter().map(|category| category.id.clone()).collect::<Vec<_>>());
+        db_conn.execute("DELETE FROM categories WHERE id <> ALL($1)", args).await?;
+    }
+
+    for category in &categories {
+        let asset_ids = match fetch_category_assets(&client, &category.id).await {
+            Ok(asset_ids) => asset_ids,
+            Err(err) => {
+                error!("Failed to fetch the assets of category {}: {}", category.id, err);
+                continue;
+            }
+        };
+
+        let mut args = PgArguments::default();
+        args.add(&category.id);
+        args.add(&asset_ids);
+        let removed = db_conn
+            .fetch_all("DELETE FROM asset_categories WHERE category_id = $1 AND asset_id <> ALL($2) RETURNING asset_id", args)
+            .await?;
+
+        let mut args = PgArguments::default();
+        args.add(&category.id);
+        args.add(&asset_ids);
+        let added = db_conn
+            .fetch_all(r#"INSERT INTO asset_categories (asset_id, category_id)
+                          SELECT a.id, $1 FROM assets a WHERE a.id = ANY($2)
+                          ON CONFLICT DO NOTHING RETURNING asset_id"#, args)
+            .await?;
+        changed_assets.extend(removed.iter().chain(&added).map(|record| record.get::<i32, _>("asset_id")));
+    }
+    touch_watchlist_groups_of_assets(&db_conn, &changed_assets.into_iter().collect::<Vec<_>>()).await?;
+
+    redis_client.del("categories".to_string()).await;
+    redis_client.del("screener".to_string()).await;
+    info!("Synced {} categories", categories.len());
+
+    let refreshed = refresh_smart_groups(&db_conn, &redis_client).await?;
+    debug!("Refreshed {} smart watchlist groups", refreshed);
+    Ok(())
+}
+
+async fn fetch_category_assets(client: &Client, category_id: &str) -> Result<Vec<i32>, Box<dyn Error>> {
+    let request = client.get(&CONFIG.cmc_category_endpoint)
+        .header("X-CMC_PRO_API_KEY", &CONFIG.cmc_api_key as &str)
+        .query(&[("id", category_id.to_string()), ("limit", CONFIG.category_assets_limit.to_string())]);
+    let response = send_cmc_request("category", request).await?;
+
+    if !response.status().is_success() {
+        return Err(format!("Request failed with status code: {}", response.status()).into());
+    }
+
+    let api_response: CMCCategoryResponse = response.json().await?;
+    Ok(api_response.data.coins.into_iter().map(|asset| asset.id).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_is_safe_category_prune() {
+        assert!(is_safe_category_prune(0, 0));
+        assert!(is_safe_category_prune(200, 3));
+        assert!(is_safe_category_prune(200, 100));
+        assert!(!is_safe_category_prune(200, 101));
+        assert!(!is_safe_category_prune(200, 200));
+    }
+}
@@ -1,9 +1,15 @@
 use std::fmt::Debug;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Instant;
 use async_trait::async_trait;
-use sqlx::{Error, PgPool, Postgres, Row, Transaction};
+use sqlx::{Arguments, Encode, Error, PgPool, Postgres, Row, Type};
 use sqlx::postgres::{PgArguments, PgPoolOptions, PgQueryResult, PgRow};
+use tracing::field::Empty;
+use tracing::{info_span, Instrument};
 use crate::config::CONFIG;
+use crate::metrics::record_db_query;
+use crate::telemetry::record_outcome;
 
 #[async_trait]
 pub trait Database: Send + Sync + Debug {
@@ -11,25 +17,116 @@ pub trait Database: Send + Sync + Debug {
     async fn fetch_all(&self, query: &str, args: PgArguments) -> Result<Vec<PgRow>, Error>;
     async fn fetch_one(&self, query: &str, args: PgArguments) -> Result<PgRow, Error>;
     async fn fetch_optional(&self, query: &str, args: PgArguments) -> Result<Option<PgRow>, Error>;
+    fn pool_usage(&self) -> PoolUsage;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolUsage {
+    pub in_use: u32,
+    pub idle: u32,
+    pub max: u32,
+}
+
+impl PostgresDB {
+    // Run a query in a client span, timing it for the metrics
+    async fn traced<T>(&self, operation: &str, query: &str, run: impl Future<Output = Result<T, Error>>) -> Result<T, Error> {
+        let span = info_span!(
+            "postgres.query",
+            otel.name = %format_args!("postgres {}", operation),
+            otel.kind = "client",
+            db.system = "postgresql",
+            db.operation = operation,
+            db.statement = query,
+            otel.status_code = Empty,
+            error.message = Empty,
+        );
+        let started = Instant::now();
+        let result = run.instrument(span.clone()).await;
+        record_db_query(operation, &result, started.elapsed());
+        record_outcome(&span, &result);
+        result
+    }
 }
 
 #[async_trait]
 impl Database for PostgresDB {
     async fn execute(&self, query: &str, args: PgArguments) -> Result<PgQueryResult, Error> {
-        sqlx::query_with(query, args).execute(&self.pool).await
+        self.traced("execute", query, sqlx::query_with(query, args).execute(&self.pool)).await
     }
 
     async fn fetch_all(&self, query: &str, args: PgArguments) -> Result<Vec<PgRow>, Error> {
-        sqlx::query_with(query, args).fetch_all(&self.pool).await
+        self.traced("fetch_all", query, sqlx::query_with(query, args).fetch_all(&self.pool)).await
     }
 
     async fn fetch_one(&self, query: &str, args: PgArguments) -> Result<PgRow, Error> {
-        sqlx::query_with(query, args).fetch_one(&self.pool).await
+        self.traced("fetch_one", query, sqlx::query_with(query, args).fetch_one(&self.pool)).await
     }
 
     async fn fetch_optional(&self, query: &str, args: PgArguments) -> Result<Option<PgRow>, Error> {
-        sqlx::query_with(query, args).fetch_optional(&self.pool).await
+        self.traced("fetch_optional", query, sqlx::query_with(query, args).fetch_optional(&self.pool)).await
+    }
+
+    fn pool_usage(&self) -> PoolUsage {
+        let idle = self.pool.num_idle() as u32;
+        PoolUsage {
+            in_use: self.pool.size().saturating_sub(idle),
+            idle,
+            max: self.pool.options().get_max_connections(),
+        }
+    }
+}
+
+// Accumulates `AND`-ed WHERE clauses together with their positional arguments. `$?` in a
+// clause is replaced by the position of the value bound with it.
+#[derive(Default)]
+pub struct SqlConditions {
+    clauses: Vec<String>,
+    args: PgArguments,
+    len: usize,
+}
+
+impl SqlConditions {
+    pub fn new() -> Self {
+        Self::default()
     }
+
+    pub fn bind<'q, T>(&mut self, value: T) -> usize
+    where T: 'q + Send + Encode<'q, Postgres> + Type<Postgres> {
+        self.args.add(value);
+        self.len += 1;
+        self.len
+    }
+
+    pub fn push<'q, T>(&mut self, clause: &str, value: T)
+    where T: 'q + Send + Encode<'q, Postgres> + Type<Postgres> {
+        let position = self.bind(value);
+        self.clauses.push(clause.replace("$?", &format!("${}", position)));
+    }
+
+    pub fn push_clause(&mut self, clause: String) {
+        self.clauses.push(clause);
+    }
+
+    pub fn where_clause(&self) -> String {
+        if self.clauses.is_empty() {
+            return String::new();
+        }
+        format!("WHERE {}", self.clauses.join(" AND "))
+    }
+
+    pub fn into_args(self) -> PgArguments {
+        self.args
+    }
+}
+
+// Escape a user supplied search term for a `LIKE` substring match
+pub fn like_pattern(term: &str) -> String {
+    let escaped = term
+        .to_lowercase()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
 }
 
 #[derive(Debug)]
@@ -1,5 +1,3 @@
-use std::fmt;
-use std::fmt::Display;
 use actix_web::{HttpResponse, ResponseError};
 use actix_web::http::header::ContentType;
 use actix_web::http::StatusCode;
@@ -14,6 +12,8 @@ pub enum ApiError {
     InternalServerError,
     #[display(fmt = "The data is not found")]
     NotFound,
+    #[display(fmt = "The resource has changed since it was retrieved")]
+    PreconditionFailed,
     #[display(fmt = "Invalid Token")]
     InvalidToken,
     #[display(fmt = "Token has expired")]
@@ -30,6 +30,8 @@ pub enum ApiError {
     RedisNil,
 }
 
+impl std::error::Error for ApiError {}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ErrorResponse {
     errors: Vec<String>,
@@ -47,6 +49,7 @@ impl ResponseError for ApiError {
             ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
             ApiError::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
             ApiError::InvalidToken => StatusCode::UNAUTHORIZED,
             ApiError::ExpiredSignature => StatusCode::UNAUTHORIZED,
             ApiError::MissingAuthorizationHeader => StatusCode::UNAUTHORIZED,
@@ -1,22 +1,177 @@
-use actix_web::web::Json;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Data, Json};
+use kafka::client::KafkaClient;
+use sqlx::Row;
+use sqlx::postgres::PgArguments;
+use crate::config::CONFIG;
 use crate::errors::ApiError;
 use crate::helpers::respond_json;
+use crate::server::AppState;
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct HealthResponse {
     pub status: String,
 }
 
-// Handler to get liveliness of the service
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Up,
+    // Still serving, with a dependency missing or stale
+    Degraded,
+    Down,
+    // Not configured for this deployment
+    Disabled,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct DependencyCheck {
+    pub status: CheckStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    // Seconds since the last asset quotes sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub age_secs: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ReadinessChecks {
+    pub postgres: DependencyCheck,
+    pub redis: DependencyCheck,
+    pub broker: DependencyCheck,
+    pub asset_sync: DependencyCheck,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ReadinessResponse {
+    pub status: CheckStatus,
+    pub checks: ReadinessChecks,
+}
+
+// Requests cannot be served without Postgres. Everything else only degrades them: Redis is bypassed
+// while it is down, and a missing broker or a stale sync leaves the data older than it should be.
+fn overall_status(checks: &ReadinessChecks) -> CheckStatus {
+    if checks.postgres.status == CheckStatus::Down {
+        return CheckStatus::Down;
+    }
+    let others = [checks.redis.status, checks.broker.status, checks.asset_sync.status];
+    if others.iter().any(|status| matches!(status, CheckStatus::Down | CheckStatus::Degraded)) {
+        return CheckStatus::Degraded;
+    }
+    CheckStatus::Up
+}
+
+// Quotes only age when this deployment syncs them. A `max_age_secs` of 0 only reports the age.
+fn asset_sync_status(sync_enabled: bool, age_secs: Option<i64>, max_age_secs: i64) -> CheckStatus {
+    match age_secs {
+        _ if !sync_enabled => CheckStatus::Disabled,
+        _ if max_age_secs <= 0 => CheckStatus::Up,
+        Some(age_secs) if age_secs <= max_age_secs => CheckStatus::Up,
+        _ => CheckStatus::Degraded,
+    }
+}
+
+// Run a check within `health_check_timeout_ms`, recording how long it took
+async fn timed_check<T, E: Display>(check: impl Future<Output = Result<T, E>>) -> (DependencyCheck, Option<T>) {
+    let started = Instant::now();
+    let result = match actix_rt::time::timeout(Duration::from_millis(CONFIG.health_check_timeout_ms), check).await {
+        Ok(result) => result.map_err(|err| err.to_string()),
+        Err(_) => Err("Timed out".to_string()),
+    };
+    let latency_ms = Some(started.elapsed().as_millis() as u64);
+
+    match result {
+        Ok(value) => (DependencyCheck { status: CheckStatus::Up, latency_ms, age_secs: None, error: None }, Some(value)),
+        Err(error) => (DependencyCheck { status: CheckStatus::Down, latency_ms, age_secs: None, error: Some(error) }, None),
+    }
+}
+
+async fn check_postgres(state: &AppState) -> DependencyCheck {
+    timed_check(state.db.fetch_one("SELECT 1", PgArguments::default())).await.0
+}
+
+async fn check_redis(state: &AppState) -> DependencyCheck {
+    timed_check(state.redis_client.ping()).await.0
+}
+
+// Loading the cluster metadata needs a broker to answer. The client blocks, so it runs on the blocking pool.
+async fn check_broker() -> DependencyCheck {
+    if CONFIG.kafka_brokers.is_empty() {
+        return DependencyCheck { status: CheckStatus::Disabled, latency_ms: None, age_secs: None, error: None };
+    }
+    let hosts: Vec<String> = CONFIG.kafka_brokers.split(',').map(|host| host.trim().to_string()).collect();
+
+    timed_check(async move {
+        web::block(move || {
+            let mut client = KafkaClient::new(hosts);
+            client.set_client_id(env!("CARGO_PKG_NAME").to_string());
+            client.load_metadata_all().map_err(|err| err.to_string())
+        })
+        .await
+        .map_err(|err| err.to_string())?
+    }).await.0
+}
+
+async fn check_asset_sync(state: &AppState) -> DependencyCheck {
+    if !CONFIG.is_asset_quotes_sync_enabled {
+        return DependencyCheck { status: CheckStatus::Disabled, latency_ms: None, age_secs: None, error: None };
+    }
+    let (mut check, record) = timed_check(state.db.fetch_one(
+        "SELECT EXTRACT(EPOCH FROM NOW() - MAX(updated_at))::bigint AS age_secs FROM asset_quotes",
+        PgArguments::default(),
+    )).await;
+
+    if let Some(record) = record {
+        let age_secs: Option<i64> = record.get("age_secs");
+        check.status = asset_sync_status(CONFIG.is_asset_quotes_sync_enabled, age_secs, CONFIG.health_max_asset_sync_age_secs);
+        check.age_secs = age_secs;
+        if check.status == CheckStatus::Degraded {
+            check.error = Some(match age_secs {
+                Some(_) => "The last asset quotes sync is too old".to_string(),
+                None => "Asset quotes were never synced".to_string(),
+            });
+        }
+    }
+    check
+}
+
+// Handler to get liveliness of the service, which touches no dependency
 pub async fn get_health() -> Result<Json<HealthResponse>, ApiError>{
     respond_json(HealthResponse{
         status: "ok".into(),
     })
 }
 
+// Handler to get whether the instance should receive traffic, 503 once it cannot serve requests
+pub async fn get_readiness(state: Data<AppState>) -> HttpResponse {
+    let (postgres, redis, broker, asset_sync) = futures_util::join!(
+        check_postgres(&state),
+        check_redis(&state),
+        check_broker(),
+        check_asset_sync(&state),
+    );
+    let checks = ReadinessChecks { postgres, redis, broker, asset_sync };
+    let status = overall_status(&checks);
+
+    let code = if status == CheckStatus::Down { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::OK };
+    HttpResponse::build(code).json(ReadinessResponse { status, checks })
+}
+
+// Handler to get the state of the Redis circuit breaker and connections, 503 while requests bypass the cache
+pub async fn get_cache_health(state: Data<AppState>) -> HttpResponse {
+    let health = state.redis_client.health().await;
+    let status = if health.status == "up" { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    HttpResponse::build(status).json(health)
+}
+
 #[cfg(test)]
 mod tests {
-    use actix_web::test;
     use super::*;
 
     #[actix::test]
@@ -29,4 +184,34 @@ mod tests {
             "ok".to_string()
         )
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_unit_readiness_status() {
+        let check = |status| DependencyCheck { status, latency_ms: Some(1), age_secs: None, error: None };
+        let mut checks = ReadinessChecks {
+            postgres: check(CheckStatus::Up),
+            redis: check(CheckStatus::Up),
+            broker: check(CheckStatus::Disabled),
+            asset_sync: check(CheckStatus::Up),
+        };
+        assert_eq!(overall_status(&checks), CheckStatus::Up);
+
+        checks.redis.status = CheckStatus::Down;
+        assert_eq!(overall_status(&checks), CheckStatus::Degraded);
+
+        checks.postgres.status = CheckStatus::Down;
+        assert_eq!(overall_status(&checks), CheckStatus::Down);
+
+    }
+
+    #[test]
+    fn test_unit_asset_sync_status() {
+        assert_eq!(asset_sync_status(true, Some(60), 1800), CheckStatus::Up);
+        assert_eq!(asset_sync_status(true, Some(1801), 1800), CheckStatus::Degraded);
+        assert_eq!(asset_sync_status(true, None, 1800), CheckStatus::Degraded);
+        assert_eq!(asset_sync_status(true, None, 0), CheckStatus::Up);
+        // Without the quotes sync nothing refreshes them, which does not make the instance degraded
+        assert_eq!(asset_sync_status(false, None, 1800), CheckStatus::Disabled);
+        assert_eq!(asset_sync_status(false, Some(86400), 1800), CheckStatus::Disabled);
+    }
+}
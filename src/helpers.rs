@@ -1,7 +1,8 @@
 use actix_web::HttpResponse;
 use actix_web::web::Json;
-use chrono::NaiveDateTime;
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
 use crate::errors::ApiError;
 
 pub fn respond_json<T>(data: T) -> Result<Json<T>, ApiError>
@@ -16,9 +17,52 @@ pub fn respond_ok() -> Result<HttpResponse, ApiError> {
     Ok(HttpResponse::Ok().finish())
 }
 
-pub fn format_datetime(datetime: Option<NaiveDateTime>) -> String {
-    match datetime {
-        Some(dt) => dt.format("%Y-%m-%d %H:%M:%S").to_string(), // Customize format as needed
-        None => String::new(), // Handle the case where datetime is None
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    #[default]
+    Rfc3339,
+    EpochMillis,
+}
+
+// A rendered timestamp, an RFC 3339 string in the user's timezone or milliseconds since the epoch
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Timestamp {
+    EpochMillis(i64),
+    Rfc3339(String),
+}
+
+// For endpoints that take no other query parameters
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub struct TimestampQuery {
+    pub time_format: Option<TimestampFormat>,
+}
+
+pub fn format_datetime(datetime: Option<DateTime<Utc>>, timezone: Tz, format: TimestampFormat) -> Option<Timestamp> {
+    datetime.map(|datetime| match format {
+        TimestampFormat::Rfc3339 => Timestamp::Rfc3339(datetime.with_timezone(&timezone).to_rfc3339()),
+        TimestampFormat::EpochMillis => Timestamp::EpochMillis(datetime.timestamp_millis()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_unit_format_datetime() {
+        let datetime = Utc.with_ymd_and_hms(2024, 5, 18, 9, 13, 33).unwrap();
+
+        assert_eq!(
+            format_datetime(Some(datetime), Tz::Asia__Jakarta, TimestampFormat::Rfc3339),
+            Some(Timestamp::Rfc3339("2024-05-18T16:13:33+07:00".into()))
+        );
+        assert_eq!(
+            format_datetime(Some(datetime), Tz::Asia__Jakarta, TimestampFormat::EpochMillis),
+            Some(Timestamp::EpochMillis(1716023613000))
+        );
+        assert_eq!(format_datetime(None, Tz::UTC, TimestampFormat::Rfc3339), None);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,122 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::http::header::{CacheControl, CacheDirective, ETag, EntityTag, Header, HttpDate, IfMatch, IfModifiedSince, IfNoneMatch, LastModified};
+use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::errors::ApiError;
+
+// Validators of a response: a strong ETag naming the state it was rendered from (e.g. a group version) and
+// the variant it was rendered for (query, timezone...), and when that state last changed
+#[derive(Debug, Clone)]
+pub struct Validators {
+    etag: EntityTag,
+    last_modified: Option<HttpDate>,
+}
+
+// `<state>.<variant hash>`, the state must not contain a dot
+fn entity_tag(state: &str, variant: &str) -> EntityTag {
+    let hash = Sha256::digest(variant.as_bytes());
+    let hash: String = hash[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+    EntityTag::new_strong(format!("{}.{}", state, hash))
+}
+
+fn tag_state(tag: &EntityTag) -> &str {
+    tag.tag().rsplit_once('.').map_or(tag.tag(), |(state, _)| state)
+}
+
+fn http_date(datetime: DateTime<Utc>) -> Option<HttpDate> {
+    let secs = u64::try_from(datetime.timestamp()).ok()?;
+    Some(HttpDate::from(UNIX_EPOCH + Duration::from_secs(secs)))
+}
+
+impl Validators {
+    pub fn new(state: &str, variant: &str, last_modified: Option<DateTime<Utc>>) -> Self {
+        Validators { etag: entity_tag(state, variant), last_modified: last_modified.and_then(http_date) }
+    }
+
+    // Whether the client already has this response. If-Modified-Since only counts without If-None-Match.
+    pub fn is_fresh(&self, request: &HttpRequest) -> bool {
+        // Parsing succeeds with an empty list when the header is missing
+        if request.headers().contains_key(IfNoneMatch::name()) {
+            return match IfNoneMatch::parse(request) {
+                Ok(IfNoneMatch::Any) => true,
+                Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&self.etag)),
+                Err(_) => false,
+            };
+        }
+        match (IfModifiedSince::parse(request), self.last_modified) {
+            (Ok(IfModifiedSince(since)), Some(last_modified)) => SystemTime::from(last_modified) <= SystemTime::from(since),
+            _ => false,
+        }
+    }
+
+    pub fn not_modified(&self) -> HttpResponse {
+        self.headers(HttpResponse::NotModified()).finish()
+    }
+
+    // 304 when the client's copy is fresh, the JSON body otherwise
+    pub fn respond<T: Serialize>(&self, request: &HttpRequest, body: T) -> Result<HttpResponse, ApiError> {
+        if self.is_fresh(request) {
+            return Ok(self.not_modified());
+        }
+        Ok(self.headers(HttpResponse::Ok()).json(body))
+    }
+
+    fn headers(&self, mut response: HttpResponseBuilder) -> HttpResponseBuilder {
+        response.insert_header(ETag(self.etag.clone()));
+        if let Some(last_modified) = self.last_modified {
+            response.insert_header(LastModified(last_modified));
+        }
+        // Responses are per user and must be revalidated before reuse
+        response.insert_header(CacheControl(vec![CacheDirective::Private, CacheDirective::NoCache]));
+        response
+    }
+
+    pub fn etag(&self) -> ETag {
+        ETag(self.etag.clone())
+    }
+}
+
+// The states named by If-Match, `None` when any state is acceptable. Weak tags never match.
+pub fn if_match_states(request: &HttpRequest) -> Option<Vec<String>> {
+    if !request.headers().contains_key(IfMatch::name()) {
+        return None;
+    }
+    match IfMatch::parse(request) {
+        Ok(IfMatch::Any) => None,
+        Ok(IfMatch::Items(tags)) => Some(tags.iter().filter(|tag| !tag.weak).map(|tag| tag_state(tag).to_string()).collect()),
+        Err(_) => Some(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use super::*;
+
+    #[test]
+    fn test_unit_conditional_requests() {
+        let updated_at = DateTime::from_timestamp(1_760_000_000, 500_000_000).unwrap();
+        let validators = Validators::new("wg1v3", "UTC::{}", Some(updated_at));
+        let etag = validators.etag.to_string();
+        assert!(etag.starts_with("\"wg1v3."));
+        assert_ne!(etag, Validators::new("wg1v3", "Asia/Tokyo::{}", Some(updated_at)).etag.to_string());
+
+        let request = TestRequest::get().insert_header(("If-None-Match", etag.as_str())).to_http_request();
+        assert!(validators.is_fresh(&request));
+        let request = TestRequest::get().insert_header(("If-None-Match", "\"wg1v2.0000000000000000\"")).to_http_request();
+        assert!(!validators.is_fresh(&request));
+
+        // Last-Modified is rounded down to the second
+        let request = TestRequest::get().insert_header(("If-Modified-Since", "Thu, 09 Oct 2025 08:53:20 GMT")).to_http_request();
+        assert!(validators.is_fresh(&request));
+        let request = TestRequest::get().insert_header(("If-Modified-Since", "Thu, 09 Oct 2025 08:53:19 GMT")).to_http_request();
+        assert!(!validators.is_fresh(&request));
+
+        let request = TestRequest::put().insert_header(("If-Match", format!("W/\"wg1v1.00\", {}", etag))).to_http_request();
+        assert_eq!(if_match_states(&request), Some(vec!["wg1v3".to_string()]));
+        assert_eq!(if_match_states(&TestRequest::put().insert_header(("If-Match", "*")).to_http_request()), None);
+        assert_eq!(if_match_states(&TestRequest::put().to_http_request()), None);
+    }
+}
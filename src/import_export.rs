@@ -0,0 +1,502 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::web::{Data, Path, Query};
+use chrono::Utc;
+use chrono_tz::Tz;
+use sqlx::{Arguments, Row};
+use sqlx::postgres::PgArguments;
+use tracing::instrument;
+use crate::database::Database;
+use crate::errors::ApiError;
+use crate::errors::ApiError::BadRequest;
+use crate::helpers::{format_datetime, respond_json, Timestamp, TimestampFormat};
+use crate::middleware_custom::Claims;
+use crate::preferences::load_user_preferences;
+use crate::server::AppState;
+use crate::smart_groups::ensure_manual_group;
+use crate::watchlist::{insert_watchlist_entries, WatchlistResponse};
+use crate::watchlistgroup::find_user_watchlist_group;
+
+const EXPORT_VERSION: u8 = 1;
+const CSV_HEADERS: [&str; 5] = ["group_id", "group_name", "asset_id", "symbol", "name"];
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    #[default]
+    Json,
+    Csv,
+    Symbols,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    format: Option<ExportFormat>,
+    time_format: Option<TimestampFormat>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    format: Option<ImportFormat>,
+    dry_run: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WatchlistExport {
+    version: u8,
+    exported_at: String,
+    groups: Vec<WatchlistGroupExport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WatchlistGroupExport {
+    id: i32,
+    name: String,
+    created_at: Option<Timestamp>,
+    assets: Vec<WatchlistResponse>,
+}
+
+// A single line of an import, pointing to an asset id, a ticker symbol or a slug
+#[derive(Debug, Deserialize, Clone, PartialEq, Default)]
+pub struct ImportEntry {
+    #[serde(default, alias = "asset_id")]
+    pub id: Option<i32>,
+    #[serde(default)]
+    pub symbol: Option<String>,
+    #[serde(default)]
+    pub slug: Option<String>,
+    // Base asset of an exchange pair such as `ETHUSDT`, only tried when `symbol` is not a known asset
+    #[serde(skip)]
+    pub pair_base: Option<String>,
+    // The raw value as it appeared in the uploaded file, used for reporting
+    #[serde(skip)]
+    pub source: Option<String>,
+}
+
+impl ImportEntry {
+    pub fn from_symbol(symbol: &str) -> Self {
+        ImportEntry { symbol: Some(symbol.trim().to_uppercase()), ..Default::default() }
+    }
+
+    // Symbols to match against the assets, the exact symbol first so `WBTC` is never read as a `W` pair
+    fn symbol_candidates(&self) -> impl Iterator<Item = String> + '_ {
+        self.symbol.iter().chain(self.pair_base.iter()).map(|symbol| symbol.to_uppercase())
+    }
+
+    fn label(&self) -> String {
+        match (&self.source, &self.symbol, &self.slug, self.id) {
+            (Some(source), _, _, _) => source.trim().to_string(),
+            (None, Some(symbol), _, _) => symbol.trim().to_uppercase(),
+            (None, None, Some(slug), _) => slug.trim().to_lowercase(),
+            (None, None, None, Some(id)) => format!("#{}", id),
+            (None, None, None, None) => String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportDocument {
+    groups: Vec<ImportDocumentGroup>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportDocumentGroup {
+    assets: Vec<ImportEntry>,
+}
+
+// Accepts our own export document, a list of entries or a plain list of symbols
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonImportPayload {
+    Document(ImportDocument),
+    Entries(Vec<ImportEntry>),
+    Symbols(Vec<String>),
+}
+
+// Outcome of matching import entries against the `assets` table
+#[derive(Debug, Default)]
+pub struct ResolvedImport {
+    pub assets: Vec<WatchlistResponse>,
+    pub duplicates: Vec<String>,
+    pub unknown: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    group_id: i32,
+    dry_run: bool,
+    // Assets that were (or, on a dry run, would be) added to the group
+    added: Vec<WatchlistResponse>,
+    already_present: Vec<WatchlistResponse>,
+    duplicates: Vec<String>,
+    unknown: Vec<String>,
+}
+
+pub fn parse_import_payload(format: ImportFormat, body: &str) -> Result<Vec<ImportEntry>, ApiError> {
+    let entries = match format {
+        ImportFormat::Json => parse_json(body)?,
+        ImportFormat::Csv => parse_csv(body)?,
+        ImportFormat::Symbols => parse_symbols(body),
+    };
+
+    Ok(without_empty_entries(entries))
+}
+
+pub fn without_empty_entries(entries: Vec<ImportEntry>) -> Vec<ImportEntry> {
+    entries
+        .into_iter()
+        .filter(|entry| entry.id.is_some() || entry.symbol.is_some() || entry.slug.is_some())
+        .collect()
+}
+
+fn parse_json(body: &str) -> Result<Vec<ImportEntry>, ApiError> {
+    let payload: JsonImportPayload = serde_json::from_str(body)
+        .map_err(|err| BadRequest(format!("Invalid JSON import: {}", err)))?;
+
+    Ok(match payload {
+        JsonImportPayload::Document(document) => document.groups
+            .into_iter()
+            .flat_map(|group| group.assets)
+            .collect(),
+        JsonImportPayload::Entries(entries) => entries,
+        JsonImportPayload::Symbols(symbols) => symbols
+            .iter()
+            .map(|symbol| ImportEntry::from_symbol(symbol))
+            .collect(),
+    })
+}
+
+fn parse_csv(body: &str) -> Result<Vec<ImportEntry>, ApiError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes());
+
+    let headers = reader.headers()
+        .map_err(|err| BadRequest(format!("Invalid CSV import: {}", err)))?
+        .clone();
+    let column = |name: &str| headers.iter().position(|header| header.eq_ignore_ascii_case(name));
+    let symbol_column = column("symbol");
+    let id_column = column("asset_id").or_else(|| column("id"));
+
+    if symbol_column.is_none() && id_column.is_none() {
+        return Err(BadRequest("CSV import must contain a symbol or asset_id column".into()));
+    }
+
+    let mut entries = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|err| BadRequest(format!("Invalid CSV import: {}", err)))?;
+        let id = id_column
+            .and_then(|index| record.get(index))
+            .filter(|value| !value.is_empty())
+            .map(|value| value.parse::<i32>()
+                .map_err(|_| BadRequest(format!("Invalid asset_id in CSV import: {}", value))))
+            .transpose()?;
+        let symbol = symbol_column
+            .and_then(|index| record.get(index))
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_uppercase());
+
+        entries.push(ImportEntry { id, symbol, ..Default::default() });
+    }
+
+    Ok(entries)
+}
+
+fn parse_symbols(body: &str) -> Vec<ImportEntry> {
+    body
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter(|symbol| !symbol.is_empty())
+        .map(ImportEntry::from_symbol)
+        .collect()
+}
+
+// Match import entries to assets. Ids win over symbols, symbols win over slugs and, when
+// several assets share a symbol, the best ranked listing is picked.
+#[instrument]
+pub async fn resolve_import_entries(
+    db: &Arc<dyn Database>,
+    entries: Vec<ImportEntry>,
+) -> Result<ResolvedImport, ApiError> {
+    let ids: Vec<i32> = entries.iter().filter_map(|entry| entry.id).collect();
+    let symbols: Vec<String> = entries.iter().flat_map(ImportEntry::symbol_candidates).collect();
+    let slugs: Vec<String> = entries.iter()
+        .filter_map(|entry| entry.slug.as_ref().map(|slug| slug.to_lowercase()))
+        .collect();
+
+    let mut args = PgArguments::default();
+    args.add(&ids);
+    args.add(&symbols);
+    args.add(&slugs);
+    let records = db
+        .fetch_all(r#"SELECT id, name, symbol, slug, rank FROM assets
+                      WHERE id = ANY($1) OR UPPER(symbol) = ANY($2) OR LOWER(slug) = ANY($3)
+                      ORDER BY rank NULLS LAST, id"#, args)
+        .await?;
+
+    let mut by_id: HashMap<i32, WatchlistResponse> = HashMap::new();
+    let mut by_symbol: HashMap<String, WatchlistResponse> = HashMap::new();
+    let mut by_slug: HashMap<String, WatchlistResponse> = HashMap::new();
+    for record in &records {
+        let asset = WatchlistResponse::from_row(record);
+        let slug: Option<String> = record.get("slug");
+        if let Some(slug) = slug {
+            by_slug.entry(slug.to_lowercase()).or_insert_with(|| asset.clone());
+        }
+        by_symbol.entry(asset.symbol.to_uppercase()).or_insert_with(|| asset.clone());
+        by_id.insert(asset.id, asset);
+    }
+
+    let mut resolved = ResolvedImport::default();
+    let mut seen: HashSet<i32> = HashSet::new();
+    for entry in entries {
+        let asset = entry.id.and_then(|id| by_id.get(&id))
+            .or_else(|| entry.symbol_candidates().find_map(|symbol| by_symbol.get(&symbol)))
+            .or_else(|| entry.slug.as_ref().and_then(|slug| by_slug.get(&slug.to_lowercase())));
+
+        match asset {
+            Some(asset) if !seen.insert(asset.id) => resolved.duplicates.push(entry.label()),
+            Some(asset) => resolved.assets.push(asset.clone()),
+            None => resolved.unknown.push(entry.label()),
+        }
+    }
+
+    Ok(resolved)
+}
+
+#[instrument]
+async fn fetch_export(
+    db: &Arc<dyn Database>,
+    user_id: i32,
+    group_id: Option<i32>,
+    timezone: Tz,
+    format: TimestampFormat,
+) -> Result<WatchlistExport, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    args.add(group_id);
+
+    let records = db
+        .fetch_all(r#"SELECT wg.id AS group_id, wg.name AS group_name, wg.created_at, a.id, a.name, a.symbol, a.rank, w.added_at
+                      FROM watchlist_groups wg
+                      LEFT JOIN watchlist w ON w.group_id = wg.id
+                      LEFT JOIN assets a ON a.id = w.asset_id
+                      WHERE wg.user_id = $1 AND wg.deleted_at IS NULL AND ($2::int IS NULL OR wg.id = $2)
+                      ORDER BY wg.id, a.id"#, args)
+        .await?;
+
+    let mut groups: Vec<WatchlistGroupExport> = vec![];
+    for record in &records {
+        let group_id: i32 = record.get("group_id");
+        if groups.last().map(|group| group.id) != Some(group_id) {
+            groups.push(WatchlistGroupExport {
+                id: group_id,
+                name: record.get("group_name"),
+                created_at: format_datetime(record.get("created_at"), timezone, format),
+                assets: vec![],
+            });
+        }
+
+        let asset_id: Option<i32> = record.get("id");
+        if asset_id.is_some() {
+            if let Some(group) = groups.last_mut() {
+                group.assets.push(WatchlistResponse::from_row(record).with_added_at(record.get("added_at"), timezone, format));
+            }
+        }
+    }
+
+    Ok(WatchlistExport {
+        version: EXPORT_VERSION,
+        exported_at: Utc::now().to_rfc3339(),
+        groups,
+    })
+}
+
+fn to_csv(export: &WatchlistExport) -> Result<Vec<u8>, ApiError> {
+    let csv_error = |err: csv::Error| ApiError::SerdeError(err.to_string());
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(CSV_HEADERS).map_err(csv_error)?;
+
+    for group in &export.groups {
+        let group_id = group.id.to_string();
+        if group.assets.is_empty() {
+            writer.write_record([group_id.as_str(), &group.name, "", "", ""]).map_err(csv_error)?;
+        }
+        for asset in &group.assets {
+            writer
+                .write_record([group_id.as_str(), &group.name, &asset.id.to_string(), &asset.symbol, &asset.name])
+                .map_err(csv_error)?;
+        }
+    }
+
+    writer.into_inner().map_err(|err| ApiError::SerdeError(err.to_string()))
+}
+
+fn respond_export(export: WatchlistExport, format: ExportFormat, file_name: &str) -> Result<HttpResponse, ApiError> {
+    let (content_type, extension, body) = match format {
+        ExportFormat::Json => (ContentType::json().to_string(), "json", serde_json::to_vec(&export)?),
+        ExportFormat::Csv => ("text/csv; charset=utf-8".to_string(), "csv", to_csv(&export)?),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!("{}.{}", file_name, extension))],
+        })
+        .body(body))
+}
+
+#[instrument]
+pub async fn export_all_watchlist_groups(
+    state: Data<AppState>,
+    query: Query<ExportQuery>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let export = fetch_export(&state.db, user_id, None, timezone, query.time_format.unwrap_or_default()).await?;
+
+    respond_export(export, query.format.unwrap_or_default(), "watchlists")
+}
+
+#[instrument]
+pub async fn export_watchlist_group(
+    state: Data<AppState>,
+    query: Query<ExportQuery>,
+    request: HttpRequest,
+    path: Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let group_id = path.into_inner();
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let export = fetch_export(&state.db, user_id, Some(group_id), timezone, query.time_format.unwrap_or_default()).await?;
+
+    if export.groups.is_empty() {
+        return Err(ApiError::NotFound);
+    }
+
+    respond_export(export, query.format.unwrap_or_default(), &format!("watchlist-{}", group_id))
+}
+
+#[instrument(skip(body))]
+pub async fn import_watchlist_group(
+    state: Data<AppState>,
+    query: Query<ImportQuery>,
+    request: HttpRequest,
+    path: Path<i32>,
+    body: String,
+) -> Result<actix_web::web::Json<ImportReport>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let group_id = path.into_inner();
+    let dry_run = query.dry_run.unwrap_or(false);
+    find_user_watchlist_group(&state.db, user_id, group_id).await?;
+    ensure_manual_group(&state.db, group_id).await?;
+
+    let entries = parse_import_payload(query.format.unwrap_or_default(), &body)?;
+    if entries.is_empty() {
+        return Err(BadRequest("The import does not contain any symbols".into()));
+    }
+    let resolved = resolve_import_entries(&state.db, entries).await?;
+
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    let existing: HashSet<i32> = state.db
+        .fetch_all("SELECT asset_id FROM watchlist WHERE group_id = $1", args)
+        .await?
+        .iter()
+        .map(|record| record.get("asset_id"))
+        .collect();
+
+    let (already_present, added): (Vec<WatchlistResponse>, Vec<WatchlistResponse>) = resolved.assets
+        .into_iter()
+        .partition(|asset| existing.contains(&asset.id));
+
+    if !dry_run && !added.is_empty() {
+        let asset_ids: Vec<i32> = added.iter().map(|asset| asset.id).collect();
+        insert_watchlist_entries(&state.db, group_id, &asset_ids).await?;
+
+        state.redis_client.del(format!("all_watchlist::{}", group_id)).await;
+        state.redis_client.del(format!("group_analytics::{}", group_id)).await;
+        state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
+    }
+
+    respond_json(ImportReport {
+        group_id,
+        dry_run,
+        added,
+        already_present,
+        duplicates: resolved.duplicates,
+        unknown: resolved.unknown,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_parse_symbols() {
+        let entries = parse_import_payload(ImportFormat::Symbols, "btc, eth\nsol;;  doge").unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                ImportEntry::from_symbol("BTC"),
+                ImportEntry::from_symbol("ETH"),
+                ImportEntry::from_symbol("SOL"),
+                ImportEntry::from_symbol("DOGE"),
+            ]
+        )
+    }
+
+    #[test]
+    fn test_unit_parse_csv_export() {
+        let body = "group_id,group_name,asset_id,symbol,name\n1,Main,1,BTC,Bitcoin\n1,Main,,eth,Ethereum\n2,Empty,,,\n";
+        let entries = parse_import_payload(ImportFormat::Csv, body).unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                ImportEntry { id: Some(1), symbol: Some("BTC".into()), ..Default::default() },
+                ImportEntry::from_symbol("ETH"),
+            ]
+        )
+    }
+
+    #[test]
+    fn test_unit_symbol_candidates() {
+        let entry = ImportEntry { symbol: Some("busd".into()), pair_base: Some("B".into()), ..Default::default() };
+
+        assert_eq!(entry.symbol_candidates().collect::<Vec<_>>(), vec!["BUSD", "B"]);
+        assert_eq!(ImportEntry::from_symbol("wbtc").symbol_candidates().collect::<Vec<_>>(), vec!["WBTC"]);
+    }
+
+    #[test]
+    fn test_unit_parse_csv_without_symbol_column() {
+        let result = parse_import_payload(ImportFormat::Csv, "ticker\nBTC\n");
+
+        assert!(result.is_err())
+    }
+
+    #[test]
+    fn test_unit_parse_json_variants() {
+        let document = r#"{"version":1,"groups":[{"id":1,"name":"Main","assets":[{"id":1,"symbol":"BTC","name":"Bitcoin"}]}]}"#;
+        let symbols = r#"["btc", "eth"]"#;
+        let objects = r#"[{"symbol":"sol"},{"asset_id":1027}]"#;
+
+        assert_eq!(parse_import_payload(ImportFormat::Json, document).unwrap().len(), 1);
+        assert_eq!(parse_import_payload(ImportFormat::Json, symbols).unwrap()[1], ImportEntry::from_symbol("ETH"));
+        assert_eq!(parse_import_payload(ImportFormat::Json, objects).unwrap()[1].id, Some(1027));
+    }
+}
@@ -0,0 +1,319 @@
+use std::str::FromStr;
+use actix_multipart::Multipart;
+use actix_web::{HttpMessage, HttpRequest};
+use actix_web::web::{Data, Json};
+use futures_util::StreamExt;
+use tracing::instrument;
+use crate::errors::ApiError;
+use crate::errors::ApiError::BadRequest;
+use crate::helpers::{respond_json, TimestampFormat};
+use crate::import_export::{resolve_import_entries, without_empty_entries, ImportEntry};
+use crate::middleware_custom::Claims;
+use crate::preferences::load_user_preferences;
+use crate::server::AppState;
+use crate::watchlist::{insert_watchlist_entries, WatchlistResponse};
+use crate::watchlistgroup::{insert_watchlist_group, WatchlistGroupResponse};
+
+const MAX_IMPORT_FILE_SIZE: usize = 1024 * 1024;
+
+// Quote currencies stripped from trading pairs, longest first so `USDT` wins over `USD`
+const QUOTE_ASSETS: [&str; 17] = [
+    "FDUSD", "USDT", "USDC", "BUSD", "TUSD", "USDP", "DAI", "USD", "EUR", "GBP", "TRY", "BRL", "JPY", "IDR",
+    "BTC", "ETH", "BNB",
+];
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportSource {
+    Binance,
+    CoinGecko,
+    TradingView,
+}
+
+impl ImportSource {
+    fn label(&self) -> &'static str {
+        match self {
+            ImportSource::Binance => "Binance",
+            ImportSource::CoinGecko => "CoinGecko",
+            ImportSource::TradingView => "TradingView",
+        }
+    }
+}
+
+impl FromStr for ImportSource {
+    type Err = ApiError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "binance" => Ok(ImportSource::Binance),
+            "coingecko" => Ok(ImportSource::CoinGecko),
+            "tradingview" => Ok(ImportSource::TradingView),
+            other => Err(BadRequest(format!("Unsupported import source: {}", other))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExchangeImportReport {
+    source: ImportSource,
+    group: WatchlistGroupResponse,
+    matched: Vec<WatchlistResponse>,
+    unmatched: Vec<String>,
+    duplicates: Vec<String>,
+}
+
+// Reduce an exchange ticker such as `BINANCE:ETHUSDT`, `BTC/USDT` or `BTCUSDT.P` to the symbol it names.
+// Quote currencies are only stripped from pair-shaped values, into `pair_base`, so a listed symbol such
+// as `WBTC` or `BUSD` is still matched as is.
+fn ticker_entry(raw: &str, is_pair: bool) -> Option<ImportEntry> {
+    let value = raw.trim().to_uppercase();
+    let ticker = value.rsplit(':').next()?;
+    let ticker = ticker.split('.').next()?;
+
+    let (symbol, pair_base) = if ticker.contains(['/', '-', '_']) {
+        (ticker.split(['/', '-', '_']).next()?, None)
+    } else if is_pair || value.contains(':') {
+        (ticker, strip_quote_asset(ticker))
+    } else {
+        (ticker, None)
+    };
+
+    Some(ImportEntry {
+        symbol: Some(symbol.to_string()).filter(|symbol| !symbol.is_empty()),
+        pair_base: pair_base.map(str::to_string),
+        source: Some(raw.trim().to_string()),
+        ..Default::default()
+    })
+}
+
+fn strip_quote_asset(pair: &str) -> Option<&str> {
+    QUOTE_ASSETS.iter().find_map(|quote| pair.strip_suffix(quote).filter(|base| !base.is_empty()))
+}
+
+fn csv_reader(body: &str) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes())
+}
+
+fn find_column(headers: &csv::StringRecord, candidates: &[&str]) -> Option<usize> {
+    candidates.iter()
+        .find_map(|candidate| headers.iter().position(|header| header.eq_ignore_ascii_case(candidate)))
+}
+
+fn csv_error(err: csv::Error) -> ApiError {
+    BadRequest(format!("Invalid CSV file: {}", err))
+}
+
+// Binance exports trade history and favourites with the pair in a `Pair`/`Market` column, and balances
+// with a `Coin`/`Asset` column. Files without a recognised header are treated as a plain list of pairs.
+fn parse_binance(body: &str) -> Result<Vec<ImportEntry>, ApiError> {
+    let mut reader = csv_reader(body);
+    let headers = reader.headers().map_err(csv_error)?.clone();
+
+    let (column, is_pair) = match find_column(&headers, &["pair", "market"]) {
+        Some(column) => (column, true),
+        None => match find_column(&headers, &["symbol", "coin", "asset"]) {
+            Some(column) => (column, false),
+            None => return Ok(parse_ticker_list(body, true)),
+        },
+    };
+
+    let mut entries = vec![];
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        if let Some(entry) = record.get(column).and_then(|value| ticker_entry(value, is_pair)) {
+            entries.push(entry);
+        }
+    }
+
+    Ok(entries)
+}
+
+// CoinGecko portfolio exports carry the coin symbol and the CoinGecko API id, which matches our slugs
+fn parse_coingecko(body: &str) -> Result<Vec<ImportEntry>, ApiError> {
+    let mut reader = csv_reader(body);
+    let headers = reader.headers().map_err(csv_error)?.clone();
+    let symbol_column = find_column(&headers, &["symbol", "coin symbol"]);
+    let slug_column = find_column(&headers, &["id", "coin id", "api id", "coingecko id"]);
+
+    if symbol_column.is_none() && slug_column.is_none() {
+        return Err(BadRequest("CoinGecko export must contain a Symbol or Coin ID column".into()));
+    }
+
+    let mut entries = vec![];
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let symbol = symbol_column
+            .and_then(|index| record.get(index))
+            .filter(|value| !value.is_empty());
+        let slug = slug_column
+            .and_then(|index| record.get(index))
+            .filter(|value| !value.is_empty());
+
+        entries.push(ImportEntry {
+            symbol: symbol.map(str::to_uppercase),
+            slug: slug.map(str::to_lowercase),
+            source: symbol.or(slug).map(str::to_string),
+            ..Default::default()
+        });
+    }
+
+    Ok(entries)
+}
+
+// TradingView watchlist exports are comma separated `EXCHANGE:PAIR` values with `###Section` markers
+fn parse_ticker_list(body: &str, is_pair: bool) -> Vec<ImportEntry> {
+    body
+        .split([',', '\n', '\r'])
+        .map(str::trim)
+        .filter(|value| !value.is_empty() && !value.starts_with("###"))
+        .filter_map(|value| ticker_entry(value, is_pair))
+        .collect()
+}
+
+pub fn parse_source_file(source: ImportSource, body: &str) -> Result<Vec<ImportEntry>, ApiError> {
+    let entries = match source {
+        ImportSource::Binance => parse_binance(body)?,
+        ImportSource::CoinGecko => parse_coingecko(body)?,
+        ImportSource::TradingView => parse_ticker_list(body, false),
+    };
+
+    Ok(without_empty_entries(entries))
+}
+
+fn multipart_error(err: actix_multipart::MultipartError) -> ApiError {
+    BadRequest(format!("Invalid multipart upload: {}", err))
+}
+
+// Multipart fields: `source` (binance, coingecko or tradingview), `file`, and an optional group `name`
+#[instrument(skip(payload))]
+pub async fn import_watchlist_group_from_file(
+    state: Data<AppState>,
+    request: HttpRequest,
+    mut payload: Multipart,
+) -> Result<Json<ExchangeImportReport>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let mut source: Option<ImportSource> = None;
+    let mut name: Option<String> = None;
+    let mut file: Vec<u8> = vec![];
+
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(multipart_error)?;
+        let field_name = field.name().to_string();
+        let mut value: Vec<u8> = vec![];
+        while let Some(chunk) = field.next().await {
+            value.extend_from_slice(&chunk.map_err(multipart_error)?);
+            if value.len() > MAX_IMPORT_FILE_SIZE {
+                return Err(BadRequest("The uploaded file is too large".into()));
+            }
+        }
+
+        match field_name.as_str() {
+            "source" => source = Some(String::from_utf8_lossy(&value).parse()?),
+            "name" => name = Some(String::from_utf8_lossy(&value).trim().to_string()).filter(|name| !name.is_empty()),
+            "file" => file = value,
+            _ => {}
+        }
+    }
+
+    let source = source.ok_or(BadRequest("Missing the import source".into()))?;
+    let entries = parse_source_file(source, &String::from_utf8_lossy(&file))?;
+    if entries.is_empty() {
+        return Err(BadRequest("The uploaded file does not contain any symbols".into()));
+    }
+
+    let resolved = resolve_import_entries(&state.db, entries).await?;
+    if resolved.assets.is_empty() {
+        return Err(BadRequest(format!("None of the {} entries matched a known asset", resolved.unknown.len())));
+    }
+
+    let name = name.unwrap_or_else(|| format!("Imported from {}", source.label()));
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let group = insert_watchlist_group(&state.db, user_id, &name, timezone, TimestampFormat::default()).await?;
+    let asset_ids: Vec<i32> = resolved.assets.iter().map(|asset| asset.id).collect();
+    insert_watchlist_entries(&state.db, group.id, &asset_ids).await?;
+
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
+
+    respond_json(ExchangeImportReport {
+        source,
+        group,
+        matched: resolved.assets,
+        unmatched: resolved.unknown,
+        duplicates: resolved.duplicates,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticker(raw: &str, is_pair: bool) -> (Option<String>, Option<String>) {
+        ticker_entry(raw, is_pair)
+            .map(|entry| (entry.symbol, entry.pair_base))
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_unit_ticker_entry_pairs() {
+        assert_eq!(ticker("BTCUSDT", true), (Some("BTCUSDT".into()), Some("BTC".into())));
+        assert_eq!(ticker("BINANCE:ethusdt", false), (Some("ETHUSDT".into()), Some("ETH".into())));
+        assert_eq!(ticker("BYBIT:SOLUSDT.P", false), (Some("SOLUSDT".into()), Some("SOL".into())));
+        assert_eq!(ticker("COINBASE:BTC-USD", false), (Some("BTC".into()), None));
+        assert_eq!(ticker("ETH/BTC", false), (Some("ETH".into()), None));
+        assert_eq!(ticker("USDCUSDT", true), (Some("USDCUSDT".into()), Some("USDC".into())));
+        assert_eq!(ticker("  ", true), (None, None));
+    }
+
+    #[test]
+    fn test_unit_ticker_entry_bare_symbols() {
+        for symbol in ["BTC", "WBTC", "BUSD", "TUSD", "FDUSD", "STETH"] {
+            assert_eq!(ticker(symbol, false), (Some(symbol.into()), None));
+        }
+    }
+
+    #[test]
+    fn test_unit_parse_tradingview() {
+        let entries = parse_source_file(ImportSource::TradingView, "###Majors,BINANCE:BTCUSDT,BINANCE:ETHUSDT\n###Alts,KRAKEN:SOLUSD").unwrap();
+        let bases: Vec<String> = entries.iter().filter_map(|entry| entry.pair_base.clone()).collect();
+
+        assert_eq!(bases, vec!["BTC", "ETH", "SOL"]);
+        assert_eq!(entries[0].source, Some("BINANCE:BTCUSDT".into()));
+    }
+
+    #[test]
+    fn test_unit_parse_binance() {
+        let body = "Date(UTC),Pair,Side,Price\n2024-05-01 10:00:00,BTCUSDT,BUY,60000\n2024-05-01 11:00:00,ETHBTC,SELL,0.05\n";
+        let entries = parse_source_file(ImportSource::Binance, body).unwrap();
+        let symbols: Vec<String> = entries.iter().filter_map(|entry| entry.symbol.clone()).collect();
+
+        assert_eq!(symbols, vec!["BTCUSDT", "ETHBTC"]);
+        assert_eq!(entries[1].pair_base, Some("ETH".into()));
+    }
+
+    #[test]
+    fn test_unit_parse_binance_coins() {
+        let body = "Coin,Free,Locked
+WBTC,0.1,0
+BUSD,100,0
+TUSD,50,0
+STETH,2,0
+";
+        let entries = parse_source_file(ImportSource::Binance, body).unwrap();
+        let symbols: Vec<String> = entries.iter().filter_map(|entry| entry.symbol.clone()).collect();
+
+        assert_eq!(symbols, vec!["WBTC", "BUSD", "TUSD", "STETH"]);
+        assert!(entries.iter().all(|entry| entry.pair_base.is_none()));
+    }
+
+    #[test]
+    fn test_unit_parse_coingecko() {
+        let body = "Coin ID,Symbol,Holdings\nbitcoin,btc,0.5\nwrapped-bitcoin,wbtc,1\n";
+        let entries = parse_source_file(ImportSource::CoinGecko, body).unwrap();
+
+        assert_eq!(entries[1].symbol, Some("WBTC".into()));
+        assert_eq!(entries[1].slug, Some("wrapped-bitcoin".into()));
+    }
+}
@@ -4,12 +4,29 @@ mod config;
 mod data_provider;
 mod routes;
 mod health;
+mod metrics;
 mod errors;
 mod helpers;
+mod http_cache;
 mod watchlist;
 mod watchlistgroup;
 mod middleware_custom;
 mod cache;
+mod redis_pool;
+mod candles;
+mod import_export;
+mod import_formats;
+mod pagination;
+mod analytics;
+mod portfolio;
+mod currency;
+mod preferences;
+mod notifications;
+mod bots;
+mod screener;
+mod categories;
+mod smart_groups;
+mod telemetry;
 
 #[macro_use]
 extern crate lazy_static;
@@ -0,0 +1,141 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+use actix_web::HttpResponse;
+use actix_web::http::header;
+use actix_web::web::Data;
+use actix_web::HttpRequest;
+use prometheus::{exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use crate::config::CONFIG;
+use crate::errors::ApiError;
+use crate::server::AppState;
+
+pub const METRICS_PATH: &str = "/metrics";
+
+// Label of the requests that matched no route, so probes of random paths add no series
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new();
+
+    static ref HTTP_REQUESTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("http_requests_total", "HTTP requests by route pattern, method and status"),
+        &["route", "method", "status"],
+    ));
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("http_request_duration_seconds", "Time to respond to HTTP requests by route pattern and method"),
+        &["route", "method"],
+    ));
+    static ref CACHE_LOOKUPS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("cache_lookups_total", "Cache lookups by key family and result: local_hit, hit, miss, error or bypassed"),
+        &["family", "result"],
+    ));
+    static ref REDIS_COMMAND_ERRORS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("redis_command_errors_total", "Failed Redis commands by kind: remote, topology_change or connection"),
+        &["kind"],
+    ));
+    static ref DB_QUERY_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("db_query_duration_seconds", "Time to run Postgres queries by operation and outcome"),
+        &["operation", "outcome"],
+    ));
+    static ref DB_POOL_CONNECTIONS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new("db_pool_connections", "Postgres pool connections by state: idle, in_use or max"),
+        &["state"],
+    ));
+    static ref DATA_PROVIDER_REQUESTS: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("data_provider_requests_total", "CoinMarketCap requests by endpoint and outcome"),
+        &["endpoint", "outcome"],
+    ));
+    static ref ASSET_SYNC_DURATION: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new("asset_sync_duration_seconds", "Duration of the asset data syncs by job and outcome")
+            .buckets(exponential_buckets(0.5, 2.0, 12).unwrap()),
+        &["job", "outcome"],
+    ));
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(collector: Result<T, prometheus::Error>) -> T {
+    let collector = collector.expect("Invalid metric definition");
+    REGISTRY.register(Box::new(collector.clone())).expect("Metric registered twice");
+    collector
+}
+
+fn outcome<T, E>(result: &Result<T, E>) -> &'static str {
+    if result.is_ok() { "success" } else { "error" }
+}
+
+// `route` is the matched pattern, e.g. `/api/v1/watchlistgroup/{id}`, never the raw path
+pub fn record_http_request(route: Option<&str>, method: &str, status: u16, elapsed: Duration) {
+    let route = route.unwrap_or(UNMATCHED_ROUTE);
+    HTTP_REQUESTS.with_label_values(&[route, method, &status.to_string()]).inc();
+    HTTP_REQUEST_DURATION.with_label_values(&[route, method]).observe(elapsed.as_secs_f64());
+}
+
+pub fn record_cache_lookup(family: &str, result: &str) {
+    CACHE_LOOKUPS.with_label_values(&[family, result]).inc();
+}
+
+pub fn record_redis_error(kind: &str) {
+    REDIS_COMMAND_ERRORS.with_label_values(&[kind]).inc();
+}
+
+pub fn record_db_query<T, E>(operation: &str, result: &Result<T, E>, elapsed: Duration) {
+    DB_QUERY_DURATION.with_label_values(&[operation, outcome(result)]).observe(elapsed.as_secs_f64());
+}
+
+pub fn record_data_provider_request(endpoint: &str, outcome: &str) {
+    DATA_PROVIDER_REQUESTS.with_label_values(&[endpoint, outcome]).inc();
+}
+
+// Run one of the asset data syncs, recording how long it took
+pub async fn time_sync<T, E>(job: &str, sync: impl Future<Output = Result<T, E>>) -> Result<T, E> {
+    let started = Instant::now();
+    let result = sync.await;
+    ASSET_SYNC_DURATION.with_label_values(&[job, outcome(&result)]).observe(started.elapsed().as_secs_f64());
+    result
+}
+
+fn encode() -> Result<String, ApiError> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .map_err(|_| ApiError::InternalServerError)?;
+    String::from_utf8(buffer).map_err(|_| ApiError::InternalServerError)
+}
+
+// Handler to scrape the metrics in the Prometheus text format. When `metrics_token` is set it must
+// be sent as a bearer token, the JWT of the users is not required.
+pub async fn get_metrics(request: HttpRequest, state: Data<AppState>) -> Result<HttpResponse, ApiError> {
+    if !CONFIG.metrics_token.is_empty() {
+        let authorization = request.headers().get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+        if authorization != Some(format!("Bearer {}", CONFIG.metrics_token).as_str()) {
+            return Err(ApiError::InvalidToken);
+        }
+    }
+
+    // Pool usage is only sampled when scraped
+    let usage = state.db.pool_usage();
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(usage.idle as i64);
+    DB_POOL_CONNECTIONS.with_label_values(&["in_use"]).set(usage.in_use as i64);
+    DB_POOL_CONNECTIONS.with_label_values(&["max"]).set(usage.max as i64);
+
+    Ok(HttpResponse::Ok()
+        .content_type(TextEncoder::new().format_type())
+        .body(encode()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_metrics_exposition() {
+        record_http_request(Some("/api/v1/watchlistgroup/{id}"), "GET", 200, Duration::from_millis(12));
+        record_http_request(None, "GET", 404, Duration::from_millis(1));
+        record_cache_lookup("all_watchlist_group", "hit");
+
+        let text = encode().unwrap();
+        assert!(text.contains(r#"http_requests_total{method="GET",route="/api/v1/watchlistgroup/{id}",status="200"} 1"#));
+        assert!(text.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#));
+        assert!(text.contains(r#"http_request_duration_seconds_bucket{method="GET",route="/api/v1/watchlistgroup/{id}",le="0.025"} 1"#));
+        assert!(text.contains(r#"cache_lookups_total{family="all_watchlist_group",result="hit"} 1"#));
+    }
+}
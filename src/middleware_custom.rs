@@ -1,13 +1,14 @@
 use std::future::{ready, Ready};
 use std::rc::Rc;
-use actix_web::{dev::{Service, ServiceRequest, ServiceResponse, Transform}, Error, HttpMessage, HttpResponse};
+use std::time::Instant;
+use actix_web::{dev::{Service, ServiceRequest, ServiceResponse, Transform}, Error, HttpMessage};
 use futures_util::future::LocalBoxFuture;
 use jsonwebtoken::{decode, DecodingKey, Validation, TokenData, Algorithm, errors::ErrorKind};
 use serde::{Deserialize, Serialize};
-use log::{debug, error};
 use actix_web::dev::forward_ready;
-use crate::config::CONFIG;
+use crate::bots::BOT_HOOKS_PATH;
 use crate::errors::ApiError;
+use crate::metrics::{record_http_request, METRICS_PATH};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -64,7 +65,7 @@ impl<S, B> Service<ServiceRequest> for JwtMiddlewareMiddleware<S>
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        if req.path() == "/health" {
+        if req.path() == "/health" || req.path() == METRICS_PATH || req.path().starts_with("/health/") || req.path().starts_with(BOT_HOOKS_PATH) {
             return Box::pin(self.service.call(req));
         }
 
@@ -111,3 +112,59 @@ impl<S, B> Service<ServiceRequest> for JwtMiddlewareMiddleware<S>
         })
     }
 }
+
+// Counts and times every request per route pattern. Wrapped outside the JWT middleware so rejected
+// requests are counted too.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+    where
+        S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Resolved from the app's resource map, so it is known before routing and for rejected requests
+        let route = req.match_pattern();
+        let method = req.method().to_string();
+        let started = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let result = fut.await;
+            let status = match &result {
+                Ok(res) => res.status(),
+                Err(err) => err.as_response_error().status_code(),
+            };
+            record_http_request(route.as_deref(), &method, status.as_u16(), started.elapsed());
+            result
+        })
+    }
+}
@@ -0,0 +1,854 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use actix_web::web::{Data, Json, Path, Query};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use hmac::{Hmac, Mac};
+use hyper::client::connect::dns::Name;
+use lettre::{Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use log::{error, warn};
+use rand::RngCore;
+use reqwest::{Client, ClientBuilder, RequestBuilder, Url};
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use sha2::Sha256;
+use sqlx::{Arguments, Row};
+use sqlx::postgres::{PgArguments, PgRow};
+use tracing::field::Empty;
+use tracing::{instrument, Span};
+use uuid::Uuid;
+use crate::bots::{BotPlatform, DiscordSender, TelegramSender};
+use crate::config::CONFIG;
+use crate::database::{Database, SqlConditions};
+use crate::errors::ApiError;
+use crate::errors::ApiError::{BadRequest, InternalServerError};
+use crate::helpers::{format_datetime, respond_json, respond_ok, Timestamp, TimestampFormat, TimestampQuery};
+use crate::middleware_custom::Claims;
+use crate::pagination::{page_limit, paginate, KeysetSort, Page, SortOrder};
+use crate::preferences::{load_user_preferences, NotificationChannel};
+use crate::server::AppState;
+use crate::telemetry::{record_outcome, with_trace_context};
+
+const SIGNATURE_HEADER: &str = "X-Watchlist-Signature";
+const EVENT_HEADER: &str = "X-Watchlist-Event";
+const DELIVERY_HEADER: &str = "X-Watchlist-Delivery";
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// Something worth telling a user about, rendered by every channel they have enabled
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Notification {
+    pub kind: String,
+    pub title: String,
+    pub body: String,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+}
+
+// Where a channel delivers for one user, a webhook URL or an email address
+#[derive(Debug, Clone)]
+pub struct ChannelTarget {
+    pub address: String,
+    pub secret: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SendError {
+    message: String,
+    retryable: bool,
+    response_status: Option<u16>,
+}
+
+impl SendError {
+    pub fn new(message: impl Into<String>, retryable: bool) -> Self {
+        SendError { message: message.into(), retryable, response_status: None }
+    }
+}
+
+#[async_trait]
+pub trait NotificationSender: Send + Sync {
+    // Deliver once, returning the response status of the target when it has one
+    async fn send(&self, user_id: i32, target: Option<&ChannelTarget>, notification: &Notification) -> Result<Option<u16>, SendError>;
+}
+
+struct InAppSender {
+    db: Arc<dyn Database>,
+}
+
+#[async_trait]
+impl NotificationSender for InAppSender {
+    async fn send(&self, user_id: i32, _target: Option<&ChannelTarget>, notification: &Notification) -> Result<Option<u16>, SendError> {
+        let mut args = PgArguments::default();
+        args.add(user_id);
+        args.add(&notification.kind);
+        args.add(&notification.title);
+        args.add(&notification.body);
+        args.add(&notification.payload);
+
+        self.db
+            .execute("INSERT INTO notifications (user_id, kind, title, body, payload) VALUES ($1, $2, $3, $4, $5)", args)
+            .await
+            .map_err(|err| SendError::new(format!("Failed to store the notification: {}", err), true))?;
+        Ok(None)
+    }
+}
+
+struct WebhookSender {
+    client: Client,
+}
+
+#[async_trait]
+impl NotificationSender for WebhookSender {
+    async fn send(&self, _user_id: i32, target: Option<&ChannelTarget>, notification: &Notification) -> Result<Option<u16>, SendError> {
+        let target = target.ok_or_else(|| SendError::new("No webhook URL configured", false))?;
+        validate_target(NotificationChannel::Webhook, &target.address, CONFIG.webhook_allow_insecure_targets)
+            .map_err(|err| SendError::new(err.to_string(), false))?;
+        let delivery_id = Uuid::now_v7().to_string();
+        let body = serde_json::json!({
+            "id": delivery_id,
+            "kind": notification.kind,
+            "title": notification.title,
+            "body": notification.body,
+            "payload": notification.payload,
+            "sent_at": Utc::now().to_rfc3339(),
+        }).to_string();
+
+        let timestamp = Utc::now().timestamp();
+        let signature = sign_payload(target.secret.as_deref().unwrap_or_default(), timestamp, &body);
+
+        let request = self.client
+            .post(&target.address)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, format!("t={},v1={}", timestamp, signature))
+            .header(EVENT_HEADER, &notification.kind)
+            .header(DELIVERY_HEADER, delivery_id)
+            .body(body);
+        send_request(request, "Webhook").await
+    }
+}
+
+// Shared by the HTTP based senders, `what` names the receiver in error messages
+#[instrument(skip(request), fields(otel.kind = "client", http.status_code = Empty, otel.status_code = Empty, error.message = Empty))]
+pub async fn send_request(request: RequestBuilder, what: &str) -> Result<Option<u16>, SendError> {
+    let span = Span::current();
+    let result = with_trace_context(request).send().await;
+    record_outcome(&span, &result);
+    let response = result.map_err(|err| SendError::new(format!("{} request failed: {}", what, err), true))?;
+    span.record("http.status_code", response.status().as_u16());
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(Some(status.as_u16()));
+    }
+    Err(SendError {
+        message: format!("{} responded with {}", what, status),
+        // The receiver is overloaded or down, anything else will not change on a retry
+        retryable: status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+        response_status: Some(status.as_u16()),
+    })
+}
+
+pub fn http_client() -> Result<Client, reqwest::Error> {
+    ClientBuilder::new()
+        .timeout(Duration::from_secs(CONFIG.webhook_timeout_secs))
+        .build()
+}
+
+// Webhook URLs come from users, so redirects are not followed and hosts may only resolve to public
+// addresses, unless insecure targets are allowed to reach a local stand-in
+fn webhook_client(allow_insecure: bool) -> Result<Client, reqwest::Error> {
+    let builder = ClientBuilder::new()
+        .timeout(Duration::from_secs(CONFIG.webhook_timeout_secs))
+        .redirect(Policy::none());
+    if allow_insecure {
+        return builder.build();
+    }
+    builder.dns_resolver(Arc::new(PublicAddressResolver)).build()
+}
+
+// Resolves on every delivery, so a host that later points at the internal network is still refused
+struct PublicAddressResolver;
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses = resolve_public_addresses(name.as_str()).await?;
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+async fn resolve_public_addresses(host: &str) -> Result<Vec<SocketAddr>, Box<dyn Error + Send + Sync>> {
+    let lookup = host.trim_start_matches('[').trim_end_matches(']').to_string();
+    let addresses: Vec<SocketAddr> = actix_rt::task::spawn_blocking(move || (lookup.as_str(), 0).to_socket_addrs())
+        .await??
+        .filter(|address| is_public_address(address.ip()))
+        .collect();
+    if addresses.is_empty() {
+        return Err(format!("{} does not resolve to a public address", host).into());
+    }
+    Ok(addresses)
+}
+
+// Loopback, private, link-local and similar ranges are only reachable from inside our network
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let shared = ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64;
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()
+                || ip.is_multicast() || ip.is_documentation() || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(IpAddr::V4(ip)),
+            None => {
+                let unique_local = (ip.segments()[0] & 0xfe00) == 0xfc00;
+                let link_local = (ip.segments()[0] & 0xffc0) == 0xfe80;
+                !(ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || unique_local || link_local)
+            }
+        },
+    }
+}
+
+struct EmailSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+#[async_trait]
+impl NotificationSender for EmailSender {
+    async fn send(&self, _user_id: i32, target: Option<&ChannelTarget>, notification: &Notification) -> Result<Option<u16>, SendError> {
+        let target = target.ok_or_else(|| SendError::new("No email address configured", false))?;
+        let from = CONFIG.smtp_from.parse()
+            .map_err(|err| SendError::new(format!("Invalid SMTP_FROM address: {}", err), false))?;
+        let to = target.address.parse()
+            .map_err(|err| SendError::new(format!("Invalid email address: {}", err), false))?;
+
+        let message = Message::builder()
+            .from(from)
+            .to(to)
+            .subject(&notification.title)
+            .header(ContentType::TEXT_PLAIN)
+            .body(notification.body.clone())
+            .map_err(|err| SendError::new(format!("Failed to build the email: {}", err), false))?;
+
+        self.transport.send(message).await
+            .map_err(|err| SendError::new(format!("SMTP delivery failed: {}", err), !err.is_permanent()))?;
+        Ok(None)
+    }
+}
+
+fn smtp_transport() -> Result<AsyncSmtpTransport<Tokio1Executor>, lettre::transport::smtp::Error> {
+    let builder = if CONFIG.smtp_starttls {
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&CONFIG.smtp_host)?
+    } else {
+        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&CONFIG.smtp_host)
+    };
+
+    let mut builder = builder
+        .port(CONFIG.smtp_port)
+        .timeout(Some(Duration::from_secs(CONFIG.webhook_timeout_secs)));
+    let username = CONFIG.smtp_username.as_deref().filter(|username| !username.is_empty());
+    if let Some(username) = username {
+        builder = builder.credentials(Credentials::new(username.to_string(), CONFIG.smtp_password.clone().unwrap_or_default()));
+    }
+    Ok(builder.build())
+}
+
+fn sender_for(channel: NotificationChannel, db: &Arc<dyn Database>) -> Option<Box<dyn NotificationSender>> {
+    if channel == NotificationChannel::InApp {
+        return Some(Box::new(InAppSender { db: db.clone() }));
+    }
+    if channel == NotificationChannel::Email {
+        return smtp_transport()
+            .map_err(|err| error!("Failed to build the SMTP transport: {}", err))
+            .ok()
+            .map(|transport| Box::new(EmailSender { transport }) as Box<dyn NotificationSender>);
+    }
+
+    let client = match channel {
+        NotificationChannel::Webhook => webhook_client(CONFIG.webhook_allow_insecure_targets),
+        _ => http_client(),
+    };
+    let client = client
+        .map_err(|err| error!("Failed to build the notification HTTP client: {}", err))
+        .ok()?;
+    match channel {
+        NotificationChannel::Webhook => Some(Box::new(WebhookSender { client })),
+        NotificationChannel::Telegram if BotPlatform::Telegram.is_enabled() => Some(Box::new(TelegramSender { client })),
+        NotificationChannel::Discord if BotPlatform::Discord.is_enabled() => Some(Box::new(DiscordSender { client })),
+        _ => None,
+    }
+}
+
+// Whether the channel needs a per-user target before it can deliver
+fn requires_target(channel: NotificationChannel) -> bool {
+    channel != NotificationChannel::InApp
+}
+
+// Hex encoded HMAC-SHA256 of `{timestamp}.{body}`, receivers recompute it with their secret
+pub fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    to_hex(&mac.finalize().into_bytes())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 24];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("whsec_{}", to_hex(&bytes))
+}
+
+// Delay before retry number `attempt` (1 based), doubling each time up to `MAX_RETRY_DELAY`
+pub fn retry_delay(base: Duration, attempt: u32) -> Duration {
+    base.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))).min(MAX_RETRY_DELAY)
+}
+
+fn validate_target(channel: NotificationChannel, target: &str, allow_insecure: bool) -> Result<String, ApiError> {
+    let target = target.trim();
+    match channel {
+        NotificationChannel::Webhook => {
+            let url = Url::parse(target).map_err(|_| BadRequest(format!("Invalid webhook URL: {}", target)))?;
+            let secure = url.scheme() == "https" || (allow_insecure && url.scheme() == "http");
+            let Some(host) = url.host_str().filter(|_| secure) else {
+                return Err(BadRequest("Webhook URL must be an https:// URL".into()));
+            };
+            // Literal addresses never reach the resolver, host names are checked when they are resolved
+            let literal = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>();
+            if !allow_insecure && literal.is_ok_and(|ip| !is_public_address(ip)) {
+                return Err(BadRequest("Webhook URL must point to a public address".into()));
+            }
+            Ok(url.to_string())
+        }
+        NotificationChannel::Email => target.parse::<Address>()
+            .map(|address| address.to_string())
+            .map_err(|_| BadRequest(format!("Invalid email address: {}", target))),
+        NotificationChannel::Telegram | NotificationChannel::Discord => Err(BadRequest(format!("The {} channel is set up by linking an account with the bot", channel.code()))),
+        channel => Err(BadRequest(format!("The {} channel does not take a target", channel.code()))),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Delivered,
+    Failed,
+    Skipped,
+}
+
+impl DeliveryStatus {
+    // Value stored in `notification_deliveries.status`
+    fn code(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Skipped => "skipped",
+        }
+    }
+
+    fn from_code(code: &str) -> Self {
+        match code {
+            "delivered" => DeliveryStatus::Delivered,
+            "skipped" => DeliveryStatus::Skipped,
+            _ => DeliveryStatus::Failed,
+        }
+    }
+}
+
+struct DeliveryOutcome {
+    status: DeliveryStatus,
+    attempts: i32,
+    response_status: Option<i32>,
+    error: Option<String>,
+}
+
+impl DeliveryOutcome {
+    fn skipped(error: &str) -> Self {
+        DeliveryOutcome { status: DeliveryStatus::Skipped, attempts: 0, response_status: None, error: Some(error.to_string()) }
+    }
+}
+
+async fn send_with_retry(sender: &dyn NotificationSender, user_id: i32, target: Option<&ChannelTarget>, notification: &Notification) -> DeliveryOutcome {
+    let max_attempts = CONFIG.notification_max_attempts.max(1);
+    let base_delay = Duration::from_millis(CONFIG.notification_retry_base_delay_ms);
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        match sender.send(user_id, target, notification).await {
+            Ok(response_status) => return DeliveryOutcome {
+                status: DeliveryStatus::Delivered,
+                attempts: attempt as i32,
+                response_status: response_status.map(i32::from),
+                error: None,
+            },
+            Err(err) if !err.retryable || attempt >= max_attempts => return DeliveryOutcome {
+                status: DeliveryStatus::Failed,
+                attempts: attempt as i32,
+                response_status: err.response_status.map(i32::from),
+                error: Some(err.message),
+            },
+            Err(err) => {
+                warn!("Notification delivery attempt {} for user {} failed: {}", attempt, user_id, err.message);
+                actix_rt::time::sleep(retry_delay(base_delay, attempt)).await;
+            }
+        }
+    }
+}
+
+async fn fetch_channel_targets(db: &Arc<dyn Database>, user_id: i32) -> Result<HashMap<NotificationChannel, ChannelTarget>, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    let records = db
+        .fetch_all("SELECT channel, target, secret FROM notification_channel_settings WHERE user_id = $1", args)
+        .await?;
+
+    Ok(records.iter()
+        .filter_map(|record| {
+            let channel = NotificationChannel::from_code(record.get("channel"))?;
+            Some((channel, ChannelTarget { address: record.get("target"), secret: record.get("secret") }))
+        })
+        .collect())
+}
+
+// Deliver a notification over each of the channels, logging one delivery per channel.
+// Returns the ids of the logged deliveries in channel order.
+pub async fn deliver(
+    db: &Arc<dyn Database>,
+    user_id: i32,
+    notification: &Notification,
+    channels: &[NotificationChannel],
+) -> Result<Vec<i32>, ApiError> {
+    let targets = fetch_channel_targets(db, user_id).await?;
+    let mut delivery_ids = vec![];
+
+    for channel in channels {
+        let target = targets.get(channel);
+        let outcome = if requires_target(*channel) && target.is_none() {
+            DeliveryOutcome::skipped("No target configured for this channel")
+        } else {
+            match sender_for(*channel, db) {
+                Some(sender) => send_with_retry(sender.as_ref(), user_id, target, notification).await,
+                None => DeliveryOutcome::skipped("This channel is not available"),
+            }
+        };
+
+        let mut args = PgArguments::default();
+        args.add(user_id);
+        args.add(channel.code());
+        args.add(&notification.kind);
+        args.add(target.map(|target| target.address.clone()));
+        args.add(outcome.status.code());
+        args.add(outcome.attempts);
+        args.add(outcome.response_status);
+        args.add(outcome.error);
+        let record = db
+            .fetch_one(r#"INSERT INTO notification_deliveries (user_id, channel, kind, target, status, attempts, response_status, error)
+                          VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id"#, args)
+            .await?;
+        delivery_ids.push(record.get("id"));
+    }
+
+    Ok(delivery_ids)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NotificationResponse {
+    id: i32,
+    kind: String,
+    title: String,
+    body: String,
+    payload: serde_json::Value,
+    read_at: Option<Timestamp>,
+    created_at: Option<Timestamp>,
+}
+
+impl NotificationResponse {
+    fn from_row(record: &PgRow, timezone: Tz, format: TimestampFormat) -> Self {
+        NotificationResponse {
+            id: record.get("id"),
+            kind: record.get("kind"),
+            title: record.get("title"),
+            body: record.get("body"),
+            payload: record.get("payload"),
+            read_at: format_datetime(record.get::<Option<DateTime<Utc>>, _>("read_at"), timezone, format),
+            created_at: format_datetime(record.get::<Option<DateTime<Utc>>, _>("created_at"), timezone, format),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeliveryResponse {
+    id: i32,
+    channel: NotificationChannel,
+    kind: String,
+    target: Option<String>,
+    status: DeliveryStatus,
+    attempts: i32,
+    response_status: Option<i32>,
+    error: Option<String>,
+    created_at: Option<Timestamp>,
+}
+
+impl DeliveryResponse {
+    fn from_row(record: &PgRow, timezone: Tz, format: TimestampFormat) -> Self {
+        DeliveryResponse {
+            id: record.get("id"),
+            channel: NotificationChannel::from_code(record.get("channel")).unwrap_or(NotificationChannel::InApp),
+            kind: record.get("kind"),
+            target: record.get("target"),
+            status: DeliveryStatus::from_code(record.get("status")),
+            attempts: record.get("attempts"),
+            response_status: record.get("response_status"),
+            error: record.get("error"),
+            created_at: format_datetime(record.get::<Option<DateTime<Utc>>, _>("created_at"), timezone, format),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChannelSettingsResponse {
+    channel: NotificationChannel,
+    target: String,
+    secret: Option<String>,
+    // Whether the channel is switched on in the user's preferences
+    enabled: bool,
+    updated_at: Option<Timestamp>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChannelSettingsRequest {
+    target: String,
+    // Webhook signing secret, generated when omitted
+    secret: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    unread: Option<bool>,
+    time_format: Option<TimestampFormat>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveryQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    channel: Option<NotificationChannel>,
+    status: Option<DeliveryStatus>,
+    time_format: Option<TimestampFormat>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TestNotificationRequest {
+    // Defaults to every channel enabled in the user's preferences
+    channel: Option<NotificationChannel>,
+}
+
+const DELIVERY_COLUMNS: &str = "id, channel, kind, target, status, attempts, response_status, error, created_at";
+
+#[instrument]
+pub async fn retrieve_notifications(
+    state: Data<AppState>,
+    request: HttpRequest,
+    query: Query<NotificationQuery>,
+) -> Result<Json<Page<NotificationResponse>>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let limit = page_limit(query.limit)?;
+    let sort = KeysetSort { name: "created_at", expression: "created_at", sql_type: "timestamptz", order: SortOrder::Desc };
+    let cursor = sort.decode_cursor(query.cursor.as_deref())?;
+
+    let mut conditions = SqlConditions::new();
+    conditions.push("user_id = $?", user_id);
+    if query.unread.unwrap_or(false) {
+        conditions.push_clause("read_at IS NULL".into());
+    }
+    if let Some(cursor) = cursor {
+        let value_param = conditions.bind(cursor.value);
+        let id_param = conditions.bind(cursor.id);
+        conditions.push_clause(sort.after("id", value_param, id_param));
+    }
+
+    let sql = format!(
+        "SELECT id, kind, title, body, payload, read_at, created_at, {} FROM notifications {} {} LIMIT {}",
+        sort.select(), conditions.where_clause(), sort.order_by("id"), limit + 1
+    );
+    let records = state.db
+        .fetch_all(&sql, conditions.into_args())
+        .await?;
+
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let time_format = query.time_format.unwrap_or_default();
+    respond_json(paginate(records, limit, &sort, "id", |record| NotificationResponse::from_row(record, timezone, time_format)))
+}
+
+#[instrument]
+pub async fn mark_notification_read(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+
+    let mut args = PgArguments::default();
+    args.add(path.into_inner());
+    args.add(user_id);
+    let result = state.db
+        .execute("UPDATE notifications SET read_at = COALESCE(read_at, NOW()) WHERE id = $1 AND user_id = $2", args)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound);
+    }
+    respond_ok()
+}
+
+#[instrument]
+pub async fn mark_all_notifications_read(
+    state: Data<AppState>,
+    request: HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    state.db
+        .execute("UPDATE notifications SET read_at = NOW() WHERE user_id = $1 AND read_at IS NULL", args)
+        .await?;
+
+    respond_ok()
+}
+
+#[instrument]
+pub async fn retrieve_notification_channels(
+    state: Data<AppState>,
+    request: HttpRequest,
+    query: Query<TimestampQuery>,
+) -> Result<Json<Vec<ChannelSettingsResponse>>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    let records = state.db
+        .fetch_all("SELECT channel, target, secret, updated_at FROM notification_channel_settings WHERE user_id = $1 ORDER BY channel", args)
+        .await?;
+
+    let time_format = query.time_format.unwrap_or_default();
+    let channels = records.iter()
+        .filter_map(|record| {
+            let channel = NotificationChannel::from_code(record.get("channel"))?;
+            Some(ChannelSettingsResponse {
+                channel,
+                target: record.get("target"),
+                secret: record.get("secret"),
+                enabled: preferences.notification_channels.contains(&channel),
+                updated_at: format_datetime(record.get::<Option<DateTime<Utc>>, _>("updated_at"), preferences.tz(), time_format),
+            })
+        })
+        .collect();
+
+    respond_json(channels)
+}
+
+#[instrument]
+pub async fn update_notification_channel(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<NotificationChannel>,
+    query: Query<TimestampQuery>,
+    body: Json<ChannelSettingsRequest>,
+) -> Result<Json<ChannelSettingsResponse>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let channel = path.into_inner();
+    let body = body.into_inner();
+    let target = validate_target(channel, &body.target, CONFIG.webhook_allow_insecure_targets)?;
+    if channel == NotificationChannel::Webhook && !CONFIG.webhook_allow_insecure_targets {
+        let host = Url::parse(&target).ok().and_then(|url| url.host_str().map(str::to_string)).unwrap_or_default();
+        resolve_public_addresses(&host).await
+            .map_err(|err| BadRequest(format!("Invalid webhook URL: {}", err)))?;
+    }
+
+    // Only webhooks are signed
+    let secret = match channel {
+        NotificationChannel::Webhook => Some(body.secret.filter(|secret| !secret.trim().is_empty()).unwrap_or_else(generate_secret)),
+        _ => None,
+    };
+
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    args.add(channel.code());
+    args.add(&target);
+    args.add(&secret);
+    let record = state.db
+        .fetch_one(r#"INSERT INTO notification_channel_settings (user_id, channel, target, secret)
+                      VALUES ($1, $2, $3, $4)
+                      ON CONFLICT (user_id, channel) DO UPDATE
+                      SET target = EXCLUDED.target, secret = EXCLUDED.secret, updated_at = NOW()
+                      RETURNING updated_at"#, args)
+        .await?;
+
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    respond_json(ChannelSettingsResponse {
+        channel,
+        target,
+        secret,
+        enabled: preferences.notification_channels.contains(&channel),
+        updated_at: format_datetime(record.get::<Option<DateTime<Utc>>, _>("updated_at"), preferences.tz(), query.time_format.unwrap_or_default()),
+    })
+}
+
+#[instrument]
+pub async fn delete_notification_channel(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<NotificationChannel>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    args.add(path.into_inner().code());
+    let result = state.db
+        .execute("DELETE FROM notification_channel_settings WHERE user_id = $1 AND channel = $2", args)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ApiError::NotFound);
+    }
+    respond_ok()
+}
+
+#[instrument]
+pub async fn retrieve_notification_deliveries(
+    state: Data<AppState>,
+    request: HttpRequest,
+    query: Query<DeliveryQuery>,
+) -> Result<Json<Page<DeliveryResponse>>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let limit = page_limit(query.limit)?;
+    let sort = KeysetSort { name: "created_at", expression: "created_at", sql_type: "timestamptz", order: SortOrder::Desc };
+    let cursor = sort.decode_cursor(query.cursor.as_deref())?;
+
+    let mut conditions = SqlConditions::new();
+    conditions.push("user_id = $?", user_id);
+    if let Some(channel) = query.channel {
+        conditions.push("channel = $?", channel.code());
+    }
+    if let Some(status) = query.status {
+        conditions.push("status = $?", status.code());
+    }
+    if let Some(cursor) = cursor {
+        let value_param = conditions.bind(cursor.value);
+        let id_param = conditions.bind(cursor.id);
+        conditions.push_clause(sort.after("id", value_param, id_param));
+    }
+
+    let sql = format!(
+        "SELECT {}, {} FROM notification_deliveries {} {} LIMIT {}",
+        DELIVERY_COLUMNS, sort.select(), conditions.where_clause(), sort.order_by("id"), limit + 1
+    );
+    let records = state.db
+        .fetch_all(&sql, conditions.into_args())
+        .await?;
+
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let time_format = query.time_format.unwrap_or_default();
+    respond_json(paginate(records, limit, &sort, "id", |record| DeliveryResponse::from_row(record, timezone, time_format)))
+}
+
+#[instrument]
+pub async fn send_test_notification(
+    state: Data<AppState>,
+    request: HttpRequest,
+    query: Query<TimestampQuery>,
+    body: Json<TestNotificationRequest>,
+) -> Result<Json<Vec<DeliveryResponse>>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    let channels = match body.channel {
+        Some(channel) => vec![channel],
+        None => preferences.notification_channels.clone(),
+    };
+    if channels.is_empty() {
+        return Err(BadRequest("No notification channel is enabled".into()));
+    }
+
+    let notification = Notification {
+        kind: "test".into(),
+        title: "Test notification".into(),
+        body: "Notifications from your crypto watchlist will arrive here.".into(),
+        payload: serde_json::json!({}),
+    };
+    let delivery_ids = deliver(&state.db, user_id, &notification, &channels).await?;
+
+    let mut args = PgArguments::default();
+    args.add(&delivery_ids);
+    let records = state.db
+        .fetch_all(&format!("SELECT {} FROM notification_deliveries WHERE id = ANY($1) ORDER BY id", DELIVERY_COLUMNS), args)
+        .await?;
+    if records.len() != delivery_ids.len() {
+        return Err(InternalServerError);
+    }
+
+    let time_format = query.time_format.unwrap_or_default();
+    respond_json(records.iter().map(|record| DeliveryResponse::from_row(record, preferences.tz(), time_format)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_sign_payload() {
+        assert_eq!(
+            sign_payload("whsec_test", 1700000000, r#"{"kind":"test"}"#),
+            "94f5692e57a0c550df2b9a1b63fcb9a1aab3b8c1b4c7776a94f40fa745a70389"
+        );
+    }
+
+    #[test]
+    fn test_unit_retry_delay() {
+        let base = Duration::from_millis(500);
+        assert_eq!(retry_delay(base, 1), Duration::from_millis(500));
+        assert_eq!(retry_delay(base, 3), Duration::from_secs(2));
+        assert_eq!(retry_delay(base, 40), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_unit_is_public_address() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fe80::1", "fd00::1", "::ffff:10.0.0.1"] {
+            assert!(!is_public_address(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public_address(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_unit_validate_target() {
+        assert_eq!(
+            validate_target(NotificationChannel::Webhook, " https://example.com/hooks ", false).unwrap(),
+            "https://example.com/hooks"
+        );
+        assert!(validate_target(NotificationChannel::Webhook, "http://localhost:9000/hook", false).is_err());
+        assert!(validate_target(NotificationChannel::Webhook, "http://localhost:9000/hook", true).is_ok());
+        for target in ["https://127.0.0.1/hook", "https://169.254.169.254/latest", "https://10.0.0.5/", "https://[::1]/", "https://[fe80::1]/", "https://[::ffff:192.168.1.1]/"] {
+            assert!(validate_target(NotificationChannel::Webhook, target, false).is_err(), "{}", target);
+            assert!(validate_target(NotificationChannel::Webhook, target, true).is_ok(), "{}", target);
+        }
+        assert!(validate_target(NotificationChannel::Webhook, "https://93.184.216.34/hook", false).is_ok());
+        assert_eq!(validate_target(NotificationChannel::Email, "me@example.com", false).unwrap(), "me@example.com");
+        assert!(validate_target(NotificationChannel::Email, "not an address", false).is_err());
+        assert!(validate_target(NotificationChannel::InApp, "anything", false).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use sqlx::Row;
+use sqlx::postgres::PgRow;
+use crate::errors::ApiError;
+use crate::errors::ApiError::BadRequest;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    fn sql(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+
+    fn comparison(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => ">",
+            SortOrder::Desc => "<",
+        }
+    }
+}
+
+// Envelope shared by every list endpoint
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub pagination: PageInfo,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PageInfo {
+    pub limit: i64,
+    pub has_more: bool,
+    pub next_cursor: Option<String>,
+}
+
+// Position of the last row of a page: the sort key it was produced with, the row's sort value
+// and its id as a tie-breaker
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Cursor {
+    pub sort: String,
+    pub value: String,
+    pub id: i32,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(cursor: &str) -> Result<Self, ApiError> {
+        URL_SAFE_NO_PAD.decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or(BadRequest("Invalid cursor".into()))
+    }
+}
+
+// Keyset ordering over a single non-null SQL expression plus an id column
+#[derive(Debug, Clone)]
+pub struct KeysetSort {
+    pub name: &'static str,
+    pub expression: &'static str,
+    pub sql_type: &'static str,
+    pub order: SortOrder,
+}
+
+impl KeysetSort {
+    pub fn select(&self) -> String {
+        format!("({})::text AS sort_value", self.expression)
+    }
+
+    // `value_param` and `id_param` are the positions of the cursor's bind parameters
+    pub fn after(&self, id_column: &str, value_param: usize, id_param: usize) -> String {
+        format!(
+            "({}, {}) {} (CAST(${} AS {}), ${})",
+            self.expression, id_column, self.order.comparison(), value_param, self.sql_type, id_param
+        )
+    }
+
+    pub fn order_by(&self, id_column: &str) -> String {
+        format!("ORDER BY {} {}, {} {}", self.expression, self.order.sql(), id_column, self.order.sql())
+    }
+
+    pub fn decode_cursor(&self, cursor: Option<&str>) -> Result<Option<Cursor>, ApiError> {
+        let Some(cursor) = cursor else {
+            return Ok(None);
+        };
+
+        let cursor = Cursor::decode(cursor)?;
+        if cursor.sort != self.cursor_sort() {
+            return Err(BadRequest("The cursor does not match the requested sort".into()));
+        }
+        Ok(Some(cursor))
+    }
+
+    fn cursor_sort(&self) -> String {
+        format!("{}:{:?}", self.name, self.order)
+    }
+}
+
+pub fn page_limit(limit: Option<i64>) -> Result<i64, ApiError> {
+    match limit {
+        None => Ok(DEFAULT_PAGE_SIZE),
+        Some(limit) if (1..=MAX_PAGE_SIZE).contains(&limit) => Ok(limit),
+        Some(_) => Err(BadRequest(format!("limit must be between 1 and {}", MAX_PAGE_SIZE))),
+    }
+}
+
+// Build a page from rows fetched with `LIMIT limit + 1`, the extra row only signalling there is more
+pub fn paginate<T>(
+    records: Vec<PgRow>,
+    limit: i64,
+    sort: &KeysetSort,
+    id_column: &str,
+    map: impl Fn(&PgRow) -> T,
+) -> Page<T> {
+    let has_more = records.len() as i64 > limit;
+    let records = &records[..records.len().min(limit as usize)];
+
+    let next_cursor = match records.last() {
+        Some(last) if has_more => Some(Cursor {
+            sort: sort.cursor_sort(),
+            value: last.get("sort_value"),
+            id: last.get(id_column),
+        }.encode()),
+        _ => None,
+    };
+
+    Page {
+        data: records.iter().map(map).collect(),
+        pagination: PageInfo { limit, has_more, next_cursor },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name_sort(order: SortOrder) -> KeysetSort {
+        KeysetSort { name: "name", expression: "LOWER(name)", sql_type: "text", order }
+    }
+
+    #[test]
+    fn test_unit_cursor_roundtrip() {
+        let sort = name_sort(SortOrder::Desc);
+        let cursor = Cursor { sort: sort.cursor_sort(), value: "bitcoin".into(), id: 7 };
+
+        assert_eq!(sort.decode_cursor(Some(&cursor.encode())).unwrap(), Some(cursor));
+        assert!(name_sort(SortOrder::Asc).decode_cursor(Some(&Cursor { sort: sort.cursor_sort(), value: "x".into(), id: 1 }.encode())).is_err());
+        assert!(sort.decode_cursor(Some("not a cursor")).is_err());
+    }
+
+    #[test]
+    fn test_unit_keyset_sql() {
+        let sort = name_sort(SortOrder::Desc);
+
+        assert_eq!(sort.after("id", 2, 3), "(LOWER(name), id) < (CAST($2 AS text), $3)");
+        assert_eq!(sort.order_by("id"), "ORDER BY LOWER(name) DESC, id DESC");
+    }
+
+    #[test]
+    fn test_unit_page_limit() {
+        assert_eq!(page_limit(None).unwrap(), DEFAULT_PAGE_SIZE);
+        assert_eq!(page_limit(Some(50)).unwrap(), 50);
+        assert!(page_limit(Some(0)).is_err());
+        assert!(page_limit(Some(MAX_PAGE_SIZE + 1)).is_err());
+    }
+}
@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use actix_web::web::{Data, Json, Path, Query};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use sqlx::{Arguments, Row};
+use sqlx::postgres::{PgArguments, PgRow};
+use tracing::instrument;
+use crate::cache::CacheKey;
+use crate::config::CONFIG;
+use crate::currency::{conversion_rate, convert_price, parse_currency, BASE_CURRENCY};
+use crate::database::{Database, SqlConditions};
+use crate::errors::ApiError;
+use crate::errors::ApiError::{BadRequest, InternalServerError};
+use crate::helpers::{format_datetime, respond_json, respond_ok, Timestamp, TimestampFormat, TimestampQuery};
+use crate::middleware_custom::Claims;
+use crate::pagination::{page_limit, paginate, KeysetSort, Page, SortOrder};
+use crate::preferences::load_user_preferences;
+use crate::server::AppState;
+use crate::watchlist::WatchlistResponse;
+use crate::watchlistgroup::find_user_watchlist_group;
+
+// Sells may close a position that float arithmetic leaves a hair above zero
+const QUANTITY_EPSILON: f64 = 1e-9;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionSide {
+    Buy,
+    Sell,
+}
+
+impl TransactionSide {
+    // Value stored in `portfolio_transactions.side`
+    fn code(&self) -> &'static str {
+        match self {
+            TransactionSide::Buy => "buy",
+            TransactionSide::Sell => "sell",
+        }
+    }
+
+    fn from_code(code: &str) -> Self {
+        match code {
+            "sell" => TransactionSide::Sell,
+            _ => TransactionSide::Buy,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionRequest {
+    asset_id: i32,
+    side: TransactionSide,
+    quantity: f64,
+    // Price per unit in USD
+    price: f64,
+    fee: Option<f64>,
+    executed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransactionResponse {
+    id: i32,
+    group_id: i32,
+    asset_id: i32,
+    symbol: String,
+    side: TransactionSide,
+    quantity: f64,
+    price: f64,
+    fee: f64,
+    executed_at: Option<Timestamp>,
+}
+
+impl TransactionResponse {
+    fn from_row(record: &PgRow, timezone: Tz, format: TimestampFormat) -> Self {
+        TransactionResponse {
+            id: record.get("id"),
+            group_id: record.get("group_id"),
+            asset_id: record.get("asset_id"),
+            symbol: record.get("symbol"),
+            side: TransactionSide::from_code(record.get("side")),
+            quantity: record.get("quantity"),
+            price: record.get("price"),
+            fee: record.get("fee"),
+            executed_at: format_datetime(record.get("executed_at"), timezone, format),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TransactionQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    asset_id: Option<i32>,
+    time_format: Option<TimestampFormat>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PortfolioQuery {
+    convert: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HoldingResponse {
+    #[serde(flatten)]
+    asset: WatchlistResponse,
+    quantity: f64,
+    average_cost: Option<f64>,
+    cost_basis: f64,
+    price: Option<f64>,
+    value: Option<f64>,
+    unrealized_pnl: Option<f64>,
+    realized_pnl: f64,
+    allocation_percent: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PortfolioResponse {
+    group_id: i32,
+    currency: String,
+    total_value: f64,
+    total_cost_basis: f64,
+    unrealized_pnl: f64,
+    realized_pnl: f64,
+    holdings: Vec<HoldingResponse>,
+}
+
+#[derive(Debug, Clone)]
+struct Trade {
+    id: i32,
+    side: TransactionSide,
+    quantity: f64,
+    price: f64,
+    fee: f64,
+    executed_at: DateTime<Utc>,
+}
+
+// Open quantity and cost of an asset using the average cost method, fees included in the cost
+#[derive(Debug, Default, PartialEq)]
+struct Position {
+    quantity: f64,
+    cost_basis: f64,
+    realized_pnl: f64,
+}
+
+impl Position {
+    fn apply(&mut self, trade: &Trade) -> Result<(), ApiError> {
+        match trade.side {
+            TransactionSide::Buy => {
+                self.quantity += trade.quantity;
+                self.cost_basis += trade.quantity * trade.price + trade.fee;
+            }
+            TransactionSide::Sell => {
+                if trade.quantity > self.quantity + QUANTITY_EPSILON {
+                    return Err(BadRequest(format!(
+                        "Cannot sell {} when only {} is held at {}", trade.quantity, self.quantity, trade.executed_at.to_rfc3339()
+                    )));
+                }
+                let sold_cost = self.cost_basis * trade.quantity / self.quantity;
+                self.realized_pnl += trade.quantity * trade.price - trade.fee - sold_cost;
+                self.quantity -= trade.quantity;
+                self.cost_basis -= sold_cost;
+                if self.quantity < QUANTITY_EPSILON {
+                    self.quantity = 0.0;
+                    self.cost_basis = 0.0;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Replay the trades of one asset in execution order, failing when a sell exceeds the holding
+fn replay(trades: &mut [Trade]) -> Result<Position, ApiError> {
+    trades.sort_by_key(|trade| (trade.executed_at, trade.id));
+
+    let mut position = Position::default();
+    for trade in trades.iter() {
+        position.apply(trade)?;
+    }
+    Ok(position)
+}
+
+async fn fetch_trades(db: &Arc<dyn Database>, group_id: i32, asset_id: Option<i32>) -> Result<HashMap<i32, Vec<Trade>>, ApiError> {
+    let mut conditions = SqlConditions::new();
+    conditions.push("group_id = $?", group_id);
+    if let Some(asset_id) = asset_id {
+        conditions.push("asset_id = $?", asset_id);
+    }
+
+    let sql = format!(
+        "SELECT id, asset_id, side, quantity, price, fee, executed_at FROM portfolio_transactions {}",
+        conditions.where_clause()
+    );
+    let records = db.fetch_all(&sql, conditions.into_args()).await?;
+
+    let mut trades: HashMap<i32, Vec<Trade>> = HashMap::new();
+    for record in &records {
+        trades.entry(record.get("asset_id")).or_default().push(Trade {
+            id: record.get("id"),
+            side: TransactionSide::from_code(record.get("side")),
+            quantity: record.get("quantity"),
+            price: record.get("price"),
+            fee: record.get("fee"),
+            executed_at: record.get("executed_at"),
+        });
+    }
+    Ok(trades)
+}
+
+// Latest USD close of each asset from the stored candles
+pub async fn latest_prices(db: &Arc<dyn Database>, asset_ids: Vec<i32>) -> Result<HashMap<i32, f64>, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(asset_ids);
+
+    let records = db
+        .fetch_all("SELECT DISTINCT ON (asset_id) asset_id, close FROM asset_candles WHERE asset_id = ANY($1) ORDER BY asset_id, close_time DESC", args)
+        .await?;
+
+    Ok(records.iter().map(|record| (record.get("asset_id"), record.get("close"))).collect())
+}
+
+impl PortfolioResponse {
+    // Portfolios are valued and cached in USD and converted on the way out
+    fn convert(mut self, currency: String, rate: f64) -> Self {
+        for holding in self.holdings.iter_mut() {
+            holding.average_cost = convert_price(holding.average_cost, rate);
+            holding.cost_basis *= rate;
+            holding.price = convert_price(holding.price, rate);
+            holding.value = convert_price(holding.value, rate);
+            holding.unrealized_pnl = convert_price(holding.unrealized_pnl, rate);
+            holding.realized_pnl *= rate;
+        }
+        self.total_value *= rate;
+        self.total_cost_basis *= rate;
+        self.unrealized_pnl *= rate;
+        self.realized_pnl *= rate;
+        self.currency = currency;
+        self
+    }
+}
+
+fn build_portfolio(
+    group_id: i32,
+    assets: Vec<WatchlistResponse>,
+    positions: &HashMap<i32, Position>,
+    prices: &HashMap<i32, f64>,
+) -> PortfolioResponse {
+    let mut holdings: Vec<HoldingResponse> = assets.into_iter()
+        .filter_map(|asset| {
+            let position = positions.get(&asset.id)?;
+            let price = prices.get(&asset.id).copied();
+            let cost_basis = position.cost_basis;
+            let value = price.map(|price| price * position.quantity);
+
+            Some(HoldingResponse {
+                quantity: position.quantity,
+                average_cost: (position.quantity > 0.0).then(|| cost_basis / position.quantity),
+                cost_basis,
+                price,
+                value,
+                unrealized_pnl: value.map(|value| value - cost_basis),
+                realized_pnl: position.realized_pnl,
+                allocation_percent: None,
+                asset,
+            })
+        })
+        .collect();
+
+    let total_value: f64 = holdings.iter().filter_map(|holding| holding.value).sum();
+    for holding in holdings.iter_mut() {
+        holding.allocation_percent = holding.value
+            .filter(|_| total_value > 0.0)
+            .map(|value| value / total_value * 100.0);
+    }
+
+    PortfolioResponse {
+        group_id,
+        currency: BASE_CURRENCY.to_string(),
+        total_value,
+        total_cost_basis: holdings.iter().map(|holding| holding.cost_basis).sum(),
+        unrealized_pnl: holdings.iter().filter_map(|holding| holding.unrealized_pnl).sum(),
+        realized_pnl: holdings.iter().map(|holding| holding.realized_pnl).sum(),
+        holdings,
+    }
+}
+
+#[instrument]
+pub async fn create_transaction(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<i32>,
+    body: Json<TransactionRequest>,
+    query: Query<TimestampQuery>,
+) -> Result<Json<TransactionResponse>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let group_id = path.into_inner();
+    find_user_watchlist_group(&state.db, user_id, group_id).await?;
+
+    let fee = body.fee.unwrap_or(0.0);
+    let executed_at = body.executed_at.unwrap_or_else(Utc::now);
+    if !body.quantity.is_finite() || body.quantity <= 0.0 {
+        return Err(BadRequest("quantity must be greater than zero".into()));
+    }
+    if !body.price.is_finite() || body.price < 0.0 || !fee.is_finite() || fee < 0.0 {
+        return Err(BadRequest("price and fee must not be negative".into()));
+    }
+    if executed_at > Utc::now() {
+        return Err(BadRequest("executed_at must not be in the future".into()));
+    }
+
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    args.add(body.asset_id);
+    state.db
+        .fetch_optional("SELECT asset_id FROM watchlist WHERE group_id = $1 AND asset_id = $2", args)
+        .await?
+        .ok_or(BadRequest("Asset is not in this watchlist group".into()))?;
+
+    // A backdated sell must not oversell at any point of the history
+    let mut trades = fetch_trades(&state.db, group_id, Some(body.asset_id)).await?
+        .remove(&body.asset_id)
+        .unwrap_or_default();
+    trades.push(Trade { id: i32::MAX, side: body.side, quantity: body.quantity, price: body.price, fee, executed_at });
+    replay(&mut trades)?;
+
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    args.add(body.asset_id);
+    args.add(body.side.code());
+    args.add(body.quantity);
+    args.add(body.price);
+    args.add(fee);
+    args.add(executed_at);
+    let record = state.db
+        .fetch_one(r#"WITH inserted AS (
+                          INSERT INTO portfolio_transactions (group_id, asset_id, side, quantity, price, fee, executed_at)
+                          VALUES ($1, $2, $3, $4, $5, $6, $7)
+                          RETURNING id, group_id, asset_id, side, quantity, price, fee, executed_at
+                      )
+                      SELECT t.*, a.symbol FROM inserted t JOIN assets a ON a.id = t.asset_id"#, args)
+        .await?;
+
+    state.redis_client.del(format!("portfolio::{}", group_id)).await;
+
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    respond_json(TransactionResponse::from_row(&record, timezone, query.time_format.unwrap_or_default()))
+}
+
+#[instrument]
+pub async fn retrieve_transactions(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<i32>,
+    query: Query<TransactionQuery>,
+) -> Result<Json<Page<TransactionResponse>>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let group_id = path.into_inner();
+    find_user_watchlist_group(&state.db, user_id, group_id).await?;
+
+    let limit = page_limit(query.limit)?;
+    let sort = KeysetSort { name: "executed_at", expression: "t.executed_at", sql_type: "timestamptz", order: SortOrder::Desc };
+    let cursor = sort.decode_cursor(query.cursor.as_deref())?;
+
+    let mut conditions = SqlConditions::new();
+    conditions.push("t.group_id = $?", group_id);
+    if let Some(asset_id) = query.asset_id {
+        conditions.push("t.asset_id = $?", asset_id);
+    }
+    if let Some(cursor) = cursor {
+        let value_param = conditions.bind(cursor.value);
+        let id_param = conditions.bind(cursor.id);
+        conditions.push_clause(sort.after("t.id", value_param, id_param));
+    }
+
+    let sql = format!(
+        "SELECT t.id, t.group_id, t.asset_id, a.symbol, t.side, t.quantity, t.price, t.fee, t.executed_at, {} \
+         FROM portfolio_transactions t JOIN assets a ON a.id = t.asset_id {} {} LIMIT {}",
+        sort.select(), conditions.where_clause(), sort.order_by("t.id"), limit + 1
+    );
+    let records = state.db
+        .fetch_all(&sql, conditions.into_args())
+        .await?;
+
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let time_format = query.time_format.unwrap_or_default();
+    respond_json(paginate(records, limit, &sort, "id", |record| TransactionResponse::from_row(record, timezone, time_format)))
+}
+
+#[instrument]
+pub async fn delete_transaction(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<(i32, i32)>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let (group_id, transaction_id) = path.into_inner();
+    find_user_watchlist_group(&state.db, user_id, group_id).await?;
+
+    let mut args = PgArguments::default();
+    args.add(transaction_id);
+    args.add(group_id);
+    let record = state.db
+        .fetch_optional("SELECT asset_id FROM portfolio_transactions WHERE id = $1 AND group_id = $2", args)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let asset_id: i32 = record.get("asset_id");
+
+    // Removing a buy must not leave a later sell without a holding
+    let mut trades = fetch_trades(&state.db, group_id, Some(asset_id)).await?
+        .remove(&asset_id)
+        .unwrap_or_default();
+    trades.retain(|trade| trade.id != transaction_id);
+    replay(&mut trades)?;
+
+    let mut args = PgArguments::default();
+    args.add(transaction_id);
+    args.add(group_id);
+    let record = state.db
+        .execute("DELETE FROM portfolio_transactions WHERE id = $1 AND group_id = $2", args)
+        .await?;
+
+    if record.rows_affected() == 0 {
+        return Err(InternalServerError);
+    }
+
+    state.redis_client.del(format!("portfolio::{}", group_id)).await;
+
+    respond_ok()
+}
+
+#[instrument]
+pub async fn retrieve_portfolio(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<i32>,
+    query: Query<PortfolioQuery>,
+) -> Result<Json<PortfolioResponse>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let group_id = path.into_inner();
+    find_user_watchlist_group(&state.db, user_id, group_id).await?;
+
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    let currency = parse_currency(query.convert.as_deref(), &preferences.quote_currency)?;
+
+    let cache_key = format!("portfolio::{}", group_id);
+    let cache_field = BASE_CURRENCY.to_string();
+    let portfolio = state.redis_client.read_through(CacheKey::new(cache_key, cache_field).ttl(CONFIG.portfolio_cache_ttl_secs), async {
+        let mut positions = HashMap::new();
+        for (asset_id, mut trades) in fetch_trades(&state.db, group_id, None).await? {
+            positions.insert(asset_id, replay(&mut trades)?);
+        }
+        let asset_ids: Vec<i32> = positions.keys().copied().collect();
+
+        let mut args = PgArguments::default();
+        args.add(asset_ids.clone());
+        let assets: Vec<WatchlistResponse> = state.db
+            .fetch_all("SELECT id, name, symbol, rank FROM assets WHERE id = ANY($1) ORDER BY id", args)
+            .await?
+            .iter()
+            .map(WatchlistResponse::from_row)
+            .collect();
+        let prices = latest_prices(&state.db, asset_ids).await?;
+
+        let portfolio = build_portfolio(group_id, assets, &positions, &prices);
+
+        Ok(portfolio)
+    })
+    .await?;
+
+    let rate = conversion_rate(&state.redis_client, &currency).await?;
+    respond_json(portfolio.convert(currency, rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn trade(id: i32, side: TransactionSide, quantity: f64, price: f64, day: i64) -> Trade {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        Trade { id, side, quantity, price, fee: 0.0, executed_at: start + Duration::days(day) }
+    }
+
+    #[test]
+    fn test_unit_replay_average_cost() {
+        let mut trades = vec![
+            trade(3, TransactionSide::Sell, 1.0, 400.0, 2),
+            trade(1, TransactionSide::Buy, 1.0, 100.0, 0),
+            trade(2, TransactionSide::Buy, 1.0, 300.0, 1),
+        ];
+        let position = replay(&mut trades).unwrap();
+
+        assert_eq!(position, Position { quantity: 1.0, cost_basis: 200.0, realized_pnl: 200.0 });
+    }
+
+    #[test]
+    fn test_unit_replay_rejects_oversell() {
+        let mut backdated_sell = vec![
+            trade(1, TransactionSide::Buy, 1.0, 100.0, 1),
+            trade(2, TransactionSide::Sell, 1.0, 100.0, 0),
+        ];
+        assert!(replay(&mut backdated_sell).is_err());
+
+        let mut closed = vec![
+            trade(1, TransactionSide::Buy, 0.3, 100.0, 0),
+            trade(2, TransactionSide::Buy, 0.6, 100.0, 0),
+            trade(3, TransactionSide::Sell, 0.9, 100.0, 1),
+        ];
+        assert_eq!(replay(&mut closed).unwrap().quantity, 0.0);
+    }
+
+    #[test]
+    fn test_unit_build_portfolio() {
+        let asset = |id: i32, symbol: &str| WatchlistResponse { id, name: symbol.into(), symbol: symbol.into(), rank: None, added_at: None, categories: None };
+        let positions = HashMap::from([
+            (1, Position { quantity: 2.0, cost_basis: 100.0, realized_pnl: 10.0 }),
+            (2, Position { quantity: 1.0, cost_basis: 100.0, realized_pnl: 0.0 }),
+        ]);
+        let prices = HashMap::from([(1, 150.0), (2, 100.0)]);
+        let portfolio = build_portfolio(1, vec![asset(1, "BTC"), asset(2, "ETH")], &positions, &prices);
+
+        assert_eq!(portfolio.total_value, 400.0);
+        assert_eq!(portfolio.unrealized_pnl, 200.0);
+        assert_eq!(portfolio.holdings[0].allocation_percent, Some(75.0));
+
+        let portfolio = portfolio.convert("EUR".into(), 0.5);
+        assert_eq!(portfolio.currency, "EUR");
+        assert_eq!(portfolio.total_value, 200.0);
+        assert_eq!(portfolio.realized_pnl, 5.0);
+        assert_eq!(portfolio.holdings[1].average_cost, Some(50.0));
+    }
+}
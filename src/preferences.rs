@@ -0,0 +1,252 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use actix_web::{HttpMessage, HttpRequest};
+use actix_web::web::{Data, Json};
+use chrono_tz::Tz;
+use sqlx::{Arguments, Row};
+use sqlx::postgres::PgArguments;
+use tracing::instrument;
+use crate::cache::{CacheKey, Redis};
+use crate::currency::{normalize_currency, supported_currencies, BASE_CURRENCY};
+use crate::database::Database;
+use crate::errors::ApiError;
+use crate::errors::ApiError::BadRequest;
+use crate::helpers::respond_json;
+use crate::middleware_custom::Claims;
+use crate::server::AppState;
+use crate::watchlistgroup::find_user_watchlist_group;
+
+const DEFAULT_TIMEZONE: &str = "UTC";
+const DEFAULT_LOCALE: &str = "en-US";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationChannel {
+    InApp,
+    Email,
+    Webhook,
+    Telegram,
+    Discord,
+}
+
+impl NotificationChannel {
+    // Value stored in `user_preferences.notification_channels`
+    pub fn code(&self) -> &'static str {
+        match self {
+            NotificationChannel::InApp => "in_app",
+            NotificationChannel::Email => "email",
+            NotificationChannel::Webhook => "webhook",
+            NotificationChannel::Telegram => "telegram",
+            NotificationChannel::Discord => "discord",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "in_app" => Some(NotificationChannel::InApp),
+            "email" => Some(NotificationChannel::Email),
+            "webhook" => Some(NotificationChannel::Webhook),
+            "telegram" => Some(NotificationChannel::Telegram),
+            "discord" => Some(NotificationChannel::Discord),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UserPreferences {
+    pub quote_currency: String,
+    pub timezone: String,
+    pub default_watchlist_group_id: Option<i32>,
+    pub locale: String,
+    pub notification_channels: Vec<NotificationChannel>,
+}
+
+impl Default for UserPreferences {
+    fn default() -> Self {
+        UserPreferences {
+            quote_currency: BASE_CURRENCY.to_string(),
+            timezone: DEFAULT_TIMEZONE.to_string(),
+            default_watchlist_group_id: None,
+            locale: DEFAULT_LOCALE.to_string(),
+            notification_channels: vec![NotificationChannel::InApp],
+        }
+    }
+}
+
+impl UserPreferences {
+    pub fn tz(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+}
+
+// Preferences are replaced as a whole, omitted fields go back to their defaults
+#[derive(Debug, Deserialize)]
+pub struct UserPreferencesRequest {
+    quote_currency: Option<String>,
+    timezone: Option<String>,
+    default_watchlist_group_id: Option<i32>,
+    locale: Option<String>,
+    notification_channels: Option<Vec<NotificationChannel>>,
+}
+
+// Normalise a language tag such as `en`, `en_us` or `es-419` to `en`, `en-US` and `es-419`
+fn parse_locale(locale: &str) -> Result<String, ApiError> {
+    let invalid = || BadRequest(format!("Invalid locale: {}", locale));
+    let mut parts = locale.trim().split(['-', '_']);
+
+    let language = parts.next().filter(|language| (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic()))
+        .ok_or_else(invalid)?
+        .to_lowercase();
+    let region = match parts.next() {
+        None => None,
+        Some(region) if region.len() == 2 && region.chars().all(|c| c.is_ascii_alphabetic()) => Some(region.to_uppercase()),
+        Some(region) if region.len() == 3 && region.chars().all(|c| c.is_ascii_digit()) => Some(region.to_string()),
+        Some(_) => return Err(invalid()),
+    };
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+
+    Ok(region.map_or(language.clone(), |region| format!("{}-{}", language, region)))
+}
+
+fn validate_preferences(request: UserPreferencesRequest, currencies: &[String]) -> Result<UserPreferences, ApiError> {
+    let defaults = UserPreferences::default();
+
+    let timezone = request.timezone.unwrap_or(defaults.timezone);
+    let timezone = timezone.trim().parse::<Tz>()
+        .map_err(|_| BadRequest(format!("Invalid timezone: {}", timezone)))?
+        .name()
+        .to_string();
+
+    let mut seen = HashSet::new();
+    let mut notification_channels = request.notification_channels.unwrap_or(defaults.notification_channels);
+    notification_channels.retain(|channel| seen.insert(*channel));
+
+    Ok(UserPreferences {
+        quote_currency: normalize_currency(request.quote_currency.as_deref().unwrap_or(BASE_CURRENCY), currencies)?,
+        timezone,
+        default_watchlist_group_id: request.default_watchlist_group_id,
+        locale: request.locale.as_deref().map_or(Ok(defaults.locale), parse_locale)?,
+        notification_channels,
+    })
+}
+
+// Preferences of a user, falling back to the defaults until they save their own
+pub async fn load_user_preferences(db: &Arc<dyn Database>, redis: &Arc<Redis>, user_id: i32) -> Result<UserPreferences, ApiError> {
+    let cache_key = format!("user_preferences::{}", user_id);
+    let cache_field = "preferences".to_string();
+    redis.read_through(CacheKey::new(cache_key, cache_field), async {
+        let mut args = PgArguments::default();
+        args.add(user_id);
+        let record = db
+            .fetch_optional(r#"SELECT quote_currency, timezone, default_watchlist_group_id, locale, notification_channels
+                               FROM user_preferences WHERE user_id = $1"#, args)
+            .await?;
+
+        let preferences = match record {
+            Some(record) => UserPreferences {
+                quote_currency: record.get("quote_currency"),
+                timezone: record.get("timezone"),
+                default_watchlist_group_id: record.get("default_watchlist_group_id"),
+                locale: record.get("locale"),
+                notification_channels: record.get::<Vec<String>, _>("notification_channels")
+                    .iter()
+                    .filter_map(|code| NotificationChannel::from_code(code))
+                    .collect(),
+            },
+            None => UserPreferences::default(),
+        };
+
+        Ok(preferences)
+    })
+    .await
+}
+
+#[instrument]
+pub async fn retrieve_user_preferences(
+    state: Data<AppState>,
+    request: HttpRequest,
+) -> Result<Json<UserPreferences>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    respond_json(load_user_preferences(&state.db, &state.redis_client, user_id).await?)
+}
+
+#[instrument]
+pub async fn update_user_preferences(
+    state: Data<AppState>,
+    request: HttpRequest,
+    body: Json<UserPreferencesRequest>,
+) -> Result<Json<UserPreferences>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let preferences = validate_preferences(body.into_inner(), &supported_currencies())?;
+
+    if let Some(group_id) = preferences.default_watchlist_group_id {
+        find_user_watchlist_group(&state.db, user_id, group_id).await
+            .map_err(|err| match err {
+                ApiError::NotFound => BadRequest("Watchlist Group not found".into()),
+                err => err,
+            })?;
+    }
+
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    args.add(&preferences.quote_currency);
+    args.add(&preferences.timezone);
+    args.add(preferences.default_watchlist_group_id);
+    args.add(&preferences.locale);
+    args.add(preferences.notification_channels.iter().map(|channel| channel.code()).collect::<Vec<&str>>());
+
+    state.db
+        .execute(r#"INSERT INTO user_preferences (user_id, quote_currency, timezone, default_watchlist_group_id, locale, notification_channels)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+                    ON CONFLICT (user_id) DO UPDATE
+                    SET quote_currency = EXCLUDED.quote_currency, timezone = EXCLUDED.timezone,
+                        default_watchlist_group_id = EXCLUDED.default_watchlist_group_id, locale = EXCLUDED.locale,
+                        notification_channels = EXCLUDED.notification_channels, updated_at = NOW()"#, args)
+        .await?;
+
+    // Group responses carry timestamps in the user's timezone and the default group flag
+    state.redis_client.del(format!("user_preferences::{}", user_id)).await;
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
+
+    respond_json(preferences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> UserPreferencesRequest {
+        UserPreferencesRequest { quote_currency: None, timezone: None, default_watchlist_group_id: None, locale: None, notification_channels: None }
+    }
+
+    #[test]
+    fn test_unit_parse_locale() {
+        assert_eq!(parse_locale("en").unwrap(), "en");
+        assert_eq!(parse_locale("id_id").unwrap(), "id-ID");
+        assert_eq!(parse_locale("es-419").unwrap(), "es-419");
+        assert!(parse_locale("english").is_err());
+        assert!(parse_locale("en-US-x").is_err());
+    }
+
+    #[test]
+    fn test_unit_validate_preferences() {
+        let currencies = vec!["USD".to_string(), "EUR".to_string()];
+        assert_eq!(validate_preferences(request(), &currencies).unwrap(), UserPreferences::default());
+
+        let preferences = validate_preferences(UserPreferencesRequest {
+            quote_currency: Some("eur".into()),
+            timezone: Some("Asia/Jakarta".into()),
+            notification_channels: Some(vec![NotificationChannel::Email, NotificationChannel::InApp, NotificationChannel::Email]),
+            ..request()
+        }, &currencies).unwrap();
+        assert_eq!(preferences.quote_currency, "EUR");
+        assert_eq!(preferences.tz(), Tz::Asia__Jakarta);
+        assert_eq!(preferences.notification_channels, vec![NotificationChannel::Email, NotificationChannel::InApp]);
+
+        assert!(validate_preferences(UserPreferencesRequest { timezone: Some("Mars/Olympus".into()), ..request() }, &currencies).is_err());
+        assert!(validate_preferences(UserPreferencesRequest { quote_currency: Some("IDR".into()), ..request() }, &currencies).is_err());
+    }
+}
@@ -0,0 +1,364 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use futures_util::future::join_all;
+use redis_async::client::{ConnectionBuilder, PairedConnection};
+use redis_async::resp::RespValue;
+use tracing::{debug, info};
+use crate::config::CONFIG;
+use crate::errors::ApiError;
+
+// Number of hash slots of a Redis Cluster
+const CLUSTER_SLOTS: u16 = 16384;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedisMode {
+    // `redis_host`:`redis_port`
+    #[default]
+    Standalone,
+    // The master of `redis_sentinel_master`, as reported by the first reachable of `redis_sentinels`
+    Sentinel,
+    // Every master of the cluster `redis_cluster_nodes` belongs to, commands go to the node owning their key
+    Cluster,
+}
+
+// Connections to every node of the deployment, `redis_pool_size` per node, used in turn
+#[derive(Debug)]
+pub struct RedisPool {
+    mode: RedisMode,
+    // The first node also carries the commands without a key
+    nodes: Vec<RedisNode>,
+    // Sorted by first slot, empty outside cluster mode
+    slots: Vec<SlotRange>,
+    next: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct RedisNode {
+    host: String,
+    port: u16,
+    connections: Vec<PairedConnection>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SlotRange {
+    first: u16,
+    last: u16,
+    node: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RedisNodeHealth {
+    pub address: String,
+    pub connections: usize,
+    // Connections that answered PING within `redis_command_timeout_ms`
+    pub healthy_connections: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+}
+
+// `host:port` pairs, e.g. `sentinel-1:26379,sentinel-2:26379`
+fn parse_addresses(addresses: &str) -> Vec<(String, u16)> {
+    addresses.split(',')
+        .filter_map(|address| address.trim().rsplit_once(':'))
+        .filter_map(|(host, port)| Some((host.to_string(), port.parse().ok()?)))
+        .collect()
+}
+
+// CRC16/XMODEM, the checksum Redis Cluster derives slots from
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
+
+// Only the part between the first `{` and the next `}` is hashed, when it is not empty
+fn key_slot(key: &[u8]) -> u16 {
+    let hashed = key.iter().position(|byte| *byte == b'{')
+        .and_then(|open| {
+            let tag = &key[open + 1..];
+            tag.iter().position(|byte| *byte == b'}').filter(|len| *len > 0).map(|len| &tag[..len])
+        })
+        .unwrap_or(key);
+    crc16(hashed) % CLUSTER_SLOTS
+}
+
+// Every command sent to Redis names its key first, except these
+fn command_key(command: &[RespValue]) -> Option<&[u8]> {
+    match command {
+        [RespValue::BulkString(name), ..] if name == b"PING" || name == b"PUBLISH" => None,
+        [_, RespValue::BulkString(key), ..] => Some(key),
+        _ => None,
+    }
+}
+
+// Replies telling that the command reached a node which no longer serves it: a cluster resharding or a sentinel failover
+pub fn is_topology_change(message: &str) -> bool {
+    ["MOVED", "ASK", "READONLY", "CLUSTERDOWN"].iter().any(|prefix| message.starts_with(prefix))
+}
+
+fn connection_builder(host: &str, port: u16, username: &str, password: &str) -> Result<ConnectionBuilder, ApiError> {
+    let mut builder = ConnectionBuilder::new(host, port)?;
+    // The builder authenticates again whenever the connection is re-established
+    if !username.is_empty() {
+        builder.username(username);
+    }
+    if !password.is_empty() {
+        builder.password(password);
+    }
+    if CONFIG.redis_tls {
+        builder.tls();
+    }
+    Ok(builder)
+}
+
+pub fn node_connection_builder(host: &str, port: u16) -> Result<ConnectionBuilder, ApiError> {
+    connection_builder(host, port, &CONFIG.redis_username, &CONFIG.redis_password)
+}
+
+fn command(args: &[&str]) -> RespValue {
+    RespValue::Array(args.iter().map(|arg| RespValue::BulkString(arg.as_bytes().to_vec())).collect())
+}
+
+async fn open(builder: ConnectionBuilder) -> Result<PairedConnection, ApiError> {
+    let timeout = Duration::from_millis(CONFIG.redis_command_timeout_ms);
+    let connection = actix_rt::time::timeout(timeout * 4, builder.paired_connect()).await
+        .map_err(|_| ApiError::RedisError("Timed out connecting to Redis".into()))??;
+    ping(&connection).await?;
+    Ok(connection)
+}
+
+async fn ping(connection: &PairedConnection) -> Result<(), ApiError> {
+    let timeout = Duration::from_millis(CONFIG.redis_command_timeout_ms);
+    match actix_rt::time::timeout(timeout, connection.send::<RespValue>(command(&["PING"]))).await {
+        Ok(Ok(RespValue::SimpleString(ref pong))) if pong == "PONG" => Ok(()),
+        Ok(Err(err)) => Err(err.into()),
+        _ => Err(ApiError::RedisError("Redis did not answer PING".into())),
+    }
+}
+
+async fn open_node(host: String, port: u16) -> Result<RedisNode, ApiError> {
+    let mut connections = Vec::new();
+    for _ in 0..CONFIG.redis_pool_size.max(1) {
+        connections.push(open(node_connection_builder(&host, port)?).await?);
+    }
+    Ok(RedisNode { host, port, connections })
+}
+
+// Ask the sentinels in turn where the master is, then make sure it still is the master
+async fn resolve_sentinel_master() -> Result<(String, u16), ApiError> {
+    let mut last_error = ApiError::RedisError("No sentinel configured".into());
+    for (host, port) in parse_addresses(&CONFIG.redis_sentinels) {
+        let builder = connection_builder(&host, port, "", &CONFIG.redis_sentinel_password)?;
+        let result = async {
+            let sentinel = open(builder).await?;
+            let address = sentinel
+                .send::<Vec<String>>(command(&["SENTINEL", "get-master-addr-by-name", &CONFIG.redis_sentinel_master]))
+                .await?;
+            match address.as_slice() {
+                [host, port] => Ok((host.clone(), port.parse().map_err(|_| ApiError::RedisError(format!("Invalid master port: {}", port)))?)),
+                _ => Err(ApiError::RedisError(format!("Sentinel does not know the master {}", CONFIG.redis_sentinel_master))),
+            }
+        }.await;
+
+        match result {
+            Ok(master) => return Ok(master),
+            Err(err) => {
+                debug!("Sentinel {}:{} did not resolve the master: {}", host, port, err);
+                last_error = err;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+async fn check_master(node: &RedisNode) -> Result<(), ApiError> {
+    let role = node.connections[0].send::<RespValue>(command(&["ROLE"])).await?;
+    match role {
+        RespValue::Array(ref parts) if matches!(parts.first(), Some(RespValue::BulkString(role)) if role == b"master") => Ok(()),
+        _ => Err(ApiError::RedisError(format!("{}:{} is not a master", node.host, node.port))),
+    }
+}
+
+// `CLUSTER SLOTS` replies with `[first, last, [host, port, ..], replicas..]` per range
+fn parse_cluster_slots(reply: RespValue) -> Result<Vec<(u16, u16, String, u16)>, ApiError> {
+    let invalid = || ApiError::RedisError("Invalid CLUSTER SLOTS reply".into());
+    let RespValue::Array(ranges) = reply else { return Err(invalid()) };
+    ranges.into_iter()
+        .map(|range| match range {
+            RespValue::Array(parts) => match parts.as_slice() {
+                [RespValue::Integer(first), RespValue::Integer(last), RespValue::Array(master), ..] => match master.as_slice() {
+                    [RespValue::BulkString(host), RespValue::Integer(port), ..] => Ok((
+                        *first as u16,
+                        *last as u16,
+                        String::from_utf8_lossy(host).to_string(),
+                        *port as u16,
+                    )),
+                    _ => Err(invalid()),
+                },
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+async fn discover_cluster() -> Result<(Vec<RedisNode>, Vec<SlotRange>), ApiError> {
+    let mut seeds = parse_addresses(&CONFIG.redis_cluster_nodes);
+    if seeds.is_empty() {
+        seeds.push((CONFIG.redis_host.clone(), CONFIG.redis_port));
+    }
+
+    let mut last_error = ApiError::RedisError("No cluster node reachable".into());
+    for (host, port) in seeds {
+        let result = async {
+            let seed = open(node_connection_builder(&host, port)?).await?;
+            parse_cluster_slots(seed.send::<RespValue>(command(&["CLUSTER", "SLOTS"])).await?)
+        }.await;
+
+        let ranges = match result {
+            Ok(ranges) => ranges,
+            Err(err) => {
+                debug!("Cluster node {}:{} did not report its slots: {}", host, port, err);
+                last_error = err;
+                continue;
+            }
+        };
+
+        let mut addresses: Vec<(String, u16)> = Vec::new();
+        let mut slots = Vec::new();
+        for (first, last, host, port) in ranges {
+            let node = match addresses.iter().position(|address| address.0 == host && address.1 == port) {
+                Some(node) => node,
+                None => {
+                    addresses.push((host, port));
+                    addresses.len() - 1
+                }
+            };
+            slots.push(SlotRange { first, last, node });
+        }
+        slots.sort_by_key(|range| range.first);
+
+        let mut nodes = Vec::new();
+        for (host, port) in addresses {
+            nodes.push(open_node(host, port).await?);
+        }
+        return Ok((nodes, slots));
+    }
+    Err(last_error)
+}
+
+impl RedisPool {
+    pub async fn connect() -> Result<RedisPool, ApiError> {
+        let (nodes, slots) = match CONFIG.redis_mode {
+            RedisMode::Standalone => (vec![open_node(CONFIG.redis_host.clone(), CONFIG.redis_port).await?], Vec::new()),
+            RedisMode::Sentinel => {
+                let (host, port) = resolve_sentinel_master().await?;
+                let master = open_node(host, port).await?;
+                check_master(&master).await?;
+                (vec![master], Vec::new())
+            }
+            RedisMode::Cluster => discover_cluster().await?,
+        };
+        if nodes.is_empty() {
+            return Err(ApiError::RedisError("No Redis node to connect to".into()));
+        }
+
+        let pool = RedisPool { mode: CONFIG.redis_mode, nodes, slots, next: AtomicUsize::new(0) };
+        info!("Connected to Redis ({:?}): {}", pool.mode, pool.addresses().join(", "));
+        Ok(pool)
+    }
+
+    pub fn mode(&self) -> RedisMode {
+        self.mode
+    }
+
+    fn addresses(&self) -> Vec<String> {
+        self.nodes.iter().map(|node| format!("{}:{}", node.host, node.port)).collect()
+    }
+
+    // Where subscriptions go. Published messages reach the subscribers of every node of a cluster.
+    pub fn primary_address(&self) -> (&str, u16) {
+        (&self.nodes[0].host, self.nodes[0].port)
+    }
+
+    fn node_for(&self, key: Option<&[u8]>) -> &RedisNode {
+        let node = key
+            .map(key_slot)
+            .and_then(|slot| {
+                let range = self.slots.partition_point(|range| range.first <= slot).checked_sub(1)?;
+                Some(&self.slots[range]).filter(|range| slot <= range.last)
+            })
+            .map_or(0, |range| range.node);
+        &self.nodes[node]
+    }
+
+    fn connection_for(&self, command: &[RespValue]) -> &PairedConnection {
+        let node = self.node_for(command_key(command));
+        let next = self.next.fetch_add(1, Ordering::Relaxed);
+        &node.connections[next % node.connections.len()]
+    }
+
+    pub async fn send(&self, command: Vec<RespValue>) -> Result<RespValue, redis_async::error::Error> {
+        self.connection_for(&command).send::<RespValue>(RespValue::Array(command)).await
+    }
+
+    pub fn send_and_forget(&self, command: Vec<RespValue>) {
+        self.connection_for(&command).send_and_forget(RespValue::Array(command));
+    }
+
+    // PING every pooled connection
+    pub async fn health(&self) -> Vec<RedisNodeHealth> {
+        join_all(self.nodes.iter().map(|node| async move {
+            let started = Instant::now();
+            let pings = join_all(node.connections.iter().map(ping)).await;
+            let healthy_connections = pings.iter().filter(|ping| ping.is_ok()).count();
+            RedisNodeHealth {
+                address: format!("{}:{}", node.host, node.port),
+                connections: node.connections.len(),
+                healthy_connections,
+                latency_ms: (healthy_connections > 0).then(|| started.elapsed().as_millis() as u64),
+            }
+        })).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_redis_cluster_slots() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_slot(b"foo"), 12182);
+        assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+        // An empty tag hashes the whole key
+        assert_eq!(key_slot(b"foo{}bar"), crc16(b"foo{}bar") % CLUSTER_SLOTS);
+
+        let reply = RespValue::Array(vec![
+            RespValue::Array(vec![
+                RespValue::Integer(0),
+                RespValue::Integer(8191),
+                RespValue::Array(vec![RespValue::BulkString(b"10.0.0.1".to_vec()), RespValue::Integer(7000)]),
+                RespValue::Array(vec![RespValue::BulkString(b"10.0.0.3".to_vec()), RespValue::Integer(7002)]),
+            ]),
+            RespValue::Array(vec![
+                RespValue::Integer(8192),
+                RespValue::Integer(16383),
+                RespValue::Array(vec![RespValue::BulkString(b"10.0.0.2".to_vec()), RespValue::Integer(7001), RespValue::BulkString(b"id".to_vec())]),
+            ]),
+        ]);
+        let ranges = parse_cluster_slots(reply).unwrap();
+        assert_eq!(ranges[1], (8192, 16383, "10.0.0.2".to_string(), 7001));
+        assert!(parse_cluster_slots(RespValue::Integer(1)).is_err());
+
+        assert_eq!(parse_addresses("sentinel-1:26379, sentinel-2:26380,broken"), vec![
+            ("sentinel-1".to_string(), 26379),
+            ("sentinel-2".to_string(), 26380),
+        ]);
+        assert!(is_topology_change("MOVED 3999 127.0.0.1:6381"));
+        assert!(!is_topology_change("WRONGTYPE Operation against a key holding the wrong kind of value"));
+    }
+}
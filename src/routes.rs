@@ -1,7 +1,20 @@
 use actix_web::web;
-use crate::health::get_health;
+use crate::analytics::retrieve_group_analytics;
+use crate::bots::{create_link_code, delete_bot_account, discord_interactions, retrieve_bot_accounts, telegram_webhook};
+use crate::candles::retrieve_candles;
+use crate::categories::retrieve_categories;
+use crate::currency::retrieve_currencies;
+use crate::health::{get_cache_health, get_health, get_readiness};
+use crate::import_export::{export_all_watchlist_groups, export_watchlist_group, import_watchlist_group};
+use crate::import_formats::import_watchlist_group_from_file;
+use crate::metrics::{get_metrics, METRICS_PATH};
+use crate::notifications::{delete_notification_channel, mark_all_notifications_read, mark_notification_read, retrieve_notification_channels, retrieve_notification_deliveries, retrieve_notifications, send_test_notification, update_notification_channel};
+use crate::portfolio::{create_transaction, delete_transaction, retrieve_portfolio, retrieve_transactions};
+use crate::preferences::{retrieve_user_preferences, update_user_preferences};
+use crate::screener::{retrieve_screener, save_screener_as_watchlist_group};
+use crate::smart_groups::{create_smart_watchlist_group, delete_smart_rule, update_smart_rule};
 use crate::watchlist::{create_watchlist, delete_watchlist, retrieve_all_watchlist};
-use crate::watchlistgroup::{create_watchlist_group, delete_watchlist_group, retrieve_all_watchlist_groups, update_watchlist_group};
+use crate::watchlistgroup::{create_watchlist_group, delete_watchlist_group, restore_watchlist_group, retrieve_all_watchlist_groups, retrieve_deleted_watchlist_groups, retrieve_watchlist_group, update_watchlist_group};
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg
@@ -9,14 +22,74 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
             web::resource("health")
                 .route(web::get().to(get_health))
         )
+        .service(
+            web::resource("health/live")
+                .route(web::get().to(get_health))
+        )
+        .service(
+            web::resource("health/ready")
+                .route(web::get().to(get_readiness))
+        )
+        .service(
+            web::resource("health/cache")
+                .route(web::get().to(get_cache_health))
+        )
+        .service(
+            web::resource(METRICS_PATH)
+                .route(web::get().to(get_metrics))
+        )
         .service(
             web::scope("/api/v1")
                 .service(
                     web::scope("/watchlistgroup")
                         .route("", web::get().to(retrieve_all_watchlist_groups))
                         .route("", web::post().to(create_watchlist_group))
+                        .route("/trash", web::get().to(retrieve_deleted_watchlist_groups))
+                        .route("/export", web::get().to(export_all_watchlist_groups))
+                        .route("/import", web::post().to(import_watchlist_group_from_file))
+                        .route("/smart", web::post().to(create_smart_watchlist_group))
+                        .route("/{group_id}/export", web::get().to(export_watchlist_group))
+                        .route("/{group_id}/import", web::post().to(import_watchlist_group))
+                        .route("/{group_id}/rule", web::put().to(update_smart_rule))
+                        .route("/{group_id}/rule", web::delete().to(delete_smart_rule))
+                        .route("/{group_id}/analytics", web::get().to(retrieve_group_analytics))
+                        .route("/{group_id}/portfolio", web::get().to(retrieve_portfolio))
+                        .route("/{group_id}/transactions", web::get().to(retrieve_transactions))
+                        .route("/{group_id}/transactions", web::post().to(create_transaction))
+                        .route("/{group_id}/transactions/{transaction_id}", web::delete().to(delete_transaction))
+                        .route("/{group_id}", web::get().to(retrieve_watchlist_group))
                         .route("/{group_id}", web::put().to(update_watchlist_group))
                         .route("/{group_id}", web::delete().to(delete_watchlist_group))
+                        .route("/{group_id}/restore", web::post().to(restore_watchlist_group))
+                )
+                .route("/currencies", web::get().to(retrieve_currencies))
+                .route("/categories", web::get().to(retrieve_categories))
+                .route("/screener", web::get().to(retrieve_screener))
+                .route("/screener/watchlistgroup", web::post().to(save_screener_as_watchlist_group))
+                .route("/preferences", web::get().to(retrieve_user_preferences))
+                .route("/preferences", web::put().to(update_user_preferences))
+                .service(
+                    web::scope("/bots")
+                        .route("/link", web::post().to(create_link_code))
+                        .route("/accounts", web::get().to(retrieve_bot_accounts))
+                        .route("/accounts/{platform}", web::delete().to(delete_bot_account))
+                        .route("/hooks/telegram", web::post().to(telegram_webhook))
+                        .route("/hooks/discord", web::post().to(discord_interactions))
+                )
+                .service(
+                    web::scope("/notifications")
+                        .route("", web::get().to(retrieve_notifications))
+                        .route("/read", web::post().to(mark_all_notifications_read))
+                        .route("/channels", web::get().to(retrieve_notification_channels))
+                        .route("/channels/{channel}", web::put().to(update_notification_channel))
+                        .route("/channels/{channel}", web::delete().to(delete_notification_channel))
+                        .route("/deliveries", web::get().to(retrieve_notification_deliveries))
+                        .route("/test", web::post().to(send_test_notification))
+                        .route("/{notification_id}/read", web::post().to(mark_notification_read))
+                )
+                .service(
+                    web::scope("/assets")
+                        .route("/{asset_id}/candles", web::get().to(retrieve_candles))
                 )
                 .service(
                     web::scope("/watchlist")
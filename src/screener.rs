@@ -0,0 +1,357 @@
+use std::sync::Arc;
+use actix_web::{HttpMessage, HttpRequest};
+use actix_web::web::{Data, Json, Query};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use sqlx::Row;
+use sqlx::postgres::PgRow;
+use sqlx::types::Json as SqlJson;
+use tracing::instrument;
+use crate::cache::CacheKey;
+use crate::categories::{categories_json, category_filter, AssetCategory};
+use crate::config::CONFIG;
+use crate::currency::{conversion_rate, convert_price, parse_currency};
+use crate::database::{Database, SqlConditions};
+use crate::errors::ApiError;
+use crate::errors::ApiError::BadRequest;
+use crate::helpers::{format_datetime, respond_json, Timestamp, TimestampFormat};
+use crate::middleware_custom::Claims;
+use crate::pagination::{page_limit, paginate, KeysetSort, Page, SortOrder};
+use crate::preferences::load_user_preferences;
+use crate::server::AppState;
+use crate::watchlist::insert_watchlist_entries;
+use crate::watchlistgroup::{insert_watchlist_group, WatchlistGroupResponse};
+
+const CACHE_KEY: &str = "screener";
+// Matches assets that are not tokens on another chain
+const NATIVE_PLATFORM: &str = "native";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScreenerSortKey {
+    #[default]
+    Rank,
+    MarketCap,
+    #[serde(rename = "volume_24h")]
+    Volume24h,
+    #[serde(rename = "change_24h")]
+    Change24h,
+    #[serde(rename = "change_7d")]
+    Change7d,
+    Price,
+    Name,
+}
+
+impl ScreenerSortKey {
+    // Rank and name read best ascending, the market figures descending
+    pub fn default_order(&self) -> SortOrder {
+        match self {
+            ScreenerSortKey::Rank | ScreenerSortKey::Name => SortOrder::Asc,
+            _ => SortOrder::Desc,
+        }
+    }
+
+    // Assets without the figure go last in either direction
+    pub fn keyset(&self, order: SortOrder) -> KeysetSort {
+        let (name, ascending, descending) = match self {
+            ScreenerSortKey::Rank => ("rank", "COALESCE(a.rank, 2147483647)", "COALESCE(a.rank, -1)"),
+            ScreenerSortKey::MarketCap => ("market_cap", "COALESCE(q.market_cap, 'Infinity')", "COALESCE(q.market_cap, '-Infinity')"),
+            ScreenerSortKey::Volume24h => ("volume_24h", "COALESCE(q.volume_24h, 'Infinity')", "COALESCE(q.volume_24h, '-Infinity')"),
+            ScreenerSortKey::Change24h => ("change_24h", "COALESCE(q.percent_change_24h, 'Infinity')", "COALESCE(q.percent_change_24h, '-Infinity')"),
+            ScreenerSortKey::Change7d => ("change_7d", "COALESCE(q.percent_change_7d, 'Infinity')", "COALESCE(q.percent_change_7d, '-Infinity')"),
+            ScreenerSortKey::Price => ("price", "COALESCE(q.price, 'Infinity')", "COALESCE(q.price, '-Infinity')"),
+            ScreenerSortKey::Name => ("name", "LOWER(COALESCE(a.name, ''))", "LOWER(COALESCE(a.name, ''))"),
+        };
+        let sql_type = match self {
+            ScreenerSortKey::Rank => "int",
+            ScreenerSortKey::Name => "text",
+            _ => "float8",
+        };
+        let expression = match order {
+            SortOrder::Asc => ascending,
+            SortOrder::Desc => descending,
+        };
+        KeysetSort { name, expression, sql_type, order }
+    }
+}
+
+// Market cap and volume bounds are in the `convert` currency, changes are in percent
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScreenerQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<ScreenerSortKey>,
+    order: Option<SortOrder>,
+    min_market_cap: Option<f64>,
+    max_market_cap: Option<f64>,
+    min_volume: Option<f64>,
+    max_volume: Option<f64>,
+    min_change_24h: Option<f64>,
+    max_change_24h: Option<f64>,
+    min_change_7d: Option<f64>,
+    max_change_7d: Option<f64>,
+    min_rank: Option<i32>,
+    max_rank: Option<i32>,
+    // Comma separated platform slugs, e.g. `ethereum,solana`, or `native` for coins with their own chain
+    platform: Option<String>,
+    // Comma separated tags an asset must all have, e.g. `defi,layer-2`
+    tags: Option<String>,
+    // Category id or name
+    category: Option<String>,
+    convert: Option<String>,
+}
+
+impl ScreenerQuery {
+    fn filters(&self) -> ScreenerFilters {
+        ScreenerFilters {
+            min_market_cap: self.min_market_cap,
+            max_market_cap: self.max_market_cap,
+            min_volume: self.min_volume,
+            max_volume: self.max_volume,
+            min_change_24h: self.min_change_24h,
+            max_change_24h: self.max_change_24h,
+            min_change_7d: self.min_change_7d,
+            max_change_7d: self.max_change_7d,
+            min_rank: self.min_rank,
+            max_rank: self.max_rank,
+            platform: self.platform.clone(),
+            tags: self.tags.clone(),
+            category: self.category.clone(),
+        }
+    }
+}
+
+// The filters of `ScreenerQuery`, also saved as the rule of smart watchlist groups
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScreenerFilters {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_market_cap: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_market_cap: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_volume: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_volume: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_change_24h: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_change_24h: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_change_7d: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_change_7d: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_rank: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rank: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScreenerAssetResponse {
+    id: i32,
+    name: Option<String>,
+    symbol: String,
+    slug: Option<String>,
+    rank: Option<i32>,
+    platform: Option<String>,
+    tags: Vec<String>,
+    categories: Vec<AssetCategory>,
+    price: Option<f64>,
+    market_cap: Option<f64>,
+    volume_24h: Option<f64>,
+    percent_change_24h: Option<f64>,
+    percent_change_7d: Option<f64>,
+    quoted_at: Option<Timestamp>,
+}
+
+impl ScreenerAssetResponse {
+    fn from_row(record: &PgRow, rate: f64) -> Self {
+        ScreenerAssetResponse {
+            id: record.get("id"),
+            name: record.get("name"),
+            symbol: record.get("symbol"),
+            slug: record.get("slug"),
+            rank: record.get("rank"),
+            platform: record.get("platform_slug"),
+            tags: record.get("tags"),
+            categories: record.get::<SqlJson<Vec<AssetCategory>>, _>("categories").0,
+            price: convert_price(record.get("price"), rate),
+            market_cap: convert_price(record.get("market_cap"), rate),
+            volume_24h: convert_price(record.get("volume_24h"), rate),
+            percent_change_24h: record.get("percent_change_24h"),
+            percent_change_7d: record.get("percent_change_7d"),
+            // Quotes are shared between users, so they are rendered in UTC
+            quoted_at: format_datetime(record.get::<Option<DateTime<Utc>>, _>("updated_at"), Tz::UTC, TimestampFormat::default()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScreenerResponse {
+    currency: String,
+    #[serde(flatten)]
+    page: Page<ScreenerAssetResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScreenerSaveRequest {
+    name: String,
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim().to_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// Amounts in the requested currency are compared against the USD columns
+fn to_usd(amount: Option<f64>, rate: f64) -> Option<f64> {
+    amount.map(|amount| amount / rate)
+}
+
+// Conditions over `assets a LEFT JOIN asset_quotes q`, with amounts in the currency of `rate`
+pub fn screener_conditions(filters: &ScreenerFilters, rate: f64) -> SqlConditions {
+    let mut conditions = SqlConditions::new();
+    let bounds = [
+        ("q.market_cap >= $?", to_usd(filters.min_market_cap, rate)),
+        ("q.market_cap <= $?", to_usd(filters.max_market_cap, rate)),
+        ("q.volume_24h >= $?", to_usd(filters.min_volume, rate)),
+        ("q.volume_24h <= $?", to_usd(filters.max_volume, rate)),
+        ("q.percent_change_24h >= $?", filters.min_change_24h),
+        ("q.percent_change_24h <= $?", filters.max_change_24h),
+        ("q.percent_change_7d >= $?", filters.min_change_7d),
+        ("q.percent_change_7d <= $?", filters.max_change_7d),
+    ];
+    for (clause, bound) in bounds {
+        if let Some(bound) = bound {
+            conditions.push(clause, bound);
+        }
+    }
+    if let Some(min_rank) = filters.min_rank {
+        conditions.push("a.rank >= $?", min_rank);
+    }
+    if let Some(max_rank) = filters.max_rank {
+        conditions.push("a.rank <= $?", max_rank);
+    }
+    if let Some(platform) = &filters.platform {
+        let platforms = split_list(platform);
+        let native = platforms.iter().any(|platform| platform == NATIVE_PLATFORM);
+        let param = conditions.bind(platforms);
+        let native_clause = if native { " OR a.platform_slug IS NULL" } else { "" };
+        conditions.push_clause(format!("(LOWER(a.platform_slug) = ANY(${}){})", param, native_clause));
+    }
+    if let Some(tags) = &filters.tags {
+        conditions.push("a.tags @> $?::text[]", split_list(tags));
+    }
+    if let Some(category) = &filters.category {
+        conditions.push(&category_filter("a.id"), category.trim());
+    }
+    conditions
+}
+
+async fn screen_assets(db: &Arc<dyn Database>, query: &ScreenerQuery, rate: f64) -> Result<Page<ScreenerAssetResponse>, ApiError> {
+    let limit = page_limit(query.limit)?;
+    let sort_key = query.sort.unwrap_or_default();
+    let sort = sort_key.keyset(query.order.unwrap_or(sort_key.default_order()));
+    let cursor = sort.decode_cursor(query.cursor.as_deref())?;
+
+    let mut conditions = screener_conditions(&query.filters(), rate);
+    if let Some(cursor) = cursor {
+        let value_param = conditions.bind(cursor.value);
+        let id_param = conditions.bind(cursor.id);
+        conditions.push_clause(sort.after("a.id", value_param, id_param));
+    }
+
+    let sql = format!(
+        "SELECT a.id, a.name, a.symbol, a.slug, a.rank, a.platform_slug, a.tags, \
+                q.price, q.market_cap, q.volume_24h, q.percent_change_24h, q.percent_change_7d, q.updated_at, {} AS categories, {} \
+         FROM assets a LEFT JOIN asset_quotes q ON q.asset_id = a.id {} {} LIMIT {}",
+        categories_json("a.id"), sort.select(), conditions.where_clause(), sort.order_by("a.id"), limit + 1
+    );
+    let records = db
+        .fetch_all(&sql, conditions.into_args())
+        .await?;
+
+    Ok(paginate(records, limit, &sort, "id", |record| ScreenerAssetResponse::from_row(record, rate)))
+}
+
+#[instrument]
+pub async fn retrieve_screener(
+    state: Data<AppState>,
+    request: HttpRequest,
+    query: Query<ScreenerQuery>,
+) -> Result<Json<ScreenerResponse>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    let currency = parse_currency(query.convert.as_deref(), &preferences.quote_currency)?;
+    // Cleared by every quotes sync and expired in between in case the sync is disabled
+    let cache_field = format!("{}::{}", currency, serde_json::to_string(&query.0)?);
+
+    let response = state.redis_client.read_through(CacheKey::new(CACHE_KEY.to_string(), cache_field).ttl(CONFIG.screener_cache_ttl_secs), async {
+        let rate = conversion_rate(&state.redis_client, &currency).await?;
+        let response = ScreenerResponse { currency, page: screen_assets(&state.db, &query, rate).await? };
+
+        Ok(response)
+    })
+    .await?;
+
+    respond_json(response)
+}
+
+// Create a watchlist group from the page of results the same query returns
+#[instrument]
+pub async fn save_screener_as_watchlist_group(
+    state: Data<AppState>,
+    request: HttpRequest,
+    query: Query<ScreenerQuery>,
+    body: Json<ScreenerSaveRequest>,
+) -> Result<Json<WatchlistGroupResponse>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(BadRequest("name must not be empty".into()));
+    }
+
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    let currency = parse_currency(query.convert.as_deref(), &preferences.quote_currency)?;
+    let rate = conversion_rate(&state.redis_client, &currency).await?;
+    let page = screen_assets(&state.db, &query, rate).await?;
+    if page.data.is_empty() {
+        return Err(BadRequest("The screener returned no assets".into()));
+    }
+
+    let watchlist_group = insert_watchlist_group(&state.db, user_id, name, preferences.tz(), TimestampFormat::default()).await?;
+    let asset_ids: Vec<i32> = page.data.iter().map(|asset| asset.id).collect();
+    insert_watchlist_entries(&state.db, watchlist_group.id, &asset_ids).await?;
+
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
+
+    respond_json(watchlist_group)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_screener_keyset() {
+        let sort = ScreenerSortKey::MarketCap.keyset(ScreenerSortKey::MarketCap.default_order());
+        assert_eq!(sort.order, SortOrder::Desc);
+        assert_eq!(sort.expression, "COALESCE(q.market_cap, '-Infinity')");
+
+        let sort = ScreenerSortKey::MarketCap.keyset(SortOrder::Asc);
+        assert_eq!(sort.expression, "COALESCE(q.market_cap, 'Infinity')");
+        assert_eq!(ScreenerSortKey::Rank.default_order(), SortOrder::Asc);
+
+        assert_eq!(split_list(" Ethereum, ,native"), vec!["ethereum", "native"]);
+        assert_eq!(to_usd(Some(160.0), 16000.0), Some(0.01));
+    }
+}
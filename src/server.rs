@@ -1,30 +1,25 @@
-use std::error::Error;
-use actix_web::{App, HttpServer, middleware, web};
+use actix_web::{App, HttpServer, web};
 use dotenv::dotenv;
-use env_logger::Builder;
 use log::{debug, error, info};
-use sqlx::{Pool, Postgres};
 use crate::config::CONFIG;
-use crate::database::{create_pool, Database, PostgresDB};
-use std::io::Write;
-use std::sync::{Arc, mpsc};
-use std::sync::mpsc::{Receiver, Sender};
-use std::thread;
+use crate::database::{create_pool, Database};
+use std::sync::Arc;
 use std::time::Duration;
-use kafka::client::RequiredAcks;
-use kafka::producer::{Compression, Producer, Record};
-use redis_async::client::PairedConnection;
 use tracing_actix_web::root_span_macro::private::tracing;
 use tracing_actix_web::TracingLogger;
 use tracing_appender::rolling;
-use tracing_appender::rolling::{RollingFileAppender, Rotation};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::{EnvFilter, Registry};
+use crate::bots::{register_discord_commands, BotPlatform};
 use crate::cache::{create_redis_client, Redis};
-use crate::data_provider::feed_assets_data;
+use crate::data_provider::{backfill_candles, feed_assets_data, sync_asset_categories, sync_asset_quotes};
 use crate::routes::routes;
+use crate::smart_groups::refresh_smart_groups;
+use crate::telemetry::{init_tracer, shutdown_tracer};
+use crate::watchlistgroup::purge_deleted_watchlist_groups;
+use crate::metrics::time_sync;
 use crate::middleware_custom;
 
 #[derive(Debug)]
@@ -43,44 +38,68 @@ pub async fn server() -> std::io::Result<()> {
     let file_appender = rolling::daily(&CONFIG.log_file_location, env!("CARGO_PKG_NAME"));
     let (non_blocking_writer, _guard) = tracing_appender::non_blocking(file_appender);
     let bunyan_formatting_layer = BunyanFormattingLayer::new(app_name, non_blocking_writer);
+    let tracer = init_tracer().expect("Unable to setup the trace exporter!");
     let subscriber = Registry::default()
         .with(EnvFilter::from_env("LOG_LEVEL"))
         .with(JsonStorageLayer)
-        .with(bunyan_formatting_layer);
+        .with(bunyan_formatting_layer)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)));
     tracing::subscriber::set_global_default(subscriber).unwrap();
 
     // Init Postgres
-    let tmp_pool;
-    match create_pool().await {
-        Ok(conn) => {
-            tmp_pool = conn;
-        }
+    let tmp_pool = match create_pool().await {
+        Ok(conn) => conn,
         Err(err) => {
             error!("Failed to create database pool: {}", err);
             std::process::exit(1);
         }
-    }
+    };
 
     // Init Redis
-    let tmp_redis_client;
-    match create_redis_client().await {
-        Ok(redis_client_out) => {
-            tmp_redis_client = redis_client_out;
-        }
+    let tmp_redis_client = match create_redis_client().await {
+        Ok(redis_client_out) => redis_client_out,
         Err(err) => {
             error!("Failed to connect to Redis: {}", err);
             std::process::exit(1);
         }
-    }
+    };
 
     if CONFIG.is_feed_assets_data_enabled {
-        match feed_assets_data(tmp_pool.clone()).await {
+        match time_sync("feed_assets", feed_assets_data(tmp_pool.clone())).await {
             Ok(()) => debug!("data has been fed to db successfully."),
             Err(err) => {
                 error!("There is an error when trying to feed the data to db: {}", err);
                 std::process::exit(1);
             }
         }
+
+        // The service still works without categories, so a failed sync is not fatal
+        if let Err(err) = time_sync("asset_categories", sync_asset_categories(tmp_pool.clone(), tmp_redis_client.clone())).await {
+            error!("There is an error when trying to sync the asset categories: {}", err);
+        }
+    }
+
+    spawn_redis_reconnect(tmp_redis_client.clone());
+    if CONFIG.local_cache_capacity > 0 {
+        spawn_cache_invalidation_listener(tmp_redis_client.clone());
+    }
+    spawn_watchlist_group_purge(tmp_pool.clone());
+    if CONFIG.is_candles_backfill_enabled {
+        spawn_candles_backfill(tmp_pool.clone(), tmp_redis_client.clone());
+    }
+    if CONFIG.is_asset_quotes_sync_enabled {
+        spawn_asset_quotes_sync(tmp_pool.clone(), tmp_redis_client.clone());
+    }
+    if CONFIG.is_smart_groups_refresh_enabled {
+        spawn_smart_groups_refresh(tmp_pool.clone(), tmp_redis_client.clone());
+    }
+
+    if BotPlatform::Discord.is_enabled() && !CONFIG.discord_application_id.is_empty() {
+        actix_rt::spawn(async {
+            if let Err(err) = register_discord_commands().await {
+                error!("Failed to register the Discord commands: {}", err);
+            }
+        });
     }
 
     info!("🚀 Server started successfully");
@@ -89,11 +108,82 @@ pub async fn server() -> std::io::Result<()> {
         App::new()
             .wrap(TracingLogger::default())
             .wrap(middleware_custom::JWTMiddleware::new(CONFIG.jwt_secret.to_string()))
+            .wrap(middleware_custom::RequestMetrics)
             .app_data(web::Data::new(AppState{
                 db: tmp_pool.clone(),
                 redis_client: tmp_redis_client.clone(),
             }))
             .configure(routes)
     });
-    server.bind(&CONFIG.server)?.run().await
-}
\ No newline at end of file
+    let result = server.bind(&CONFIG.server)?.run().await;
+    shutdown_tracer();
+    result
+}
+
+// Periodically try to reconnect to Redis while its circuit breaker is open
+fn spawn_redis_reconnect(redis_client: Arc<Redis>) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(Duration::from_secs(CONFIG.redis_reconnect_interval_secs));
+        loop {
+            interval.tick().await;
+            redis_client.reconnect_if_down().await;
+        }
+    });
+}
+
+fn spawn_cache_invalidation_listener(redis_client: Arc<Redis>) {
+    actix_rt::spawn(async move {
+        redis_client.listen_for_invalidations().await;
+    });
+}
+
+// Periodically purge watchlist groups whose retention period in the trash has passed
+fn spawn_watchlist_group_purge(db: Arc<dyn Database>) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(Duration::from_secs(CONFIG.watchlist_group_purge_interval_secs));
+        loop {
+            interval.tick().await;
+            match purge_deleted_watchlist_groups(&db, CONFIG.watchlist_group_retention_days).await {
+                Ok(purged) => debug!("Purged {} deleted watchlist groups", purged),
+                Err(err) => error!("Failed to purge deleted watchlist groups: {}", err),
+            }
+        }
+    });
+}
+
+fn spawn_candles_backfill(db: Arc<dyn Database>, redis_client: Arc<Redis>) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(Duration::from_secs(CONFIG.candles_backfill_interval_secs));
+        loop {
+            interval.tick().await;
+            if let Err(err) = time_sync("candles_backfill", backfill_candles(db.clone(), redis_client.clone())).await {
+                error!("There is an error when trying to backfill candles: {}", err);
+            }
+        }
+    });
+}
+
+fn spawn_asset_quotes_sync(db: Arc<dyn Database>, redis_client: Arc<Redis>) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(Duration::from_secs(CONFIG.asset_quotes_sync_interval_secs));
+        loop {
+            interval.tick().await;
+            if let Err(err) = time_sync("asset_quotes", sync_asset_quotes(db.clone(), redis_client.clone())).await {
+                error!("There is an error when trying to sync asset quotes: {}", err);
+            }
+        }
+    });
+}
+
+fn spawn_smart_groups_refresh(db: Arc<dyn Database>, redis_client: Arc<Redis>) {
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(Duration::from_secs(CONFIG.smart_groups_refresh_interval_secs));
+        loop {
+            interval.tick().await;
+            match refresh_smart_groups(&db, &redis_client).await {
+                Ok(refreshed) => debug!("Refreshed {} smart watchlist groups", refreshed),
+                Err(err) => error!("Failed to refresh smart watchlist groups: {}", err),
+            }
+        }
+    });
+}
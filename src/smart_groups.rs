@@ -0,0 +1,325 @@
+use std::sync::Arc;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use actix_web::web::{Data, Json, Path, Query};
+use sqlx::{Arguments, Row};
+use sqlx::postgres::PgArguments;
+use sqlx::types::Json as SqlJson;
+use tracing::{error, instrument};
+use crate::cache::Redis;
+use crate::categories::find_category;
+use crate::config::CONFIG;
+use crate::database::Database;
+use crate::errors::ApiError;
+use crate::errors::ApiError::BadRequest;
+use crate::helpers::{respond_json, respond_ok, TimestampQuery};
+use crate::middleware_custom::Claims;
+use crate::pagination::SortOrder;
+use crate::preferences::load_user_preferences;
+use crate::screener::{screener_conditions, ScreenerFilters, ScreenerSortKey};
+use crate::server::AppState;
+use crate::watchlistgroup::{find_user_watchlist_group, insert_watchlist_group, touch_watchlist_group, WatchlistGroupResponse, BUMP_VERSION};
+
+const DEFAULT_RULE_LIMIT: i32 = 100;
+const MAX_RULE_LIMIT: i32 = 1000;
+
+// Membership of a smart group: the first `limit` assets matching the screener filters in the
+// given order. Market cap and volume bounds are in USD.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SmartRule {
+    #[serde(default)]
+    pub filters: ScreenerFilters,
+    #[serde(default)]
+    pub sort: ScreenerSortKey,
+    // Defaults to the natural order of `sort`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<SortOrder>,
+    #[serde(default = "default_rule_limit")]
+    pub limit: i32,
+}
+
+fn default_rule_limit() -> i32 {
+    DEFAULT_RULE_LIMIT
+}
+
+impl SmartRule {
+    // The top `limit` assets of a category by rank
+    fn for_category(category_id: String, limit: Option<i32>) -> Self {
+        SmartRule {
+            filters: ScreenerFilters { category: Some(category_id), ..ScreenerFilters::default() },
+            sort: ScreenerSortKey::Rank,
+            order: None,
+            limit: limit.unwrap_or(DEFAULT_RULE_LIMIT),
+        }
+    }
+
+    // Checks that need no database access
+    fn check(&self) -> Result<(), ApiError> {
+        if !(1..=MAX_RULE_LIMIT).contains(&self.limit) {
+            return Err(BadRequest(format!("limit must be between 1 and {}", MAX_RULE_LIMIT)));
+        }
+
+        let filters = &self.filters;
+        let ranges = [
+            ("market_cap", filters.min_market_cap, filters.max_market_cap),
+            ("volume", filters.min_volume, filters.max_volume),
+            ("change_24h", filters.min_change_24h, filters.max_change_24h),
+            ("change_7d", filters.min_change_7d, filters.max_change_7d),
+            ("rank", filters.min_rank.map(f64::from), filters.max_rank.map(f64::from)),
+        ];
+        for (name, min, max) in ranges {
+            if [min, max].into_iter().flatten().any(|bound| !bound.is_finite()) {
+                return Err(BadRequest(format!("The {} bounds must be finite numbers", name)));
+            }
+            if let (Some(min), Some(max)) = (min, max) {
+                if min > max {
+                    return Err(BadRequest(format!("min_{} must not be greater than max_{}", name, name)));
+                }
+            }
+        }
+        if [filters.min_market_cap, filters.max_market_cap, filters.min_volume, filters.max_volume].into_iter().flatten().any(|bound| bound < 0.0) {
+            return Err(BadRequest("The market_cap and volume bounds must not be negative".into()));
+        }
+        if [filters.min_rank, filters.max_rank].into_iter().flatten().any(|rank| rank < 1) {
+            return Err(BadRequest("The rank bounds must be at least 1".into()));
+        }
+        for (name, list) in [("platform", &filters.platform), ("tags", &filters.tags)] {
+            if list.as_deref().is_some_and(|list| list.split(',').all(|item| item.trim().is_empty())) {
+                return Err(BadRequest(format!("{} must not be empty", name)));
+            }
+        }
+        Ok(())
+    }
+
+    // Validate the rule and store the category by id, so renaming it does not break the group
+    async fn validate(mut self, db: &Arc<dyn Database>) -> Result<Self, ApiError> {
+        self.check()?;
+        if let Some(category) = &self.filters.category {
+            self.filters.category = Some(find_category(db, category).await?.id);
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SmartGroupRequest {
+    // Defaults to the category name for category groups
+    name: Option<String>,
+    rule: Option<SmartRule>,
+    // Shorthand for a rule holding the top `limit` assets of a category by rank
+    category: Option<String>,
+    limit: Option<i32>,
+}
+
+// Entries of smart groups follow their rule, so they cannot be edited by hand
+pub async fn ensure_manual_group(db: &Arc<dyn Database>, group_id: i32) -> Result<(), ApiError> {
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    let record = db
+        .fetch_optional("SELECT smart_rule IS NOT NULL AS is_smart FROM watchlist_groups WHERE id = $1", args)
+        .await?;
+
+    match record.map(|record| record.get::<bool, _>("is_smart")) {
+        Some(true) => Err(BadRequest("The entries of a smart watchlist group follow its rule and cannot be changed".into())),
+        _ => Ok(()),
+    }
+}
+
+async fn evaluate_rule(db: &Arc<dyn Database>, rule: &SmartRule) -> Result<Vec<i32>, ApiError> {
+    let conditions = screener_conditions(&rule.filters, 1.0);
+    let sort = rule.sort.keyset(rule.order.unwrap_or(rule.sort.default_order()));
+    let sql = format!(
+        "SELECT a.id FROM assets a LEFT JOIN asset_quotes q ON q.asset_id = a.id {} {} LIMIT {}",
+        conditions.where_clause(), sort.order_by("a.id"), rule.limit
+    );
+    let records = db
+        .fetch_all(&sql, conditions.into_args())
+        .await?;
+
+    Ok(records.iter().map(|record| record.get("id")).collect())
+}
+
+// Make the entries of a smart group the current result of its rule.
+// Returns whether any entry was added or removed.
+async fn refresh_smart_group(db: &Arc<dyn Database>, redis: &Arc<Redis>, group_id: i32, user_id: i32, rule: &SmartRule) -> Result<bool, ApiError> {
+    let asset_ids = evaluate_rule(db, rule).await?;
+
+    // One statement, so readers never see the group half refreshed and a failure leaves it untouched
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    args.add(&asset_ids);
+    let record = db
+        .fetch_one(r#"WITH removed AS (
+                          DELETE FROM watchlist WHERE group_id = $1 AND asset_id <> ALL($2::int[]) RETURNING 1
+                      ), added AS (
+                          INSERT INTO watchlist (group_id, asset_id) SELECT $1, UNNEST($2::int[]) ON CONFLICT DO NOTHING RETURNING 1
+                      ), refreshed AS (
+                          UPDATE watchlist_groups SET smart_refreshed_at = NOW() WHERE id = $1
+                      )
+                      SELECT (SELECT COUNT(*) FROM removed) + (SELECT COUNT(*) FROM added) AS changed_entries"#, args)
+        .await?;
+
+    let changed = record.get::<i64, _>("changed_entries") > 0;
+    if changed {
+        touch_watchlist_group(db, group_id).await?;
+        redis.del(format!("all_watchlist::{}", group_id)).await;
+        redis.del(format!("group_analytics::{}", group_id)).await;
+        redis.del(format!("all_watchlist_group::{}", user_id)).await;
+    }
+    Ok(changed)
+}
+
+// Called on a schedule and after every quotes or category sync, returns the number of groups refreshed.
+// A group that fails to refresh is logged and keeps its entries, the others are still refreshed.
+pub async fn refresh_smart_groups(db: &Arc<dyn Database>, redis: &Arc<Redis>) -> Result<usize, ApiError> {
+    let records = db
+        .fetch_all("SELECT id, user_id, smart_rule FROM watchlist_groups WHERE smart_rule IS NOT NULL AND deleted_at IS NULL", PgArguments::default())
+        .await?;
+
+    let mut refreshed = 0;
+    for record in &records {
+        let group_id: i32 = record.get("id");
+        // A rule that no longer parses is skipped rather than emptying the group
+        let rule = match record.try_get::<SqlJson<SmartRule>, _>("smart_rule") {
+            Ok(rule) => rule.0,
+            Err(err) => {
+                error!("Invalid rule on smart watchlist group {}: {}", group_id, err);
+                continue;
+            }
+        };
+        match refresh_smart_group(db, redis, group_id, record.get("user_id"), &rule).await {
+            Ok(_) => refreshed += 1,
+            Err(err) => error!("Failed to refresh smart watchlist group {}: {}", group_id, err),
+        }
+    }
+    Ok(refreshed)
+}
+
+// Re-evaluate a smart group on read when it has not been refreshed for `smart_groups_refresh_interval_secs`
+pub async fn refresh_stale_smart_group(db: &Arc<dyn Database>, redis: &Arc<Redis>, group_id: i32) -> Result<(), ApiError> {
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    args.add(CONFIG.smart_groups_refresh_interval_secs as f64);
+    let record = db
+        .fetch_optional(r#"SELECT user_id, smart_rule FROM watchlist_groups
+                           WHERE id = $1 AND smart_rule IS NOT NULL AND deleted_at IS NULL
+                             AND (smart_refreshed_at IS NULL OR smart_refreshed_at < NOW() - make_interval(secs => $2))"#, args)
+        .await?;
+
+    if let Some(record) = record {
+        let rule: SqlJson<SmartRule> = record.get("smart_rule");
+        refresh_smart_group(db, redis, group_id, record.get("user_id"), &rule.0).await?;
+    }
+    Ok(())
+}
+
+async fn save_rule(db: &Arc<dyn Database>, group_id: i32, rule: Option<&SmartRule>) -> Result<(), ApiError> {
+    let mut args = PgArguments::default();
+    args.add(rule.map(SqlJson));
+    args.add(group_id);
+    db.execute(&format!("UPDATE watchlist_groups SET smart_rule = $1, smart_refreshed_at = NULL, {} WHERE id = $2", BUMP_VERSION), args).await?;
+    Ok(())
+}
+
+#[instrument]
+pub async fn create_smart_watchlist_group(
+    state: Data<AppState>,
+    request: HttpRequest,
+    query: Query<TimestampQuery>,
+    body: Json<SmartGroupRequest>,
+) -> Result<Json<WatchlistGroupResponse>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let body = body.into_inner();
+    let name = body.name.as_deref().map(str::trim).filter(|name| !name.is_empty());
+
+    let (rule, name) = match (body.rule, body.category) {
+        (Some(_), Some(_)) => return Err(BadRequest("Either rule or category must be set, not both".into())),
+        (None, None) => return Err(BadRequest("Either rule or category must be set".into())),
+        (Some(rule), None) => {
+            let name = name.ok_or_else(|| BadRequest("name must not be empty".into()))?.to_string();
+            (rule, name)
+        }
+        (None, Some(category)) => {
+            let category = find_category(&state.db, &category).await?;
+            let name = name.map(str::to_string).unwrap_or(category.name);
+            (SmartRule::for_category(category.id, body.limit), name)
+        }
+    };
+    let rule = rule.validate(&state.db).await?;
+
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    let watchlist_group = insert_watchlist_group(&state.db, user_id, &name, preferences.tz(), query.time_format.unwrap_or_default()).await?;
+    save_rule(&state.db, watchlist_group.id, Some(&rule)).await?;
+
+    refresh_smart_group(&state.db, &state.redis_client, watchlist_group.id, user_id, &rule).await?;
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
+
+    respond_json(watchlist_group)
+}
+
+// Set the rule of a group, turning a manual group into a smart one
+#[instrument]
+pub async fn update_smart_rule(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<i32>,
+    body: Json<SmartRule>,
+) -> Result<Json<SmartRule>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let group_id = path.into_inner();
+    find_user_watchlist_group(&state.db, user_id, group_id).await?;
+
+    let rule = body.into_inner().validate(&state.db).await?;
+    save_rule(&state.db, group_id, Some(&rule)).await?;
+    refresh_smart_group(&state.db, &state.redis_client, group_id, user_id, &rule).await?;
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
+
+    respond_json(rule)
+}
+
+// Drop the rule of a group, which keeps its current entries as a manual group
+#[instrument]
+pub async fn delete_smart_rule(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<i32>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let group_id = path.into_inner();
+    find_user_watchlist_group(&state.db, user_id, group_id).await?;
+
+    save_rule(&state.db, group_id, None).await?;
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
+
+    respond_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unit_smart_rule_check() {
+        let rule: SmartRule = serde_json::from_str(r#"{"filters": {"platform": "solana"}, "sort": "market_cap", "limit": 20}"#).unwrap();
+        assert!(rule.check().is_ok());
+        assert_eq!(rule.order, None);
+
+        let rule: SmartRule = serde_json::from_str(r#"{"filters": {"min_change_24h": 10}}"#).unwrap();
+        assert_eq!(rule.limit, DEFAULT_RULE_LIMIT);
+        assert_eq!(rule.sort, ScreenerSortKey::Rank);
+
+        assert!(serde_json::from_str::<SmartRule>(r#"{"filters": {"min_price": 1}}"#).is_err());
+        assert!(serde_json::from_str::<SmartRule>(r#"{"limit": 10, "unknown": true}"#).is_err());
+
+        let invalid = [
+            r#"{"limit": 0}"#,
+            r#"{"filters": {"min_market_cap": 10, "max_market_cap": 5}}"#,
+            r#"{"filters": {"min_volume": -1}}"#,
+            r#"{"filters": {"min_rank": 0}}"#,
+            r#"{"filters": {"tags": " , "}}"#,
+        ];
+        for rule in invalid {
+            assert!(serde_json::from_str::<SmartRule>(rule).unwrap().check().is_err(), "{}", rule);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt::Display;
+use std::time::Duration;
+use opentelemetry::propagation::Injector;
+use opentelemetry::trace::TraceError;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{self as sdktrace, Sampler, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::RequestBuilder;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use crate::config::CONFIG;
+
+// Incoming requests continue the trace of a sampled parent, the ratio only applies to new traces
+fn sampler(ratio: f64) -> Sampler {
+    Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio.clamp(0.0, 1.0))))
+}
+
+// Tracer exporting spans over OTLP/gRPC, `None` while no collector endpoint is configured
+pub fn init_tracer() -> Result<Option<Tracer>, TraceError> {
+    // `traceparent` is read from incoming requests by `TracingLogger` and written to outgoing ones
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    if CONFIG.otel_exporter_otlp_endpoint.is_empty() {
+        return Ok(None);
+    }
+
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(&CONFIG.otel_exporter_otlp_endpoint)
+        .with_timeout(Duration::from_millis(CONFIG.otel_exporter_otlp_timeout_ms));
+    let resource = Resource::new(vec![
+        KeyValue::new("service.name", CONFIG.otel_service_name.clone()),
+        KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+    ]);
+
+    // Spans are batched and exported from a thread of their own, off the actix workers
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(sdktrace::config()
+            .with_sampler(sampler(CONFIG.otel_traces_sampler_ratio))
+            .with_resource(resource))
+        .install_batch(runtime::TokioCurrentThread)?;
+    Ok(Some(tracer))
+}
+
+// Export the spans still batched
+pub fn shutdown_tracer() {
+    global::shutdown_tracer_provider();
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(&value)) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+// Add the `traceparent` of the current span, nothing is added while tracing is off
+pub fn with_trace_context(request: RequestBuilder) -> RequestBuilder {
+    let mut headers = HeaderMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut HeaderInjector(&mut headers)));
+    request.headers(headers)
+}
+
+// Flag a span as failed, it must declare empty `otel.status_code` and `error.message` fields
+pub fn record_outcome<T, E: Display>(span: &Span, result: &Result<T, E>) {
+    if let Err(err) = result {
+        span.record("otel.status_code", "ERROR");
+        span.record("error.message", err.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::{SamplingDecision, SpanKind, TraceId};
+    use opentelemetry_sdk::trace::ShouldSample;
+    use super::*;
+
+    #[test]
+    fn test_unit_trace_sampling() {
+        let decide = |ratio: f64, trace_id: u128| sampler(ratio)
+            .should_sample(None, TraceId::from(trace_id), "GET /health", &SpanKind::Server, &[], &[])
+            .decision;
+
+        assert_eq!(decide(1.0, 1), SamplingDecision::RecordAndSample);
+        assert_eq!(decide(0.0, 1), SamplingDecision::Drop);
+        // Out of range ratios are clamped instead of sampling everything or nothing by accident
+        assert_eq!(decide(7.0, u128::MAX), SamplingDecision::RecordAndSample);
+        assert_eq!(decide(-1.0, 1), SamplingDecision::Drop);
+    }
+}
@@ -1,24 +1,61 @@
 use std::fmt;
 use std::sync::Arc;
 use actix_web::{HttpMessage, HttpRequest, HttpResponse};
-use actix_web::web::{Data, Json, Path};
-use redis_async::error::Error;
-use redis_async::resp::{FromResp, RespValue};
-use sqlx::{Arguments, Executor, Row};
-use sqlx::postgres::PgArguments;
+use actix_web::web::{Data, Json, Path, Query};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use sqlx::{Arguments, Row};
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::types::Json as SqlJson;
 use tracing_actix_web::root_span_macro::private::tracing::instrument;
-use crate::database::Database;
+use crate::cache::CacheKey;
+use crate::categories::{categories_json, category_filter, AssetCategory};
+use crate::database::{like_pattern, Database, SqlConditions};
 use crate::errors::ApiError;
 use crate::errors::ApiError::{BadRequest, InternalServerError};
-use crate::helpers::{respond_json, respond_ok};
+use crate::helpers::{format_datetime, respond_ok, Timestamp, TimestampFormat};
 use crate::middleware_custom::Claims;
+use crate::pagination::{page_limit, paginate, KeysetSort, SortOrder};
+use crate::preferences::load_user_preferences;
 use crate::server::AppState;
+use crate::smart_groups::{ensure_manual_group, refresh_stale_smart_group};
+use crate::watchlistgroup::{find_user_watchlist_group, group_validators, touch_watchlist_group};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WatchlistResponse {
-    id: i32,
-    name: String,
-    symbol: String,
+    pub id: i32,
+    pub name: String,
+    pub symbol: String,
+    pub rank: Option<i32>,
+    // Only set when the asset is listed as an entry of a group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub categories: Option<Vec<AssetCategory>>,
+}
+
+impl WatchlistResponse {
+    // Build an entry from a row selecting `id`, `name`, `symbol` and `rank` from `assets`
+    pub fn from_row(record: &PgRow) -> Self {
+        WatchlistResponse {
+            id: record.get("id"),
+            name: record.get("name"),
+            symbol: record.get("symbol"),
+            rank: record.get("rank"),
+            added_at: None,
+            categories: None,
+        }
+    }
+
+    pub fn with_added_at(self, added_at: Option<DateTime<Utc>>, timezone: Tz, format: TimestampFormat) -> Self {
+        WatchlistResponse { added_at: format_datetime(added_at, timezone, format), ..self }
+    }
+
+    // Read the `categories` column built by `categories_json`
+    pub fn with_categories(self, record: &PgRow) -> Self {
+        let categories: SqlJson<Vec<AssetCategory>> = record.get("categories");
+        WatchlistResponse { categories: Some(categories.0), ..self }
+    }
 }
 
 impl fmt::Display for WatchlistResponse {
@@ -27,19 +64,42 @@ impl fmt::Display for WatchlistResponse {
     }
 }
 
-impl FromResp for WatchlistResponse {
-    fn from_resp(resp: RespValue) -> Result<Self, Error> {
-        match resp {
-            RespValue::BulkString(bytes) => {
-                serde_json::from_slice(&bytes).map_err(|e| Error::Internal(e.to_string()))
-            },
-            _ => Err(Error::Internal("Unexpected response type".to_string())),
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchlistSortKey {
+    #[default]
+    AddedAt,
+    Name,
+    Symbol,
+    Rank,
+}
+
+impl WatchlistSortKey {
+    fn keyset(&self, order: SortOrder) -> KeysetSort {
+        match self {
+            WatchlistSortKey::AddedAt => KeysetSort { name: "added_at", expression: "COALESCE(w.added_at, 'epoch'::timestamptz)", sql_type: "timestamptz", order },
+            WatchlistSortKey::Name => KeysetSort { name: "name", expression: "LOWER(COALESCE(a.name, ''))", sql_type: "text", order },
+            WatchlistSortKey::Symbol => KeysetSort { name: "symbol", expression: "LOWER(a.symbol)", sql_type: "text", order },
+            // Unranked assets go last
+            WatchlistSortKey::Rank => KeysetSort { name: "rank", expression: "COALESCE(a.rank, 2147483647)", sql_type: "int", order },
         }
     }
+}
 
-    fn from_resp_int(resp: RespValue) -> Result<Self, Error> {
-        todo!()
-    }
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WatchlistQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<WatchlistSortKey>,
+    order: Option<SortOrder>,
+    // Comma separated list of symbols
+    symbol: Option<String>,
+    name: Option<String>,
+    min_rank: Option<i32>,
+    max_rank: Option<i32>,
+    // Category id or name
+    category: Option<String>,
+    time_format: Option<TimestampFormat>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,16 +123,37 @@ async fn check_exists(db: &Arc<dyn Database>, table_name: &str, id: i32) -> Resu
     Ok(true)
 }
 
+// Add several assets to a group at once, skipping the ones already in it
+#[instrument]
+pub async fn insert_watchlist_entries(db: &Arc<dyn Database>, group_id: i32, asset_ids: &[i32]) -> Result<u64, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    args.add(asset_ids);
+
+    let record = db
+        .execute("INSERT INTO watchlist (group_id, asset_id) SELECT $1, UNNEST($2::int[]) ON CONFLICT DO NOTHING", args)
+        .await?;
+
+    if record.rows_affected() > 0 {
+        touch_watchlist_group(db, group_id).await?;
+    }
+    Ok(record.rows_affected())
+}
+
 #[instrument]
 pub async fn create_watchlist(
     state: Data<AppState>,
-    body: Json<WatchlistCreateOrDeleteRequest>
+    body: Json<WatchlistCreateOrDeleteRequest>,
+    request: HttpRequest
 ) -> Result<HttpResponse, ApiError> {
-    // Check if the group_id exists
-    let group_exists = check_exists(&state.db, "watchlist_groups", body.group_id).await?;
-    if !group_exists {
-        return Err(BadRequest("Watchlist Group not found".into()));
-    }
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    // Check if the group exists and belongs to the user
+    find_user_watchlist_group(&state.db, user_id, body.group_id).await
+        .map_err(|err| match err {
+            ApiError::NotFound => BadRequest("Watchlist Group not found".into()),
+            err => err,
+        })?;
+    ensure_manual_group(&state.db, body.group_id).await?;
 
     // Check if the asset_id exists
     let asset_exists = check_exists(&state.db, "assets", body.asset_id).await?;
@@ -92,8 +173,11 @@ pub async fn create_watchlist(
     if record.rows_affected() == 0 {
         return Err(InternalServerError);
     }
+    touch_watchlist_group(&state.db, body.group_id).await?;
 
-    state.redis_client.del(format!("all_watchlist::{}", body.group_id)).await.expect("Failed to delete a key on Redis");
+    state.redis_client.del(format!("all_watchlist::{}", body.group_id)).await;
+    state.redis_client.del(format!("group_analytics::{}", body.group_id)).await;
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
 
     respond_ok()
 }
@@ -101,41 +185,75 @@ pub async fn create_watchlist(
 #[instrument]
 pub async fn retrieve_all_watchlist(
     state: Data<AppState>,
-    path: Path<i32>
-) -> Result<Json<Vec<WatchlistResponse>>, ApiError> {
+    request: HttpRequest,
+    path: Path<i32>,
+    query: Query<WatchlistQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
     let watchlistgroup_id = path.into_inner();
-    let mut args = PgArguments::default();
-    let mut watchlist: Vec<WatchlistResponse> = vec![];
-    args.add(watchlistgroup_id);
+    find_user_watchlist_group(&state.db, user_id, watchlistgroup_id).await?;
+    // Smart groups are re-evaluated before serving when their entries are stale
+    refresh_stale_smart_group(&state.db, &state.redis_client, watchlistgroup_id).await?;
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let cache_key = format!("all_watchlist::{}", watchlistgroup_id);
+    // `added_at` is rendered in the user's timezone
+    let variant = format!("{}::{}", timezone.name(), serde_json::to_string(&query.0)?);
+    // Read after the refresh so the ETag names the version the entries are served at
+    let (version, validators) = group_validators(&state.db, user_id, watchlistgroup_id, &variant).await?
+        .ok_or(ApiError::NotFound)?;
+    if validators.is_fresh(&request) {
+        return Ok(validators.not_modified());
+    }
+    let cache_field = format!("v{}::{}", version, variant);
 
-    let cached_data: Result<Vec<WatchlistResponse>, ApiError> = state.redis_client.get(format!("all_watchlist::{}", watchlistgroup_id)).await;
+    let watchlist = state.redis_client.read_through(CacheKey::new(cache_key, cache_field), async {
+        let limit = page_limit(query.limit)?;
+        let sort = query.sort.unwrap_or_default().keyset(query.order.unwrap_or_default());
+        let cursor = sort.decode_cursor(query.cursor.as_deref())?;
 
-    match cached_data {
-        Ok(cached_data) => {
-            Ok(respond_json(cached_data).unwrap())
+        let mut conditions = SqlConditions::new();
+        conditions.push("w.group_id = $?", watchlistgroup_id);
+        if let Some(symbols) = &query.symbol {
+            let symbols: Vec<String> = symbols.split(',').map(|symbol| symbol.trim().to_uppercase()).collect();
+            conditions.push("UPPER(a.symbol) = ANY($?)", symbols);
         }
-        Err(ApiError::RedisNil) => {
-            let records = state.db
-                .fetch_all("SELECT a.id, a.name, a.symbol FROM watchlist w JOIN assets a ON w.asset_id = a.id where w.group_id = $1", args)
-                .await?;
-
-            if records.is_empty() {
-                return respond_json(watchlist);
-            }
-
-            watchlist = records
-                .iter()
-                .map(|record| WatchlistResponse {
-                    id: record.get("id"),
-                    name: record.get("name"),
-                    symbol: record.get("symbol"),
-                }).collect();
-
-            state.redis_client.set(format!("all_watchlist::{}", watchlistgroup_id), watchlist.clone()).await.expect("Failed to set the data to Redis");
-            respond_json(watchlist)
+        if let Some(name) = &query.name {
+            conditions.push("LOWER(a.name) LIKE $?", like_pattern(name));
         }
-        _ => Err(InternalServerError)
-    }
+        if let Some(min_rank) = query.min_rank {
+            conditions.push("a.rank >= $?", min_rank);
+        }
+        if let Some(max_rank) = query.max_rank {
+            conditions.push("a.rank <= $?", max_rank);
+        }
+        if let Some(category) = &query.category {
+            conditions.push(&category_filter("a.id"), category.trim());
+        }
+        if let Some(cursor) = cursor {
+            let value_param = conditions.bind(cursor.value);
+            let id_param = conditions.bind(cursor.id);
+            conditions.push_clause(sort.after("a.id", value_param, id_param));
+        }
+
+        let sql = format!(
+            "SELECT a.id, a.name, a.symbol, a.rank, w.added_at, {} AS categories, {} FROM watchlist w JOIN assets a ON w.asset_id = a.id \
+             JOIN watchlist_groups wg ON wg.id = w.group_id AND wg.deleted_at IS NULL {} {} LIMIT {}",
+            categories_json("a.id"), sort.select(), conditions.where_clause(), sort.order_by("a.id"), limit + 1
+        );
+        let records = state.db
+            .fetch_all(&sql, conditions.into_args())
+            .await?;
+
+        let time_format = query.time_format.unwrap_or_default();
+        Ok(paginate(records, limit, &sort, "id", |record| {
+            WatchlistResponse::from_row(record)
+                .with_added_at(record.get("added_at"), timezone, time_format)
+                .with_categories(record)
+        }))
+    })
+    .await?;
+
+    validators.respond(&request, watchlist)
 }
 
 #[instrument]
@@ -153,12 +271,13 @@ pub async fn delete_watchlist(
     let record = state.db
         .fetch_one("SELECT w.asset_id FROM watchlist w
     JOIN watchlist_groups wg ON w.group_id = wg.id
-    JOIN users u ON wg.user_id = u.id WHERE wg.id = $1 AND wg.user_id = $2", select_args)
+    JOIN users u ON wg.user_id = u.id WHERE wg.id = $1 AND wg.user_id = $2 AND wg.deleted_at IS NULL", select_args)
         .await?;
 
     if record.is_empty() {
         return Err(BadRequest("Watchlist not found".into()));
     }
+    ensure_manual_group(&state.db, body.group_id).await?;
 
     let mut args = PgArguments::default();
     args.add(&body.asset_id);
@@ -171,8 +290,11 @@ pub async fn delete_watchlist(
     if record.rows_affected() == 0 {
         return Err(InternalServerError);
     }
+    touch_watchlist_group(&state.db, body.group_id).await?;
 
-    state.redis_client.del(format!("all_watchlist::{}", body.group_id)).await.expect("Failed to delete a key on Redis");
+    state.redis_client.del(format!("all_watchlist::{}", body.group_id)).await;
+    state.redis_client.del(format!("group_analytics::{}", body.group_id)).await;
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
 
     respond_ok()
 }
\ No newline at end of file
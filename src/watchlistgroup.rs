@@ -1,106 +1,405 @@
+use std::sync::Arc;
 use actix_web::{HttpMessage, HttpRequest, HttpResponse};
 use actix_web::web::{Data, Json, Path, Query};
-use log::debug;
-use redis_async::error::Error;
-use redis_async::resp::{FromResp, RespValue};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use sqlx::{Arguments, Row};
-use sqlx::postgres::PgArguments;
+use sqlx::postgres::{PgArguments, PgRow};
+use sqlx::types::Json as SqlJson;
 use tracing::instrument;
-use crate::database::Database;
+use crate::cache::CacheKey;
+use crate::categories::categories_json;
+use crate::config::CONFIG;
+use crate::database::{like_pattern, Database, SqlConditions};
 use crate::errors::ApiError;
-use crate::errors::ApiError::InternalServerError;
-use crate::helpers::{format_datetime, respond_json, respond_ok};
+use crate::helpers::{format_datetime, respond_json, respond_ok, Timestamp, TimestampFormat, TimestampQuery};
+use crate::http_cache::{if_match_states, Validators};
 use crate::middleware_custom::Claims;
+use crate::pagination::{page_limit, paginate, KeysetSort, Page, SortOrder};
+use crate::preferences::{load_user_preferences, UserPreferences};
 use crate::server::AppState;
+use crate::smart_groups::SmartRule;
 use crate::watchlist::WatchlistResponse;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WatchlistGroupResponse {
-    id: i32,
-    user_id: i32,
-    name: String,
-    created_at: String,
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub created_at: Option<Timestamp>,
+}
+
+// A group together with its entry count and, when expanded, its assets
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WatchlistGroupDetailResponse {
+    #[serde(flatten)]
+    pub group: WatchlistGroupResponse,
+    pub entry_count: i64,
+    #[serde(default)]
+    pub is_default: bool,
+    // Set for smart groups, whose entries follow this rule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<SmartRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assets: Option<Vec<WatchlistResponse>>,
 }
 
-impl FromResp for WatchlistGroupResponse {
-    fn from_resp(resp: RespValue) -> Result<Self, Error> {
-        match resp {
-            RespValue::BulkString(bytes) => {
-                serde_json::from_slice(&bytes).map_err(|e| Error::Internal(e.to_string()))
+impl WatchlistGroupDetailResponse {
+    fn from_row(record: &PgRow, user_id: i32, expand_assets: bool, preferences: &UserPreferences, format: TimestampFormat) -> Self {
+        let assets: Option<SqlJson<Vec<WatchlistResponse>>> = if expand_assets { record.get("assets") } else { None };
+        let id: i32 = record.get("id");
+        let timezone = preferences.tz();
+
+        // The aggregated entries carry `added_at` as epoch millis, rendered here like every other timestamp
+        let assets = assets.map(|assets| assets.0
+            .into_iter()
+            .map(|asset| {
+                let added_at = match asset.added_at {
+                    Some(Timestamp::EpochMillis(millis)) => DateTime::from_timestamp_millis(millis),
+                    _ => None,
+                };
+                asset.with_added_at(added_at, timezone, format)
+            })
+            .collect());
+
+        WatchlistGroupDetailResponse {
+            group: WatchlistGroupResponse {
+                id,
+                user_id,
+                name: record.get("name"),
+                created_at: format_datetime(record.get("created_at"), timezone, format),
             },
-            _ => Err(Error::Internal("Unexpected response type".to_string())),
+            entry_count: record.get("entry_count"),
+            is_default: preferences.default_watchlist_group_id == Some(id),
+            rule: record.get::<Option<SqlJson<SmartRule>>, _>("smart_rule").map(|rule| rule.0),
+            assets,
         }
     }
+}
+
+// Columns and joins shared by the group list and detail queries, aggregating the entries of
+// each group in the same query. `extra_columns` is appended to the select list.
+fn group_detail_select(expand_assets: bool, extra_columns: &str) -> String {
+    let assets = if expand_assets {
+        format!(
+            r#"COALESCE(json_agg(json_build_object('id', a.id, 'name', a.name, 'symbol', a.symbol, 'rank', a.rank,
+                                                    'added_at', (EXTRACT(EPOCH FROM w.added_at) * 1000)::bigint,
+                                                    'categories', {})
+                         ORDER BY w.added_at, a.id) FILTER (WHERE a.id IS NOT NULL), '[]'::json) AS assets"#,
+            categories_json("a.id")
+        )
+    } else {
+        "NULL::json AS assets".to_string()
+    };
 
-    fn from_resp_int(resp: RespValue) -> Result<Self, Error> {
-        todo!()
+    format!(
+        "SELECT wg.id, wg.name, wg.created_at, wg.smart_rule, COUNT(w.asset_id) AS entry_count, {}{} \
+         FROM watchlist_groups wg \
+         LEFT JOIN watchlist w ON w.group_id = wg.id \
+         LEFT JOIN assets a ON a.id = w.asset_id",
+        assets, extra_columns
+    )
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchlistGroupSortKey {
+    #[default]
+    CreatedAt,
+    Name,
+}
+
+impl WatchlistGroupSortKey {
+    fn keyset(&self, order: SortOrder) -> KeysetSort {
+        match self {
+            WatchlistGroupSortKey::CreatedAt => KeysetSort { name: "created_at", expression: "COALESCE(wg.created_at, 'epoch'::timestamptz)", sql_type: "timestamptz", order },
+            WatchlistGroupSortKey::Name => KeysetSort { name: "name", expression: "LOWER(wg.name)", sql_type: "text", order },
+        }
     }
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchlistGroupExpand {
+    Assets,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct WatchlistGroupQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    sort: Option<WatchlistGroupSortKey>,
+    order: Option<SortOrder>,
+    name: Option<String>,
+    expand: Option<WatchlistGroupExpand>,
+    time_format: Option<TimestampFormat>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrashQuery {
+    limit: Option<i64>,
+    cursor: Option<String>,
+    time_format: Option<TimestampFormat>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletedWatchlistGroupResponse {
+    #[serde(flatten)]
+    group: WatchlistGroupResponse,
+    deleted_at: Option<Timestamp>,
+    purge_at: Option<Timestamp>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct WatchlistGroupCreateOrUpdateRequest {
     name: String,
 }
 
+// Set in every statement changing a group. Changes to its entries go through `touch_watchlist_group`.
+pub const BUMP_VERSION: &str = "version = version + 1, updated_at = NOW()";
+// SQL counterpart of `group_state`, compared with the states named by If-Match
+const GROUP_STATE: &str = "'wg' || id || 'v' || version";
+
+// What the ETags of a group's responses name, every change to the group or its entries bumps the version
+fn group_state(group_id: i32, version: i32) -> String {
+    format!("wg{}v{}", group_id, version)
+}
+
+// The version of a group and the validators of a response rendering it for `variant`. `None` when the
+// group is not the user's. Rendering also depends on the user's preferences, so they count as modifications.
+pub async fn group_validators(
+    db: &Arc<dyn Database>,
+    user_id: i32,
+    group_id: i32,
+    variant: &str,
+) -> Result<Option<(i32, Validators)>, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    args.add(user_id);
+
+    let record = db
+        .fetch_optional(r#"SELECT wg.version, GREATEST(wg.updated_at, p.updated_at) AS updated_at
+                           FROM watchlist_groups wg LEFT JOIN user_preferences p ON p.user_id = wg.user_id
+                           WHERE wg.id = $1 AND wg.user_id = $2 AND wg.deleted_at IS NULL"#, args)
+        .await?;
+
+    Ok(record.map(|record| {
+        let version: i32 = record.get("version");
+        let updated_at: Option<DateTime<Utc>> = record.get("updated_at");
+        (version, Validators::new(&group_state(group_id, version), variant, updated_at))
+    }))
+}
+
+// The versions of all the groups of a user, as one digest, and the validators of a response listing them.
+// Groups in the trash count for Last-Modified, so moving one there is a modification of the list.
+async fn user_groups_validators(db: &Arc<dyn Database>, user_id: i32, variant: &str) -> Result<(String, Validators), ApiError> {
+    let mut args = PgArguments::default();
+    args.add(user_id);
+
+    let record = db
+        .fetch_one(r#"SELECT md5(COALESCE(string_agg(wg.id || 'v' || wg.version, ',' ORDER BY wg.id) FILTER (WHERE wg.deleted_at IS NULL), '')) AS versions,
+                             GREATEST(MAX(wg.updated_at), (SELECT updated_at FROM user_preferences WHERE user_id = $1)) AS updated_at
+                      FROM watchlist_groups wg WHERE wg.user_id = $1"#, args)
+        .await?;
+
+    let versions: String = record.get("versions");
+    let validators = Validators::new(&format!("wgl{}", versions), variant, record.get("updated_at"));
+    Ok((versions, validators))
+}
+
+// Record a change to the entries of a group
+#[instrument]
+pub async fn touch_watchlist_group(db: &Arc<dyn Database>, group_id: i32) -> Result<(), ApiError> {
+    let mut args = PgArguments::default();
+    args.add(group_id);
+
+    db.execute(&format!("UPDATE watchlist_groups SET {} WHERE id = $1", BUMP_VERSION), args).await?;
+    Ok(())
+}
+
+// Record a change to how the given assets render, in every group holding them
+#[instrument]
+pub async fn touch_watchlist_groups_of_assets(db: &Arc<dyn Database>, asset_ids: &[i32]) -> Result<u64, ApiError> {
+    if asset_ids.is_empty() {
+        return Ok(0);
+    }
+    let mut args = PgArguments::default();
+    args.add(asset_ids);
+
+    let record = db
+        .execute(&format!(
+            "UPDATE watchlist_groups SET {} WHERE deleted_at IS NULL AND id IN (SELECT group_id FROM watchlist WHERE asset_id = ANY($1))",
+            BUMP_VERSION
+        ), args)
+        .await?;
+    Ok(record.rows_affected())
+}
+
+// A write conditioned on If-Match matched no row: 412 when the group exists in another state, 404 otherwise
+async fn failed_write_error(db: &Arc<dyn Database>, user_id: i32, group_id: i32, expected_states: &Option<Vec<String>>) -> ApiError {
+    if expected_states.is_some() && find_user_watchlist_group(db, user_id, group_id).await.is_ok() {
+        return ApiError::PreconditionFailed;
+    }
+    ApiError::NotFound
+}
+
+// Fetch a single watchlist group, making sure it belongs to the given user
+#[instrument]
+pub async fn find_user_watchlist_group(
+    db: &Arc<dyn Database>,
+    user_id: i32,
+    group_id: i32,
+) -> Result<WatchlistGroupResponse, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    args.add(user_id);
+
+    let record = db
+        .fetch_optional("SELECT id, name, created_at FROM watchlist_groups WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL", args)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    // Only used to check ownership, so the timestamp is left in UTC
+    Ok(WatchlistGroupResponse {
+        id: record.get("id"),
+        user_id,
+        name: record.get("name"),
+        created_at: format_datetime(record.get("created_at"), Tz::UTC, TimestampFormat::default()),
+    })
+}
+
+#[instrument]
+pub async fn insert_watchlist_group(
+    db: &Arc<dyn Database>,
+    user_id: i32,
+    name: &str,
+    timezone: Tz,
+    format: TimestampFormat,
+) -> Result<WatchlistGroupResponse, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(user_id);
+    args.add(name);
+
+    let record = db
+        .fetch_one("INSERT INTO watchlist_groups (user_id, name) VALUES ($1, $2) RETURNING id, created_at", args)
+        .await?;
+
+    Ok(WatchlistGroupResponse {
+        id: record.get("id"),
+        user_id,
+        name: name.to_string(),
+        created_at: format_datetime(record.get("created_at"), timezone, format),
+    })
+}
+
 #[instrument]
 pub async fn retrieve_all_watchlist_groups(
     state: Data<AppState>,
     request: HttpRequest,
+    query: Query<WatchlistGroupQuery>,
 )
-    -> Result<Json<Vec<WatchlistGroupResponse>>, ApiError> {
+    -> Result<HttpResponse, ApiError> {
 
     let user_id = request.extensions().get::<Claims>().unwrap().user_id;
-    let mut args = PgArguments::default();
-    args.add(user_id);
-    let cached_data: Result<Vec<WatchlistGroupResponse>, ApiError> = state.redis_client.get(format!("all_watchlist_group::{}", user_id)).await;
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    let cache_key = format!("all_watchlist_group::{}", user_id);
+    let query_field = serde_json::to_string(&query.0)?;
+    let variant = format!("{}::{:?}::{}", preferences.timezone, preferences.default_watchlist_group_id, query_field);
+    let (versions, validators) = user_groups_validators(&state.db, user_id, &variant).await?;
+    if validators.is_fresh(&request) {
+        return Ok(validators.not_modified());
+    }
+
+    let cache_field = format!("{}::{}", versions, query_field);
+    let watchlist_groups = state.redis_client.read_through(CacheKey::new(cache_key, cache_field), async {
+        let limit = page_limit(query.limit)?;
+        let sort = query.sort.unwrap_or_default().keyset(query.order.unwrap_or_default());
+        let cursor = sort.decode_cursor(query.cursor.as_deref())?;
+        let expand_assets = query.expand == Some(WatchlistGroupExpand::Assets);
 
-    match cached_data {
-        Ok(cached_data) => {
-            Ok(respond_json(cached_data).unwrap())
+        let mut conditions = SqlConditions::new();
+        conditions.push("wg.user_id = $?", user_id);
+        conditions.push_clause("wg.deleted_at IS NULL".into());
+        if let Some(name) = &query.name {
+            conditions.push("LOWER(wg.name) LIKE $?", like_pattern(name));
         }
-        Err(ApiError::RedisNil) => {
-            let records = state.db
-                .fetch_all("SELECT * FROM watchlist_groups WHERE user_id = $1", args)
-                .await?;
-            let watchlist_groups: Vec<WatchlistGroupResponse> = records
-                .iter()
-                .map(|record| WatchlistGroupResponse {
-                    id: record.get("id"),
-                    user_id,
-                    name: record.get("name"),
-                    created_at: format_datetime(record.get("created_at")),
-                })
-                .collect();
-            state.redis_client.set(format!("all_watchlist_group::{}", user_id), watchlist_groups.clone()).await.expect("Failed to set the data to Redis");
-            respond_json(watchlist_groups)
+        if let Some(cursor) = cursor {
+            let value_param = conditions.bind(cursor.value);
+            let id_param = conditions.bind(cursor.id);
+            conditions.push_clause(sort.after("wg.id", value_param, id_param));
         }
-        _ => Err(InternalServerError)
+
+        let sql = format!(
+            "{} {} GROUP BY wg.id {} LIMIT {}",
+            group_detail_select(expand_assets, &format!(", {}", sort.select())), conditions.where_clause(), sort.order_by("wg.id"), limit + 1
+        );
+        let records = state.db
+            .fetch_all(&sql, conditions.into_args())
+            .await?;
+        let watchlist_groups = paginate(records, limit, &sort, "id", |record| {
+            WatchlistGroupDetailResponse::from_row(record, user_id, expand_assets, &preferences, query.time_format.unwrap_or_default())
+        });
+        Ok(watchlist_groups)
+    })
+    .await?;
+
+    validators.respond(&request, watchlist_groups)
+}
+
+#[instrument]
+pub async fn retrieve_watchlist_group(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<i32>,
+    query: Query<TimestampQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let group_id = path.into_inner();
+    let time_format = query.time_format.unwrap_or_default();
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    let variant = format!(
+        "detail::{}::{}::{}",
+        preferences.timezone, preferences.default_watchlist_group_id == Some(group_id), serde_json::to_string(&time_format)?
+    );
+    let (version, validators) = group_validators(&state.db, user_id, group_id, &variant).await?
+        .ok_or(ApiError::NotFound)?;
+    if validators.is_fresh(&request) {
+        return Ok(validators.not_modified());
     }
+
+    let cache_key = format!("all_watchlist_group::{}", user_id);
+    let cache_field = format!("detail::{}::v{}::{}", group_id, version, serde_json::to_string(&time_format)?);
+    let watchlist_group = state.redis_client.read_through(CacheKey::new(cache_key, cache_field), async {
+        let mut args = PgArguments::default();
+        args.add(group_id);
+        args.add(user_id);
+
+        let sql = format!("{} WHERE wg.id = $1 AND wg.user_id = $2 AND wg.deleted_at IS NULL GROUP BY wg.id", group_detail_select(true, ""));
+        let record = state.db
+            .fetch_optional(&sql, args)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+        let watchlist_group = WatchlistGroupDetailResponse::from_row(&record, user_id, true, &preferences, time_format);
+
+        Ok(watchlist_group)
+    })
+    .await?;
+
+    validators.respond(&request, watchlist_group)
 }
 
 #[instrument]
 pub async fn create_watchlist_group(
     state: Data<AppState>,
     body: Json<WatchlistGroupCreateOrUpdateRequest>,
-    request: HttpRequest
+    request: HttpRequest,
+    query: Query<TimestampQuery>,
 ) -> Result<Json<WatchlistGroupResponse>, ApiError> {
     let user_id = request.extensions().get::<Claims>().unwrap().user_id;
-    let mut args = PgArguments::default();
-    args.add(user_id);
-    args.add(&body.name);
-
-    let record = state.db
-        .fetch_one("INSERT INTO watchlist_groups (user_id, name) VALUES ($1, $2) RETURNING id, created_at", args)
-        .await?;
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    let watchlist_group = insert_watchlist_group(&state.db, user_id, &body.name, preferences.tz(), query.time_format.unwrap_or_default()).await?;
 
-    let watchlist_group = WatchlistGroupResponse {
-        id: record.get("id"),
-        user_id,
-        name: body.name.clone(),
-        created_at: format_datetime(record.get("created_at")),
-    };
-
-    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await.expect("Failed to delete a key on Redis");
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
 
     respond_json(watchlist_group)
 }
@@ -110,29 +409,44 @@ pub async fn update_watchlist_group(
     state: Data<AppState>,
     body: Json<WatchlistGroupCreateOrUpdateRequest>,
     request: HttpRequest,
-    path: Path<i32>
+    path: Path<i32>,
+    query: Query<TimestampQuery>,
 )
-    -> Result<Json<WatchlistGroupResponse>, ApiError> {
+    -> Result<HttpResponse, ApiError> {
     let user_id = request.extensions().get::<Claims>().unwrap().user_id;
     let group_id = path.into_inner();
+    let preferences = load_user_preferences(&state.db, &state.redis_client, user_id).await?;
+    let time_format = query.time_format.unwrap_or_default();
+    // Only applied when the group is still in one of the states the client saw
+    let expected_states = if_match_states(&request);
     let mut args = PgArguments::default();
     args.add(&body.name);
     args.add(user_id);
     args.add(group_id);
+    args.add(&expected_states);
 
-    let record = state.db
-        .fetch_one("UPDATE watchlist_groups SET name = COALESCE($1, name) WHERE user_id = $2 AND id = $3 RETURNING name, created_at", args)
-        .await?;
+    let sql = format!(
+        "UPDATE watchlist_groups SET name = COALESCE($1, name), {} \
+         WHERE user_id = $2 AND id = $3 AND deleted_at IS NULL AND ($4::text[] IS NULL OR {} = ANY($4)) \
+         RETURNING name, created_at, version, updated_at",
+        BUMP_VERSION, GROUP_STATE
+    );
+    let Some(record) = state.db.fetch_optional(&sql, args).await? else {
+        return Err(failed_write_error(&state.db, user_id, group_id, &expected_states).await);
+    };
     let watchlist_group = WatchlistGroupResponse {
         id: group_id,
         user_id,
         name: record.get("name"),
-        created_at: format_datetime(record.get("created_at")),
+        created_at: format_datetime(record.get("created_at"), preferences.tz(), time_format),
     };
 
-    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await.expect("Failed to delete a key on Redis");
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
 
-    respond_json(watchlist_group)
+    // Tags the new state, so the client can chain conditional writes
+    let variant = format!("update::{}::{}", preferences.timezone, serde_json::to_string(&time_format)?);
+    let validators = Validators::new(&group_state(group_id, record.get("version")), &variant, record.get("updated_at"));
+    Ok(HttpResponse::Ok().insert_header(validators.etag()).json(watchlist_group))
 }
 
 #[instrument]
@@ -143,20 +457,120 @@ pub async fn delete_watchlist_group(
 ) -> Result<HttpResponse, ApiError> {
     let user_id = request.extensions().get::<Claims>().unwrap().user_id;
     let group_id = path.into_inner();
+    let expected_states = if_match_states(&request);
     let mut args = PgArguments::default();
     args.add(group_id);
     args.add(user_id);
+    args.add(&expected_states);
 
+    // Groups are only moved to the trash here, `purge_deleted_watchlist_groups` removes them for good
+    let sql = format!(
+        "UPDATE watchlist_groups SET deleted_at = NOW(), {} \
+         WHERE id = $1 AND user_id = $2 AND deleted_at IS NULL AND ($3::text[] IS NULL OR {} = ANY($3))",
+        BUMP_VERSION, GROUP_STATE
+    );
     let record = state.db
-        .execute("DELETE FROM watchlist_groups WHERE id = $1 AND user_id = $2", args)
+        .execute(&sql, args)
         .await?;
 
     if record.rows_affected() == 0 {
-        return Err(ApiError::NotFound);
+        return Err(failed_write_error(&state.db, user_id, group_id, &expected_states).await);
     }
 
-    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await.expect("Failed to delete a key on Redis");
-    state.redis_client.del(format!("all_watchlist::{}", group_id)).await.expect("Failed to delete a key on Redis");
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
+    state.redis_client.del(format!("all_watchlist::{}", group_id)).await;
 
     respond_ok()
+}
+
+#[instrument]
+pub async fn retrieve_deleted_watchlist_groups(
+    state: Data<AppState>,
+    request: HttpRequest,
+    query: Query<TrashQuery>,
+) -> Result<Json<Page<DeletedWatchlistGroupResponse>>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let limit = page_limit(query.limit)?;
+    let sort = KeysetSort { name: "deleted_at", expression: "wg.deleted_at", sql_type: "timestamptz", order: SortOrder::Desc };
+    let time_format = query.time_format.unwrap_or_default();
+    let cursor = sort.decode_cursor(query.cursor.as_deref())?;
+
+    let mut conditions = SqlConditions::new();
+    let retention_param = conditions.bind(CONFIG.watchlist_group_retention_days);
+    conditions.push("wg.user_id = $?", user_id);
+    conditions.push_clause("wg.deleted_at IS NOT NULL".into());
+    if let Some(cursor) = cursor {
+        let value_param = conditions.bind(cursor.value);
+        let id_param = conditions.bind(cursor.id);
+        conditions.push_clause(sort.after("wg.id", value_param, id_param));
+    }
+
+    let sql = format!(
+        "SELECT wg.id, wg.name, wg.created_at, wg.deleted_at, wg.deleted_at + make_interval(days => ${}) AS purge_at, {} \
+         FROM watchlist_groups wg {} {} LIMIT {}",
+        retention_param, sort.select(), conditions.where_clause(), sort.order_by("wg.id"), limit + 1
+    );
+    let records = state.db
+        .fetch_all(&sql, conditions.into_args())
+        .await?;
+
+    respond_json(paginate(records, limit, &sort, "id", |record| DeletedWatchlistGroupResponse {
+        group: WatchlistGroupResponse {
+            id: record.get("id"),
+            user_id,
+            name: record.get("name"),
+            created_at: format_datetime(record.get("created_at"), timezone, time_format),
+        },
+        deleted_at: format_datetime(record.get("deleted_at"), timezone, time_format),
+        purge_at: format_datetime(record.get("purge_at"), timezone, time_format),
+    }))
+}
+
+#[instrument]
+pub async fn restore_watchlist_group(
+    state: Data<AppState>,
+    request: HttpRequest,
+    path: Path<i32>,
+    query: Query<TimestampQuery>,
+) -> Result<Json<WatchlistGroupResponse>, ApiError> {
+    let user_id = request.extensions().get::<Claims>().unwrap().user_id;
+    let group_id = path.into_inner();
+    let timezone = load_user_preferences(&state.db, &state.redis_client, user_id).await?.tz();
+    let mut args = PgArguments::default();
+    args.add(group_id);
+    args.add(user_id);
+
+    let record = state.db
+        .fetch_optional(&format!(
+            "UPDATE watchlist_groups SET deleted_at = NULL, {} WHERE id = $1 AND user_id = $2 AND deleted_at IS NOT NULL RETURNING name, created_at",
+            BUMP_VERSION
+        ), args)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let watchlist_group = WatchlistGroupResponse {
+        id: group_id,
+        user_id,
+        name: record.get("name"),
+        created_at: format_datetime(record.get("created_at"), timezone, query.time_format.unwrap_or_default()),
+    };
+
+    state.redis_client.del(format!("all_watchlist_group::{}", user_id)).await;
+    state.redis_client.del(format!("all_watchlist::{}", group_id)).await;
+
+    respond_json(watchlist_group)
+}
+
+// Permanently remove groups that have been in the trash longer than the retention period. Their
+// entries go with them through the cascading foreign key.
+#[instrument]
+pub async fn purge_deleted_watchlist_groups(db: &Arc<dyn Database>, retention_days: i32) -> Result<u64, ApiError> {
+    let mut args = PgArguments::default();
+    args.add(retention_days);
+
+    let record = db
+        .execute("DELETE FROM watchlist_groups WHERE deleted_at < NOW() - make_interval(days => $1)", args)
+        .await?;
+
+    Ok(record.rows_affected())
 }
\ No newline at end of file